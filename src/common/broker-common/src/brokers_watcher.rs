@@ -1,10 +1,12 @@
 use std::{collections::HashMap, env, fmt, fmt::Write, sync::{Arc, LazyLock, Mutex}, time::Instant};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use ibapi::prelude::*;
 use ibapi::orders::{CommissionReport, ExecutionData, ExecutionFilter, Executions};
 
-use rqcommon::{log_and_println, rqhelper::MutexExt, utils::server_ip::ServerIp};
-use memdb::mark_value_cache::RQ_MARK_VALUE_CACHE;
+use serde_json::json;
+
+use rqcommon::{log_and_println, rqhelper::MutexExt, utils::{rqevent_hub::{RqEventTopic, RQ_EVENT_HUB}, runningenv::get_running_env_overrides, server_ip::ServerIp}};
+use memdb::{mark_value_cache::{MarkSource, RQ_MARK_VALUE_CACHE}, quote::{MarkPolicy, Quote}, quote_book::RQ_QUOTE_BOOK, quote_stream_health::{feeds_down_summary, is_mark_stale}};
 
 use crate::gateway::Gateway;
 
@@ -58,9 +60,13 @@ impl BrokersWatcher {
     }
 
     pub fn gateway_client_id() -> i32 {
+        if let Some(client_id) = get_running_env_overrides().ib_client_id { // --ib-client-id or RQCORE_IB_CLIENT_ID takes precedence over the built-in table
+            return client_id;
+        }
+
         if env::consts::OS == "windows" {
             // On windows, use USERDOMAIN, instead of USERNAME, because USERNAME can be the same on multiple machines (e.g. "gyantal" on both GYANTAL-PC and GYANTAL-LAPTOP)
-            let userdomain = env::var("USERDOMAIN").expect("Failed to get USERDOMAIN environment variable");
+            let userdomain = env::var("USERDOMAIN").unwrap_or_default();
             match userdomain.as_str() {
                 "GYANTAL-PC" => 210,
                 "GYANTAL-LAPTOP" => 211,
@@ -70,7 +76,7 @@ impl BrokersWatcher {
                 "DAYA-LAPTOP" => 215,
                 "DRCHARMAT-LAPTOP" => 216,
                 _ => panic!(
-                    "Windows user name is not recognized. Add your username and folder here!"
+                    "Windows user name '{}' is not recognized. Use --ib-client-id=<int> or RQCORE_IB_CLIENT_ID, or add your username and client ID here!", userdomain
                 ),
             }
         } else { // Linux and MacOS
@@ -78,6 +84,10 @@ impl BrokersWatcher {
         }
     }
 
+    pub fn get_ib_client(&self, broker_client: BrokerClient) -> Option<Arc<Client>> {
+        self.gateways.lock_ignore_poison().get(&broker_client).and_then(|gateway| gateway.ib_client.clone())
+    }
+
     pub async fn init(&self) {
         log::info!("BrokersWatcher.init() start");
 
@@ -89,29 +99,55 @@ impl BrokersWatcher {
         let mut gateways = self.gateways.lock_ignore_poison();
 
         let connection_url_dcmain = [ServerIp::sq_core_server_public_ip_for_clients(), ":", ServerIp::IB_SERVER_PORT_DCMAIN.to_string().as_str()].concat();
-        let mut gateway_dcmain = Gateway::new(&connection_url_dcmain, client_id);
+        let mut gateway_dcmain = Gateway::new("DcMain", &connection_url_dcmain, client_id);
         gateway_dcmain.init().await;
         gateways.insert(BrokerClient::DcMain, gateway_dcmain);
 
         let connection_url_dcblanzac = [ServerIp::sq_core_server_public_ip_for_clients(), ":", ServerIp::IB_SERVER_PORT_DCBLANZAC.to_string().as_str()].concat();
-        let mut gateway_dcblanzac = Gateway::new(&connection_url_dcblanzac, client_id);
+        let mut gateway_dcblanzac = Gateway::new("DcBlanzac", &connection_url_dcblanzac, client_id);
         gateway_dcblanzac.init().await;
         gateways.insert(BrokerClient::DcBlanzac, gateway_dcblanzac);
 
         let connection_url_gyantal = [ServerIp::sq_core_server_public_ip_for_clients(), ":", ServerIp::IB_SERVER_PORT_GYANTAL.to_string().as_str()].concat();
-        let mut gateway_gyantal = Gateway::new(&connection_url_gyantal, client_id);
+        let mut gateway_gyantal = Gateway::new("Gyantal", &connection_url_gyantal, client_id);
         gateway_gyantal.init().await;
         gateways.insert(BrokerClient::Gyantal, gateway_gyantal);
     }
 
     pub async fn exit(&self) {
         let mut gateways = self.gateways.lock_ignore_poison();
-        for gateway in gateways.values_mut() {
+        for (_broker_client, gateway) in gateways.iter_mut() {
             gateway.exit().await;
         }
         gateways.clear();
     }
 
+    // Connects the gateway again (a new Client), e.g. when its Client was not connected at startup or TWS was restarted. Returns the current client.
+    // A still connected client is kept: TWS would reject a second connection with the same client_id, and the gateway would lose its working client.
+    // The connection is made outside of the gateways lock (it can take seconds), on a new Gateway that replaces the old one only if it connected.
+    pub async fn reconnect_gateway(&self, broker_client: BrokerClient) -> Option<Arc<Client>> {
+        let (name, connection_url, client_id) = {
+            let gateways = self.gateways.lock_ignore_poison();
+            let Some(gateway) = gateways.get(&broker_client) else {
+                log::error!("BrokersWatcher.reconnect_gateway(): gateway is missing for {:?}.", broker_client);
+                return None;
+            };
+            if let Some(ib_client) = gateway.ib_client.as_ref().filter(|ib_client| ib_client.is_connected()) {
+                log::info!("BrokersWatcher.reconnect_gateway(): {:?} is still connected. Not reconnecting.", broker_client);
+                return Some(ib_client.clone());
+            }
+            (gateway.name.clone(), gateway.connection_url.clone(), gateway.client_id)
+        };
+        let mut gateway = Gateway::new(&name, &connection_url, client_id);
+        gateway.reconnect().await;
+        let Some(ib_client) = gateway.ib_client.clone() else {
+            log::warn!("BrokersWatcher.reconnect_gateway(): {:?} reconnection failed. The old gateway is kept.", broker_client);
+            return None;
+        };
+        self.gateways.lock_ignore_poison().insert(broker_client, gateway);
+        Some(ib_client)
+    }
+
     pub async fn get_order_executions(&self, broker_client: BrokerClient) -> (Vec<ExecutionData>, Vec<CommissionReport>) {
         let ib_client = {
             let gateways = self.gateways.lock_ignore_poison();
@@ -176,19 +212,38 @@ impl BrokersWatcher {
         log_and_println!("BrokersWatcher.place_orders(): {} order(s). Simulation: {}", orders.len(), is_simulation);
 
         let mut ticker_markvalues: HashMap<String, f64> = HashMap::new(); // This HashMap will not contain NaN. If it is NaN, we don't put in.
+        let mut ticker_quotes: HashMap<String, Quote> = HashMap::new(); // for the spread based limit prices
         let now = Utc::now();
-        { // Scope Mutex.lock() to avoid holding them for more than necessary. Good practice.
-            let mark_value_cache = RQ_MARK_VALUE_CACHE.lock_ignore_poison();
-            for (ticker, mark_value, mark_time) in mark_value_cache.get_mark_timevalues(orders.iter().map(|order| order.ticker.as_str()))
+        // Pre-trade check of the quote stream, lock-free: the feed states from the watchdog's snapshot, the staleness only for the order tickers (not the whole universe).
+        // A ticker without a mark, or with a stale mark (see QUOTE_STREAM_STALE_TICKER_SEC), doesn't use the cache: it falls back to IB get_price().
+        // A REST snapshot mark is never fresh: its time is the fetch time, not the time of the last trade, which can be hours old for an illiquid ticker.
+        // It is the mark only if there is no bid/ask: Quote::mark() prefers the mid over a snapshot last.
+        let feeds_health = RQ_QUOTE_BOOK.get_feeds_health();
+        log_and_println!("  Quote stream feeds down: {}", feeds_down_summary(&feeds_health, now));
+        writeln!(user_log, "  Quote stream feeds down: {}", feeds_down_summary(&feeds_health, now)).ok();
+        { // Lock-free reads (QuoteBook): the tick merge task keeps updating the quotes meanwhile
+            let mark_policy = RQ_QUOTE_BOOK.get_mark_policy();
+            for ticker in orders.iter().map(|order| order.ticker.as_str())
             {
-                log_and_println!("  MarkValue cache: {} => value: {}, time: {}", ticker, mark_value, mark_time);
-                writeln!(user_log, "  MarkValue cache: {} => value: {}, time: {}", ticker, mark_value, mark_time).ok();
-                if mark_time < now - chrono::Duration::minutes(2) {
-                    log_and_println!("  MarkValue cache: {} is stale (value: {}, time: {}). Consider improving the cache freshness or reliability.", ticker, mark_value, mark_time);
+                let quote = RQ_QUOTE_BOOK.get_quote(ticker); // the mark and the limit price are from the same Quote
+                let (mark_value, mark_time, source) = quote.mark(mark_policy, now);
+                log_and_println!("  MarkValue cache: {} => value: {}, time: {}, source: {}", ticker, mark_value, mark_time, source);
+                writeln!(user_log, "  MarkValue cache: {} => value: {}, time: {}, source: {}", ticker, mark_value, mark_time, source).ok();
+                if mark_value.is_nan() {
+                    continue;
+                }
+                if source == MarkSource::Snapshot {
+                    log_and_println!("  MarkValue cache: {} is a REST snapshot (value: {}, fetched: {}). Its trade time is unknown. Not used.", ticker, mark_value, mark_time);
+                    writeln!(user_log, "  MarkValue cache: {} is a REST snapshot (value: {}, fetched: {}). Its trade time is unknown. Not used.", ticker, mark_value, mark_time).ok();
+                    continue;
                 }
-                if !mark_value.is_nan() {
-                    ticker_markvalues.insert(ticker.to_string(), mark_value);
+                if is_mark_stale(mark_time, now) { // an illiquid ticker's last trade can be old too: IB's price is not worse then
+                    log_and_println!("  MarkValue cache: {} is stale (value: {}, time: {}, source: {}). Not used.", ticker, mark_value, mark_time, source);
+                    writeln!(user_log, "  MarkValue cache: {} is stale (value: {}, time: {}, source: {}). Not used.", ticker, mark_value, mark_time, source).ok();
+                    continue;
                 }
+                ticker_markvalues.insert(ticker.to_string(), mark_value);
+                ticker_quotes.insert(ticker.to_string(), quote);
             }
         }
 
@@ -224,7 +279,7 @@ impl BrokersWatcher {
         for order in &orders {
             if let Some(price) = ticker_markvalues.get(&order.ticker) { // if price is found in ticker_markvalues, then use it.
                 log_and_println!("  Using MarkValue cache price for {}: ${}", order.ticker, price);
-                BrokersWatcher::place_order(is_simulation, &ib_client_gyantal, &ib_client_dcmain, order, *price, user_log).await;
+                BrokersWatcher::place_order(is_simulation, &ib_client_gyantal, &ib_client_dcmain, order, *price, ticker_quotes.get(&order.ticker), user_log).await;
             } else {
                 log_and_println!("  No valid MarkValue cache price for {}. Will call IB get_price() which can be slow (e.g. 550ms)...", order.ticker);
                 unknown_price_orders.push(order);
@@ -234,12 +289,12 @@ impl BrokersWatcher {
         for order in &unknown_price_orders {
             let price : f64 = BrokersWatcher::get_knownlast_or_ib_price(&ib_client_dcmain, &order.ticker, &order.company_name, order.known_last_price).await;
             log_and_println!("  IB get_price() for {}: ${}", order.ticker, price);
-            BrokersWatcher::place_order(is_simulation, &ib_client_gyantal, &ib_client_dcmain, order, price, user_log).await;
+            BrokersWatcher::place_order(is_simulation, &ib_client_gyantal, &ib_client_dcmain, order, price, ticker_quotes.get(&order.ticker), user_log).await;
         }
 
     }
 
-    async fn place_order(is_simulation: bool, ib_client_gyantal: &Arc<Client>, _ib_client_dcmain: &Arc<Client>, order: &RqOrder, price : f64, user_log: &mut String) {
+    async fn place_order(is_simulation: bool, ib_client_gyantal: &Arc<Client>, _ib_client_dcmain: &Arc<Client>, order: &RqOrder, price : f64, quote: Option<&Quote>, user_log: &mut String) {
         if price.is_nan() {
             log_and_println!("  {:?} {} ({}, cannot determine price, skipping...)", order.order_type, order.ticker, order.company_name);
             writeln!(user_log, "  {:?} {} ({}, cannot determine price, skipping...)", order.order_type, order.ticker, order.company_name).ok();
@@ -251,9 +306,11 @@ impl BrokersWatcher {
             writeln!(user_log, "  {:?} {} ({}, price: ${}, nShares: {}, zero size, skipping...)", order.order_type, order.ticker, order.company_name, price, num_shares).ok();
             return;
         }
-        log_and_println!("  {:?} {} ({}, price: ${}, nShares: {}, before order())", order.order_type, order.ticker, order.company_name, price, num_shares);
-        writeln!(user_log, "  {:?} {} ({}, price: ${}, nShares: {}, before order())", order.order_type, order.ticker, order.company_name, price, num_shares).ok();
+        let limit_price = BrokersWatcher::get_limit_price(order.order_type, price, quote, RQ_QUOTE_BOOK.get_mark_policy(), Utc::now());
+        log_and_println!("  {:?} {} ({}, price: ${}, limit: ${}, nShares: {}, before order())", order.order_type, order.ticker, order.company_name, price, limit_price, num_shares);
+        writeln!(user_log, "  {:?} {} ({}, price: ${}, limit: ${}, nShares: {}, before order())", order.order_type, order.ticker, order.company_name, price, limit_price, num_shares).ok();
         if is_simulation {
+            RQ_EVENT_HUB.publish(RqEventTopic::Orders, "order_simulated", json!({"order_type": order.order_type.to_string(), "ticker": order.ticker, "shares": num_shares, "price": price, "limit_price": limit_price}));
             return;
         }
 
@@ -269,8 +326,7 @@ impl BrokersWatcher {
                     .order(&contract)
                     .buy(num_shares)
                     // .market()
-                    // Limit buy order at 2.1% above the price. IB rejects too-wide LMT orders.
-                    .limit(((price * 1.021) * 100.0).round() / 100.0)
+                    .limit(limit_price)
                     .submit()
                     .await
                     .expect("order submission failed!")
@@ -280,14 +336,28 @@ impl BrokersWatcher {
                     .order(&contract)
                     .sell(num_shares)
                     // .market()
-                    // Limit sell order at -2.1% below price
-                    .limit(((price * 0.979) * 100.0).round() / 100.0)
+                    .limit(limit_price)
                     .submit()
                     .await
                     .expect("order submission failed!")
             }
         };
         log_and_println!("Order submitted: OrderID: {}, Ticker: {}, Shares: {}", order_id, contract.symbol, num_shares);
+        RQ_EVENT_HUB.publish(RqEventTopic::Orders, "order_submitted", json!({"order_id": order_id.to_string(), "order_type": order.order_type.to_string(), "ticker": order.ticker, "shares": num_shares, "price": price, "limit_price": limit_price}));
+    }
+
+    // With a fresh bid/ask (both sides within the MarkPolicy recency window): half a spread through the far side (marketable, but not at any price).
+    // Without it: 2.1% from the price. The result is clamped to price ±2.1% on both sides: IB rejects too-wide LMT orders, and a bid/ask far from
+    // the price (e.g. a wide pre-open book) would give a limit that is not marketable.
+    fn get_limit_price(order_type: RqOrderType, price: f64, quote: Option<&Quote>, mark_policy: MarkPolicy, now: DateTime<Utc>) -> f64 {
+        let spread_bid_ask = quote.and_then(|q| q.fresh_spread(mark_policy, now).map(|spread| (spread, q.bid, q.ask)));
+        let limit_price = match (order_type, spread_bid_ask) {
+            (RqOrderType::Buy, Some((spread, _bid, ask))) => ask + 0.5 * spread,
+            (RqOrderType::Sell, Some((spread, bid, _ask))) => bid - 0.5 * spread,
+            (RqOrderType::Buy, None) => price * 1.021,
+            (RqOrderType::Sell, None) => price * 0.979,
+        };
+        (limit_price.clamp(price * 0.979, price * 1.021) * 100.0).round() / 100.0
     }
 
     pub async fn get_knownlast_or_ib_price(ib_client_dcmain: &Arc<Client>, ticker: &str, company_name: &str, known_last_price: Option<f64>) -> f64 {
@@ -325,4 +395,40 @@ impl BrokersWatcher {
 
         price
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(sec: i64) -> DateTime<Utc> {
+        DateTime::<Utc>::from_timestamp(1_760_000_000 + sec, 0).unwrap()
+    }
+
+    fn bid_ask(bid: f64, ask: f64, bid_ask_time: DateTime<Utc>) -> Quote {
+        Quote { bid, bid_time: bid_ask_time, ask, ask_time: bid_ask_time, bid_ask_source: MarkSource::IbStream, ..Quote::empty() }
+    }
+
+    #[test]
+    fn limit_price() {
+        let now = t(100);
+        let cases = [
+            // (order type, price, quote, expected limit)
+            (RqOrderType::Buy, 100.0, None, 102.1), // no quote: 2.1% from the price
+            (RqOrderType::Sell, 100.0, None, 97.9),
+            (RqOrderType::Buy, 100.0, Some(bid_ask(99.9, 100.1, t(95))), 100.2), // half a spread through the ask
+            (RqOrderType::Sell, 100.0, Some(bid_ask(99.9, 100.1, t(95))), 99.8),
+            (RqOrderType::Buy, 100.0, Some(bid_ask(99.9, 100.1, t(30))), 102.1), // the bid/ask is older than the 60 sec window
+            (RqOrderType::Buy, 100.0, Some(bid_ask(100.1, 99.9, t(95))), 102.1), // crossed book
+            (RqOrderType::Buy, 100.0, Some(bid_ask(f64::NAN, 100.1, t(95))), 102.1), // no bid
+            (RqOrderType::Buy, 100.0, Some(bid_ask(104.0, 106.0, t(95))), 102.1), // far from the price: clamped
+            (RqOrderType::Sell, 100.0, Some(bid_ask(90.0, 94.0, t(95))), 97.9),
+            (RqOrderType::Buy, 100.0, Some(bid_ask(90.0, 94.0, t(95))), 97.9), // not marketable at the price: clamped to the other side
+            (RqOrderType::Buy, 12.345, None, 12.6), // rounded to cents
+        ];
+        for (order_type, price, quote, expected) in cases {
+            let limit_price = BrokersWatcher::get_limit_price(order_type, price, quote.as_ref(), MarkPolicy::DEFAULT, now);
+            assert!((limit_price - expected).abs() < 1e-9, "{} {} {:?}: {}, expected {}", order_type, price, quote.map(|q| (q.bid, q.ask)), limit_price, expected);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::{sync::Arc, time::Duration};
+use chrono::{NaiveDate, NaiveTime, Utc};
+use chrono_tz::America::New_York;
+use ibapi::{prelude::*, market_data::historical::WhatToShow};
+
+use rqcommon::{rqhelper::MutexExt, utils::trading_calendar::{is_nyse_trading_day, prev_nyse_trading_day}};
+use memdb::daily_history::{fetch_yahoo_daily_bars, DailyBar, RQ_DAILY_HISTORY};
+
+use crate::brokers_watcher::{BrokerClient, RQ_BROKERS_WATCHER};
+
+// Backfills the memdb daily history (daily_history.rs) up to the last completed NYSE trading day. A ticker without history gets 2 years.
+// Source: IB historical_data() of the DcMain gateway, or Yahoo if IB is not connected or fails. The prices are split and dividend adjusted.
+// The last few days are re-downloaded. If their closes differ from the stored ones, the adjusted history changed (a split or a dividend): the full history is re-downloaded.
+const DAILY_HISTORY_INITIAL_DAYS: i64 = 2 * 365;
+const DAILY_HISTORY_OVERLAP_DAYS: i64 = 5; // re-download the last few days: a bar downloaded during the day is replaced by the final one
+const IB_HISTORICAL_DATA_PAUSE: Duration = Duration::from_millis(500); // IB's strict pacing limits are for the bars of 30 sec or less. Daily bars only need to avoid a burst.
+
+pub struct DailyHistoryBackfillResult {
+    pub num_updated: usize,
+    pub num_up_to_date: usize,
+    pub failed_tickers: Vec<String>,
+}
+
+// Today's bar is complete after the 16:00 ET close (early closes: 13:00, but then the bar is simply re-downloaded the next day).
+pub fn last_completed_nyse_trading_day() -> NaiveDate {
+    let now_et = Utc::now().with_timezone(&New_York);
+    let today = now_et.date_naive();
+    if is_nyse_trading_day(today) && now_et.time() >= NaiveTime::from_hms_opt(16, 0, 0).unwrap() {
+        today
+    } else {
+        prev_nyse_trading_day(today)
+    }
+}
+
+pub async fn backfill_daily_history(tickers: &[String]) -> DailyHistoryBackfillResult {
+    let target_date = last_completed_nyse_trading_day();
+    let ib_client_dcmain = RQ_BROKERS_WATCHER.get_ib_client(BrokerClient::DcMain);
+    let mut result = DailyHistoryBackfillResult { num_updated: 0, num_up_to_date: 0, failed_tickers: Vec::new() };
+
+    for ticker in tickers {
+        let last_date = RQ_DAILY_HISTORY.lock_ignore_poison().get_last_date(ticker);
+        let num_days = match last_date {
+            Some(last_date) if last_date >= target_date => {
+                result.num_up_to_date += 1;
+                continue;
+            }
+            Some(last_date) => (target_date - last_date).num_days() + DAILY_HISTORY_OVERLAP_DAYS,
+            None => DAILY_HISTORY_INITIAL_DAYS,
+        };
+
+        let mut bars_result = fetch_daily_bars(ib_client_dcmain.as_ref(), ticker, num_days).await;
+        let mut is_full_history = last_date.is_none();
+        if !is_full_history && bars_result.as_ref().is_ok_and(|bars| !RQ_DAILY_HISTORY.lock_ignore_poison().is_overlap_matching(ticker, bars)) {
+            log::info!("Daily history of {}: the stored closes changed (split or dividend). Re-downloading the full history.", ticker);
+            bars_result = fetch_daily_bars(ib_client_dcmain.as_ref(), ticker, DAILY_HISTORY_INITIAL_DAYS).await;
+            is_full_history = true;
+        }
+
+        match bars_result {
+            Ok(bars) => {
+                let bars: Vec<DailyBar> = bars.into_iter().filter(|bar| bar.date <= target_date).collect(); // not today's incomplete bar
+                if is_full_history {
+                    RQ_DAILY_HISTORY.lock_ignore_poison().replace_bars(ticker, bars);
+                } else {
+                    RQ_DAILY_HISTORY.lock_ignore_poison().merge_bars(ticker, bars);
+                }
+                result.num_updated += 1;
+            }
+            Err(err) => {
+                log::error!("Daily history of {} failed: {}", ticker, err);
+                result.failed_tickers.push(ticker.clone());
+            }
+        }
+    }
+
+    if result.num_updated > 0 {
+        if let Err(err) = RQ_DAILY_HISTORY.lock_ignore_poison().save() {
+            log::error!("Daily history save failed: {}", err);
+        }
+    }
+    result
+}
+
+async fn fetch_daily_bars(ib_client_dcmain: Option<&Arc<Client>>, ticker: &str, num_days: i64) -> Result<Vec<DailyBar>, String> {
+    let mut bars_result = Err("IB is not connected".to_string());
+    if let Some(ib_client) = ib_client_dcmain {
+        bars_result = fetch_ib_daily_bars(ib_client, ticker, num_days).await;
+        tokio::time::sleep(IB_HISTORICAL_DATA_PAUSE).await;
+    }
+    if let Err(ib_err) = &bars_result {
+        log::warn!("Daily history of {}: IB failed ({}). Trying Yahoo.", ticker, ib_err);
+        bars_result = fetch_yahoo_daily_bars(ticker, num_days).await;
+    }
+    bars_result
+}
+
+// AdjustedLast: split and dividend adjusted. IB only gives it without an end date (None: up to now).
+async fn fetch_ib_daily_bars(ib_client: &Arc<Client>, ticker: &str, num_days: i64) -> Result<Vec<DailyBar>, String> {
+    let contract = Contract::stock(ticker).build();
+    let duration = if num_days > 365 { (((num_days + 364) / 365) as i32).years() } else { (num_days as i32).days() }; // IB: over 365 days, the duration must be given in years
+    let historical_data = ib_client
+        .historical_data(&contract, None, duration, HistoricalBarSize::Day, Some(WhatToShow::AdjustedLast), TradingHours::Regular)
+        .await
+        .map_err(|err| err.to_string())?;
+    historical_data.bars.iter()
+        .map(|bar| {
+            let date = bar.date.date();
+            let date = NaiveDate::from_ymd_opt(date.year(), u8::from(date.month()) as u32, date.day() as u32).ok_or_else(|| format!("invalid bar date {}", bar.date))?;
+            Ok(DailyBar { date, open: bar.open, high: bar.high, low: bar.low, close: bar.close, volume: bar.volume.max(0.0) as u64 })
+        })
+        .collect()
+}
@@ -1,9 +1,13 @@
 use std::sync::Arc;
 
 use ibapi::{Client, ConnectionOptions};
+use serde_json::json;
+
+use rqcommon::utils::rqevent_hub::{RqEventTopic, RQ_EVENT_HUB};
 
 // ---------- Gateway ----------
 pub struct Gateway {
+    pub name: String, // the BrokerClient, for the logs and the Gateway events
     pub connection_url: String,
     pub client_id: i32,
 
@@ -15,12 +19,25 @@ pub struct Gateway {
 }
 
 impl Gateway {
-    pub fn new(connection_url: &str, client_id: i32) -> Self {
-        Self { connection_url: connection_url.to_string(), client_id, ib_client: None }
+    pub fn new(name: &str, connection_url: &str, client_id: i32) -> Self {
+        Self { name: name.to_string(), connection_url: connection_url.to_string(), client_id, ib_client: None }
     }
 
     pub async fn init(&mut self) {
         log::debug!("Gateway.init() start");
+        self.connect(false).await;
+    }
+
+    // A new connection (a new Client) that replaces the old one, e.g. after the TWS restart. The users that cloned the old Arc<Client> keep the dead Client:
+    // they should get the client again via BrokersWatcher.get_ib_client()
+    pub async fn reconnect(&mut self) {
+        log::debug!("Gateway.reconnect() start");
+        self.ib_client = None;
+        self.connect(true).await;
+    }
+
+    // The Gateway events are published here, so every (re)connection is reported, not only the startup ones
+    async fn connect(&mut self, is_reconnect: bool) {
 
         // tcp_no_delay: "Order submissions: 0-40ms latency reduction (small writes sent immediately)"
         // "Nagle's algorithm is a TCP optimization technique designed to improve network efficiency by reducing the number of small packets
@@ -43,16 +60,25 @@ impl Gateway {
             Ok(client) => {
                 self.ib_client = Some(Arc::new(client));
                 log::info!("Connected to TWS at {}", self.connection_url);
+                self.publish_event(if is_reconnect { "reconnected" } else { "connected" }, None);
             }
             Err(e) => {
                 log::error!("Failed to connect to TWS at {}: {}", self.connection_url, e);
+                self.publish_event("connect_failed", Some(e.to_string()));
             }
         }
     }
 
     pub async fn exit(&mut self) {
         // Client is automatically disconnected when dropped
-        self.ib_client = None; // disconnect on drop
+        let was_connected = self.ib_client.take().is_some(); // disconnect on drop
         log::info!("Disconnected from TWS at {}", self.connection_url);
+        if was_connected {
+            self.publish_event("disconnected", None);
+        }
+    }
+
+    fn publish_event(&self, kind: &str, error: Option<String>) {
+        RQ_EVENT_HUB.publish(RqEventTopic::Gateway, kind, json!({"broker_client": self.name, "connection_url": self.connection_url, "error": error}));
     }
 }
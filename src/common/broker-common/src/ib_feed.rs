@@ -0,0 +1,130 @@
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use ibapi::prelude::*;
+use tokio::{sync::mpsc, task::{JoinHandle, JoinSet}};
+
+use memdb::{feed::{FeedTick, FeedTickKind, MarkValueFeed}, mark_value_cache::MarkSource};
+
+use crate::brokers_watcher::{BrokerClient, RQ_BROKERS_WATCHER};
+
+pub const IB_FEED_DEFAULT_MAX_TICKERS: usize = 10;
+
+// ---------- IbFeed ----------
+// IB tick-by-tick trades and bid/ask of the DcMain gateway (it has the market data permissions) as a MarkValueCache feed.
+// Faster than YF, and the bid/ask arrives for the untraded tickers too.
+// IB limits the simultaneous tick-by-tick subscriptions by the market data lines of the account (error 10190 above it). Each ticker takes 2 (trades + bid/ask),
+// so only the first max_tickers tickers of the universe are subscribed. The rest is covered by the YF feed.
+// The client is not kept: each (re)start gets the current client of the DcMain gateway, so a watchdog restart uses the client of the last reconnection.
+// If the gateway is not connected (e.g. it was down at the server start), or no subscription could be made on its client (e.g. TWS was restarted,
+// or error 10190: no free market data lines), the feed just ends: the watchdog restarts it with a backoff.
+// The feed never reconnects the gateway: DcMain is the trading connection too, and a failed subscription doesn't mean its client is dead.
+pub struct IbFeed {
+    max_tickers: usize,
+}
+
+impl IbFeed {
+    pub fn new(max_tickers: usize) -> Self {
+        IbFeed { max_tickers }
+    }
+}
+
+impl MarkValueFeed for IbFeed {
+    fn source(&self) -> MarkSource { MarkSource::IbStream }
+
+    fn start(&self, symbols: Vec<String>, tick_sender: mpsc::Sender<FeedTick>) -> Result<JoinHandle<()>, String> {
+        if self.max_tickers == 0 {
+            return Err("max_tickers is 0".to_string());
+        }
+        if symbols.len() > self.max_tickers {
+            log::warn!("IbFeed: {} tickers in the universe, subscribing only the first {}.", symbols.len(), self.max_tickers);
+        }
+        let symbols = self.subscribed_symbols(&symbols);
+        Ok(tokio::spawn(async move {
+            let Some(ib_client) = RQ_BROKERS_WATCHER.get_ib_client(BrokerClient::DcMain) else {
+                log::warn!("IbFeed: DcMain gateway is not connected. The feed ends.");
+                return;
+            };
+            let mut subscription_tasks = JoinSet::new(); // aborted when this task is aborted (the JoinSet is dropped)
+            for symbol in symbols {
+                subscription_tasks.spawn(stream_trades(ib_client.clone(), symbol.clone(), tick_sender.clone()));
+                subscription_tasks.spawn(stream_bid_ask(ib_client.clone(), symbol, tick_sender.clone()));
+            }
+            drop(tick_sender);
+            let mut is_any_subscribed = false;
+            while let Some(result) = subscription_tasks.join_next().await {
+                is_any_subscribed |= result.unwrap_or(false);
+            }
+            log::warn!("IbFeed: all subscriptions ended.");
+            if !is_any_subscribed {
+                log::warn!("IbFeed: no subscription could be made on the DcMain client.");
+            }
+        }))
+    }
+
+    fn subscribed_symbols(&self, symbols: &[String]) -> Vec<String> {
+        symbols.iter().take(self.max_tickers).cloned().collect()
+    }
+}
+
+// Returns whether the subscription was made
+async fn stream_trades(ib_client: Arc<Client>, symbol: String, tick_sender: mpsc::Sender<FeedTick>) -> bool {
+    let contract = Contract::stock(&symbol).build();
+    let mut subscription = match ib_client.tick_by_tick_last(&contract, 0, false).await {
+        Ok(subscription) => subscription,
+        Err(err) => {
+            log::warn!("IbFeed: tick_by_tick_last({}) failed: {}", symbol, err);
+            return false;
+        }
+    };
+    while let Some(trade_result) = subscription.next().await {
+        let trade = match trade_result {
+            Ok(trade) => trade,
+            Err(err) => {
+                log::warn!("IbFeed: trade subscription of {} ended: {}", symbol, err);
+                break;
+            }
+        };
+        if trade.price <= 0.0 {
+            continue;
+        }
+        let tick = FeedTick { ticker: symbol.clone(), kind: FeedTickKind::Trade { price: trade.price, size: Some(trade.size as u64), volume_delta: None }, source: MarkSource::IbStream, time: to_chrono_time(trade.time), received_at: Utc::now() };
+        if tick_sender.send(tick).await.is_err() {
+            break;
+        }
+    }
+    true
+}
+
+// Returns whether the subscription was made
+async fn stream_bid_ask(ib_client: Arc<Client>, symbol: String, tick_sender: mpsc::Sender<FeedTick>) -> bool {
+    let contract = Contract::stock(&symbol).build();
+    let mut subscription = match ib_client.tick_by_tick_bid_ask(&contract, 0, false).await {
+        Ok(subscription) => subscription,
+        Err(err) => {
+            log::warn!("IbFeed: tick_by_tick_bid_ask({}) failed: {}", symbol, err);
+            return false;
+        }
+    };
+    while let Some(bid_ask_result) = subscription.next().await {
+        let bid_ask = match bid_ask_result {
+            Ok(bid_ask) => bid_ask,
+            Err(err) => {
+                log::warn!("IbFeed: bid/ask subscription of {} ended: {}", symbol, err);
+                break;
+            }
+        };
+        if bid_ask.bid_price <= 0.0 || bid_ask.ask_price <= 0.0 { // IB sends -1 or 0 for a missing side (e.g. before the open)
+            continue;
+        }
+        let tick = FeedTick { ticker: symbol.clone(), kind: FeedTickKind::BidAsk { bid: bid_ask.bid_price, ask: bid_ask.ask_price }, source: MarkSource::IbStream, time: to_chrono_time(bid_ask.time), received_at: Utc::now() };
+        if tick_sender.send(tick).await.is_err() {
+            break;
+        }
+    }
+    true
+}
+
+// IB tick times have 1 second resolution, so the IB latency statistics overestimate by up to 1 sec
+fn to_chrono_time(time: time::OffsetDateTime) -> DateTime<Utc> {
+    DateTime::from_timestamp(time.unix_timestamp(), time.nanosecond()).unwrap_or(DateTime::<Utc>::UNIX_EPOCH)
+}
@@ -1,3 +1,5 @@
 // keep root lib.rs minimal; all code should go in other files
 pub mod brokers_watcher; // publicly re-export submodules
-pub mod gateway;
\ No newline at end of file
+pub mod gateway;
+pub mod ib_feed;
+pub mod daily_history_backfill;
@@ -0,0 +1,267 @@
+use std::collections::VecDeque;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::mark_value_cache::MarkTime;
+
+// Intraday OHLCV bars from the trade ticks of the MarkValueCache feeds, so the price move and the VWAP of a short window (e.g. the FastRunner front-run window, slippage) need no IB historical_data() call.
+// The trades of a single feed are aggregated per ticker (IB if it subscribes the ticker, otherwise YF; see MarkValueCache::get_bar_source()),
+// because the feeds send the same trades with different timestamps: mixing them would count a trade twice. A late tick of that feed still goes to its bar.
+// A bar exists only for the periods with trades. Fixed size ring buffers per ticker: the oldest bars are dropped.
+
+const BAR_HISTORY_LEN_SEC1: usize = 1800; // 30 minutes of a continuously traded ticker
+const BAR_HISTORY_LEN_MIN1: usize = 960;  // 16 hours: pre-market + regular + after-hours
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarPeriod {
+    Sec1,
+    Min1,
+}
+
+impl BarPeriod {
+    pub const ALL: [BarPeriod; 2] = [BarPeriod::Sec1, BarPeriod::Min1];
+
+    pub fn parse(period: &str) -> Option<BarPeriod> {
+        match period.trim() {
+            "1s" => Some(BarPeriod::Sec1),
+            "1m" => Some(BarPeriod::Min1),
+            _ => None,
+        }
+    }
+
+    pub fn seconds(&self) -> i64 {
+        match self {
+            BarPeriod::Sec1 => 1,
+            BarPeriod::Min1 => 60,
+        }
+    }
+
+    fn history_len(&self) -> usize {
+        match self {
+            BarPeriod::Sec1 => BAR_HISTORY_LEN_SEC1,
+            BarPeriod::Min1 => BAR_HISTORY_LEN_MIN1,
+        }
+    }
+
+    // The start of the bar that contains time
+    pub fn bar_start(&self, time: MarkTime) -> MarkTime {
+        let seconds = self.seconds();
+        DateTime::<Utc>::from_timestamp(time.timestamp().div_euclid(seconds) * seconds, 0).unwrap_or(DateTime::<Utc>::UNIX_EPOCH)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Bar {
+    pub start: MarkTime,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: u64,     // 0 if the feed gave no trade size
+    pub vwap: f64,       // NaN (null in JSON) if no volume
+    pub tick_count: u32,
+    #[serde(skip)]
+    value_traded: f64,   // sum of price * volume: the VWAP numerator
+    #[serde(skip)]
+    close_time: MarkTime, // of the latest trade: a late tick (older than that) doesn't change the close
+}
+
+impl Bar {
+    fn new(start: MarkTime, time: MarkTime, price: f64, volume: u64) -> Self {
+        let mut bar = Bar { start, open: price, high: price, low: price, close: price, volume, vwap: f64::NAN, tick_count: 1, value_traded: price * volume as f64, close_time: time };
+        bar.vwap = vwap(bar.value_traded, bar.volume);
+        bar
+    }
+
+    // The open is the first arrived trade: the feeds are in time order, except for the rare late ticks
+    fn add_trade(&mut self, time: MarkTime, price: f64, volume: u64) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        if time >= self.close_time {
+            self.close = price;
+            self.close_time = time;
+        }
+        self.volume += volume;
+        self.tick_count += 1;
+        self.value_traded += price * volume as f64;
+        self.vwap = vwap(self.value_traded, self.volume);
+    }
+}
+
+fn vwap(value_traded: f64, volume: u64) -> f64 {
+    if volume == 0 { f64::NAN } else { value_traded / volume as f64 }
+}
+
+#[derive(Debug, Clone)]
+pub struct BarSeries {
+    period: BarPeriod,
+    bars: VecDeque<Bar>, // ordered by start, the newest at the back
+    has_dropped_bars: bool,
+}
+
+impl BarSeries {
+    pub fn new(period: BarPeriod) -> Self {
+        BarSeries { period, bars: VecDeque::new(), has_dropped_bars: false }
+    }
+
+    pub fn add_trade(&mut self, time: MarkTime, price: f64, volume: u64) {
+        let start = self.period.bar_start(time);
+        match self.bars.iter().rposition(|bar| bar.start <= start) {
+            Some(pos) if self.bars[pos].start == start => self.bars[pos].add_trade(time, price, volume),
+            Some(pos) => self.bars.insert(pos + 1, Bar::new(start, time, price, volume)), // usually pos + 1 == len: a new bar at the back
+            None if self.bars.len() < self.period.history_len() => self.bars.push_front(Bar::new(start, time, price, volume)),
+            None => return, // older than the history
+        }
+        if self.bars.len() > self.period.history_len() {
+            self.bars.pop_front();
+            self.has_dropped_bars = true;
+        }
+    }
+
+    // The last n bars, the oldest first
+    pub fn last_bars(&self, n: usize) -> Vec<Bar> {
+        self.bars.iter().skip(self.bars.len().saturating_sub(n)).cloned().collect()
+    }
+
+    pub fn oldest_bar_start(&self) -> Option<MarkTime> {
+        self.bars.front().map(|bar| bar.start)
+    }
+
+    // The bars that start at or after the bar of 'since'. So the first bar may contain trades up to 1 period before 'since'.
+    pub fn vwap_since(&self, since: MarkTime) -> f64 {
+        let since_start = self.period.bar_start(since);
+        let (value_traded, volume) = self.bars.iter().rev().take_while(|bar| bar.start >= since_start)
+            .fold((0.0, 0u64), |(value_traded, volume), bar| (value_traded + bar.value_traded, volume + bar.volume));
+        vwap(value_traded, volume)
+    }
+
+    // False if bars were dropped from the front: then the series doesn't cover the times before oldest_bar_start()
+    pub fn is_complete(&self) -> bool {
+        !self.has_dropped_bars
+    }
+}
+
+// The 1-second and 1-minute bars of a ticker
+#[derive(Debug, Clone)]
+pub struct TickerBars {
+    sec1: BarSeries,
+    min1: BarSeries,
+}
+
+impl TickerBars {
+    pub fn new() -> Self {
+        TickerBars { sec1: BarSeries::new(BarPeriod::Sec1), min1: BarSeries::new(BarPeriod::Min1) }
+    }
+
+    pub fn add_trade(&mut self, time: MarkTime, price: f64, volume: u64) {
+        self.sec1.add_trade(time, price, volume);
+        self.min1.add_trade(time, price, volume);
+    }
+
+    pub fn series(&self, period: BarPeriod) -> &BarSeries {
+        match period {
+            BarPeriod::Sec1 => &self.sec1,
+            BarPeriod::Min1 => &self.min1,
+        }
+    }
+
+    // From the 1-second bars, if they cover 'since', otherwise from the 1-minute bars (then the first bar may start up to 59 seconds before 'since').
+    pub fn vwap_since(&self, since: MarkTime) -> f64 {
+        let is_sec1_covering = self.sec1.is_complete() || self.sec1.oldest_bar_start().is_some_and(|oldest_start| oldest_start <= since);
+        if is_sec1_covering { self.sec1.vwap_since(since) } else { self.min1.vwap_since(since) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(sec: i64) -> MarkTime {
+        DateTime::<Utc>::from_timestamp(1_760_000_000 + sec, 0).unwrap()
+    }
+
+    #[test]
+    fn add_trade_aggregates_a_bar() {
+        let mut series = BarSeries::new(BarPeriod::Min1);
+        series.add_trade(t(0), 10.0, 100);
+        series.add_trade(t(10), 12.0, 100);
+        series.add_trade(t(20), 9.0, 200);
+        let bars = series.last_bars(10);
+        assert_eq!(bars.len(), 1);
+        let bar = &bars[0];
+        assert_eq!((bar.open, bar.high, bar.low, bar.close, bar.volume, bar.tick_count), (10.0, 12.0, 9.0, 9.0, 400, 3));
+        assert_eq!(bar.vwap, (10.0 * 100.0 + 12.0 * 100.0 + 9.0 * 200.0) / 400.0);
+    }
+
+    #[test]
+    fn add_trade_late_tick_goes_to_its_bar() {
+        let mut series = BarSeries::new(BarPeriod::Sec1);
+        series.add_trade(t(0), 10.0, 100);
+        series.add_trade(t(2), 12.0, 100);
+        series.add_trade(t(1), 11.0, 100); // late: a new bar between the 2
+        series.add_trade(t(0), 13.0, 50);  // late into an existing bar
+        let bars = series.last_bars(10);
+        assert_eq!(bars.iter().map(|bar| bar.start).collect::<Vec<_>>(), vec![t(0), t(1), t(2)]);
+        assert_eq!((bars[0].high, bars[0].volume, bars[0].tick_count), (13.0, 150, 2));
+    }
+
+    #[test]
+    fn add_trade_late_tick_keeps_the_close() {
+        let mut series = BarSeries::new(BarPeriod::Min1);
+        let late_time = t(30) - chrono::Duration::milliseconds(500);
+        series.add_trade(t(30), 10.0, 100);
+        series.add_trade(late_time, 11.0, 100);
+        let bar = &series.last_bars(1)[0];
+        assert_eq!((bar.open, bar.close, bar.high), (10.0, 10.0, 11.0));
+    }
+
+    #[test]
+    fn add_trade_evicts_the_oldest_bars() {
+        let mut series = BarSeries::new(BarPeriod::Sec1);
+        for sec in 0..BAR_HISTORY_LEN_SEC1 as i64 {
+            series.add_trade(t(sec), 10.0, 1);
+        }
+        assert!(series.is_complete());
+        assert_eq!(series.oldest_bar_start(), Some(t(0)));
+
+        series.add_trade(t(BAR_HISTORY_LEN_SEC1 as i64), 10.0, 1);
+        assert!(!series.is_complete());
+        assert_eq!(series.oldest_bar_start(), Some(t(1)));
+        assert_eq!(series.last_bars(usize::MAX).len(), BAR_HISTORY_LEN_SEC1);
+
+        series.add_trade(t(0), 99.0, 1); // older than the history: ignored
+        assert_eq!(series.oldest_bar_start(), Some(t(1)));
+        assert_eq!(series.last_bars(usize::MAX).len(), BAR_HISTORY_LEN_SEC1);
+    }
+
+    #[test]
+    fn vwap_since_sums_the_bars_from_since() {
+        let mut series = BarSeries::new(BarPeriod::Sec1);
+        series.add_trade(t(0), 10.0, 100);
+        series.add_trade(t(5), 20.0, 100);
+        series.add_trade(t(3), 30.0, 200); // out of order
+        assert_eq!(series.vwap_since(t(3)), (20.0 * 100.0 + 30.0 * 200.0) / 300.0);
+        assert_eq!(series.vwap_since(t(0)), (10.0 * 100.0 + 20.0 * 100.0 + 30.0 * 200.0) / 400.0);
+        assert!(series.vwap_since(t(6)).is_nan());
+    }
+
+    #[test]
+    fn vwap_since_is_nan_without_volume() {
+        let mut series = BarSeries::new(BarPeriod::Sec1);
+        series.add_trade(t(0), 10.0, 0);
+        assert!(series.vwap_since(t(0)).is_nan());
+    }
+
+    #[test]
+    fn ticker_bars_vwap_since_falls_back_to_min1_after_eviction() {
+        let mut ticker_bars = TickerBars::new();
+        ticker_bars.add_trade(t(0), 50.0, 100); // evicted from the 1-second bars below
+        for sec in 1..=BAR_HISTORY_LEN_SEC1 as i64 {
+            ticker_bars.add_trade(t(sec), 10.0, 1);
+        }
+        assert!(!ticker_bars.series(BarPeriod::Sec1).is_complete());
+        let vwap = ticker_bars.vwap_since(t(0));
+        let expected = (50.0 * 100.0 + 10.0 * BAR_HISTORY_LEN_SEC1 as f64) / (100.0 + BAR_HISTORY_LEN_SEC1 as f64);
+        assert!((vwap - expected).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,336 @@
+use std::{collections::{BTreeMap, HashMap}, fs, sync::{LazyLock, Mutex}};
+use chrono::{Datelike, NaiveDate};
+use serde::Serialize;
+use yfinance_rs::{HistoryBuilder, Interval, Range, YfClient};
+
+use rqcommon::utils::runningenv::rqcore_data_folder_path;
+
+// Daily OHLCV history of the traded tickers, in RAM (design_notes.md: RAM is 6x faster than SSD, 600x faster than the Internet), so sizing and reports look up
+// previous closes, N-day returns and volatilities without a download. Backfilled from IB historical_data() or Yahoo (see daily_history_backfill.rs in broker-common).
+// The prices are split and dividend adjusted (IB 'AdjustedLast', YF auto_adjust), so the returns and volatilities are not distorted by a split (a 10:1 split is not a -90% return).
+// A split or a dividend changes the adjusted past too: the backfill re-downloads the full history of a ticker if the re-downloaded overlap bars differ from the stored ones.
+// Persisted to a compact binary file, and loaded at startup.
+// File format (little endian): "RQDH", u16 version, u32 ticker count, then per ticker: u8 ticker length, ticker bytes, u32 bar count, then per bar:
+// i32 date (days from CE), f64 open, high, low, close, u64 volume. 44 bytes per bar: 2 years of 200 tickers is about 4.4 MB.
+
+const DAILY_HISTORY_FILENAME: &str = "daily_history.rqdh";
+const DAILY_HISTORY_FILE_MAGIC: &[u8; 4] = b"RQDH";
+const DAILY_HISTORY_FILE_VERSION: u16 = 2; // 2: adjusted prices. A version 1 (unadjusted) file is not loaded: the backfill re-downloads the history.
+const TRADING_DAYS_PER_YEAR: f64 = 252.0;
+const DAILY_HISTORY_CLOSE_MATCH_TOLERANCE: f64 = 1e-4; // relative. A dividend adjustment is usually above 0.1%, the rounding differences of the sources are below.
+
+pub static RQ_DAILY_HISTORY: LazyLock<Mutex<DailyHistory>> = LazyLock::new(|| Mutex::new(DailyHistory::new()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct DailyBar {
+    pub date: NaiveDate,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: u64,
+}
+
+pub struct DailyHistory {
+    histories: HashMap<String, Vec<DailyBar>>, // ticker => bars, sorted by date, one bar per date
+}
+
+impl DailyHistory {
+    pub fn new() -> Self {
+        DailyHistory { histories: HashMap::new() }
+    }
+
+    // Market data, not a secret: in the data folder, not in the sensitive config folder
+    fn file_path() -> String {
+        format!("{}{}", rqcore_data_folder_path(), DAILY_HISTORY_FILENAME)
+    }
+
+    // A missing file is an empty history. Returns the number of tickers.
+    pub fn load(&mut self) -> Result<usize, String> {
+        let path = Self::file_path();
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(format!("Cannot read '{}': {}", path, err)),
+        };
+        self.histories = decode_histories(&content).map_err(|err| format!("Invalid daily history file '{}': {}", path, err))?;
+        Ok(self.histories.len())
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::file_path();
+        // Write to a temp file and rename, so a crash during the write doesn't lose the history
+        let tmp_path = format!("{}.tmp", path);
+        fs::create_dir_all(rqcore_data_folder_path()).map_err(|err| format!("Cannot create '{}': {}", rqcore_data_folder_path(), err))?;
+        fs::write(&tmp_path, encode_histories(&self.histories)).map_err(|err| format!("Cannot write '{}': {}", tmp_path, err))?;
+        fs::rename(&tmp_path, &path).map_err(|err| format!("Cannot rename '{}' to '{}': {}", tmp_path, path, err))
+    }
+
+    // A new bar replaces the stored bar of the same date (e.g. a bar downloaded during the day is replaced by the final one)
+    pub fn merge_bars(&mut self, ticker: &str, new_bars: Vec<DailyBar>) {
+        let bars = self.histories.entry(ticker.to_string()).or_default();
+        let mut bars_by_date: BTreeMap<NaiveDate, DailyBar> = bars.drain(..).map(|bar| (bar.date, bar)).collect();
+        bars_by_date.extend(new_bars.into_iter().filter(|bar| !bar.close.is_nan()).map(|bar| (bar.date, bar)));
+        *bars = bars_by_date.into_values().collect();
+    }
+
+    // After a split or a dividend the whole adjusted history changes, so it is replaced, not merged
+    pub fn replace_bars(&mut self, ticker: &str, new_bars: Vec<DailyBar>) {
+        self.histories.remove(ticker);
+        self.merge_bars(ticker, new_bars);
+    }
+
+    // Whether the new bars of the already stored dates have the stored closes. If not, the adjusted history changed (a split or a dividend since the last backfill).
+    pub fn is_overlap_matching(&self, ticker: &str, new_bars: &[DailyBar]) -> bool {
+        let bars = self.get_bars(ticker);
+        new_bars.iter().all(|new_bar| match bars.binary_search_by_key(&new_bar.date, |bar| bar.date) {
+            Ok(i) => ((new_bar.close - bars[i].close) / bars[i].close).abs() <= DAILY_HISTORY_CLOSE_MATCH_TOLERANCE,
+            Err(_) => true, // a new date
+        })
+    }
+
+    pub fn get_tickers(&self) -> Vec<String> {
+        let mut tickers: Vec<String> = self.histories.keys().cloned().collect();
+        tickers.sort();
+        tickers
+    }
+
+    pub fn get_bars(&self, ticker: &str) -> &[DailyBar] {
+        self.histories.get(ticker).map(|bars| bars.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn get_last_date(&self, ticker: &str) -> Option<NaiveDate> {
+        self.get_bars(ticker).last().map(|bar| bar.date)
+    }
+
+    // The bars up to and including as_of. Binary search: a lookup is O(log n).
+    fn bars_until(&self, ticker: &str, as_of: NaiveDate) -> &[DailyBar] {
+        let bars = self.get_bars(ticker);
+        &bars[..bars.partition_point(|bar| bar.date <= as_of)]
+    }
+
+    // The close of the last bar before date. NaN if unknown.
+    pub fn get_prev_close(&self, ticker: &str, date: NaiveDate) -> f64 {
+        let bars = self.get_bars(ticker);
+        let end = bars.partition_point(|bar| bar.date < date);
+        if end == 0 { f64::NAN } else { bars[end - 1].close }
+    }
+
+    // close(as_of) / close(n bars before) - 1. The bars are trading days, so n = 5 is a week. NaN if the history is shorter.
+    pub fn get_n_day_return(&self, ticker: &str, n: usize, as_of: NaiveDate) -> f64 {
+        let bars = self.bars_until(ticker, as_of);
+        if n == 0 || bars.len() <= n {
+            return f64::NAN;
+        }
+        bars[bars.len() - 1].close / bars[bars.len() - 1 - n].close - 1.0
+    }
+
+    // Annualized standard deviation of the last n daily log returns up to as_of. NaN if the history is shorter.
+    pub fn get_volatility(&self, ticker: &str, n: usize, as_of: NaiveDate) -> f64 {
+        let bars = self.bars_until(ticker, as_of);
+        if n < 2 || bars.len() <= n {
+            return f64::NAN;
+        }
+        let log_returns: Vec<f64> = bars[bars.len() - 1 - n..].windows(2).map(|pair| (pair[1].close / pair[0].close).ln()).collect();
+        let mean = log_returns.iter().sum::<f64>() / n as f64;
+        let variance = log_returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1) as f64; // sample variance
+        (variance * TRADING_DAYS_PER_YEAR).sqrt()
+    }
+}
+
+// ---------- Yahoo backfill ----------
+// Split and dividend adjusted, as the IB 'AdjustedLast' bars. The date is the New York trading day. At least the last num_days days (YF gives ranges, not days).
+pub async fn fetch_yahoo_daily_bars(ticker: &str, num_days: i64) -> Result<Vec<DailyBar>, String> {
+    let range = match num_days {
+        ..=28 => Range::M1,
+        ..=180 => Range::M6,
+        ..=365 => Range::Y1,
+        _ => Range::Y2,
+    };
+    let client = YfClient::default();
+    let candles = HistoryBuilder::new(&client, ticker)
+        .range(range)
+        .interval(Interval::D1)
+        .auto_adjust(true)
+        .fetch()
+        .await
+        .map_err(|err| format!("YF history of {} failed: {}", ticker, err))?;
+    let to_f64 = yfinance_rs::core::conversions::money_to_f64;
+    Ok(candles.iter().map(|candle| DailyBar {
+        date: candle.ts.with_timezone(&chrono_tz::America::New_York).date_naive(),
+        open: to_f64(&candle.open),
+        high: to_f64(&candle.high),
+        low: to_f64(&candle.low),
+        close: to_f64(&candle.close),
+        volume: candle.volume.unwrap_or(0),
+    }).collect())
+}
+
+// ---------- File format ----------
+fn encode_histories(histories: &HashMap<String, Vec<DailyBar>>) -> Vec<u8> {
+    let num_bars: usize = histories.values().map(|bars| bars.len()).sum();
+    let mut buf: Vec<u8> = Vec::with_capacity(10 + histories.len() * 16 + num_bars * 44);
+    buf.extend_from_slice(DAILY_HISTORY_FILE_MAGIC);
+    buf.extend_from_slice(&DAILY_HISTORY_FILE_VERSION.to_le_bytes());
+    let tickers: Vec<&String> = histories.keys().filter(|ticker| !ticker.is_empty() && ticker.len() <= u8::MAX as usize).collect();
+    buf.extend_from_slice(&(tickers.len() as u32).to_le_bytes());
+    for ticker in tickers {
+        let bars = &histories[ticker];
+        buf.push(ticker.len() as u8);
+        buf.extend_from_slice(ticker.as_bytes());
+        buf.extend_from_slice(&(bars.len() as u32).to_le_bytes());
+        for bar in bars {
+            buf.extend_from_slice(&bar.date.num_days_from_ce().to_le_bytes());
+            for value in [bar.open, bar.high, bar.low, bar.close] {
+                buf.extend_from_slice(&value.to_le_bytes());
+            }
+            buf.extend_from_slice(&bar.volume.to_le_bytes());
+        }
+    }
+    buf
+}
+
+fn decode_histories(content: &[u8]) -> Result<HashMap<String, Vec<DailyBar>>, String> {
+    let mut reader = ByteReader { content, pos: 0 };
+    if reader.take(4)? != DAILY_HISTORY_FILE_MAGIC {
+        return Err("not a daily history file".to_string());
+    }
+    let version = u16::from_le_bytes(reader.take_array()?);
+    if version != DAILY_HISTORY_FILE_VERSION {
+        return Err(format!("unsupported version {}", version));
+    }
+    let num_tickers = u32::from_le_bytes(reader.take_array()?);
+    let mut histories = HashMap::with_capacity(num_tickers as usize);
+    for _ in 0..num_tickers {
+        let ticker_len = reader.take(1)?[0] as usize;
+        let ticker = String::from_utf8(reader.take(ticker_len)?.to_vec()).map_err(|_| "invalid ticker".to_string())?;
+        let num_bars = u32::from_le_bytes(reader.take_array()?);
+        let mut bars = Vec::with_capacity(num_bars as usize);
+        for _ in 0..num_bars {
+            let days_from_ce = i32::from_le_bytes(reader.take_array()?);
+            let date = NaiveDate::from_num_days_from_ce_opt(days_from_ce).ok_or_else(|| format!("invalid date of {}", ticker))?;
+            let open = f64::from_le_bytes(reader.take_array()?);
+            let high = f64::from_le_bytes(reader.take_array()?);
+            let low = f64::from_le_bytes(reader.take_array()?);
+            let close = f64::from_le_bytes(reader.take_array()?);
+            let volume = u64::from_le_bytes(reader.take_array()?);
+            bars.push(DailyBar { date, open, high, low, close, volume });
+        }
+        histories.insert(ticker, bars);
+    }
+    Ok(histories)
+}
+
+struct ByteReader<'a> {
+    content: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self.content.get(self.pos..self.pos + len).ok_or_else(|| "unexpected end of file".to_string())?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().expect("take() returns N bytes"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2025, 3, day).unwrap()
+    }
+
+    fn bar(day: u32, close: f64) -> DailyBar {
+        DailyBar { date: d(day), open: close - 1.0, high: close + 2.0, low: close - 2.0, close, volume: 1000 + day as u64 }
+    }
+
+    fn history_with_closes(ticker: &str, closes: &[f64]) -> DailyHistory {
+        let mut history = DailyHistory::new();
+        history.merge_bars(ticker, closes.iter().enumerate().map(|(i, close)| bar(i as u32 + 1, *close)).collect());
+        history
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let histories = HashMap::from([("AAPL".to_string(), vec![bar(3, 101.5), bar(4, 102.25)]), ("TSLA".to_string(), vec![bar(3, 250.0)]), ("EMPTY".to_string(), vec![])]);
+        let decoded = decode_histories(&encode_histories(&histories)).unwrap();
+        assert_eq!(decoded, histories);
+    }
+
+    #[test]
+    fn decode_truncated_input_fails() {
+        let histories = HashMap::from([("AAPL".to_string(), vec![bar(3, 101.5), bar(4, 102.25)])]);
+        let content = encode_histories(&histories);
+        for len in [0, 3, 6, 10, content.len() - 1] {
+            assert!(decode_histories(&content[..len]).is_err(), "len {}", len);
+        }
+    }
+
+    #[test]
+    fn decode_wrong_magic_or_version_fails() {
+        let mut content = encode_histories(&HashMap::from([("AAPL".to_string(), vec![bar(3, 101.5)])]));
+        let mut wrong_magic = content.clone();
+        wrong_magic[0] = b'X';
+        assert_eq!(decode_histories(&wrong_magic).unwrap_err(), "not a daily history file");
+        content[4..6].copy_from_slice(&1u16.to_le_bytes()); // the unadjusted version 1
+        assert_eq!(decode_histories(&content).unwrap_err(), "unsupported version 1");
+    }
+
+    #[test]
+    fn merge_bars_replaces_the_same_date_and_keeps_the_order() {
+        let mut history = DailyHistory::new();
+        history.merge_bars("AAPL", vec![bar(5, 10.0), bar(3, 8.0)]);
+        history.merge_bars("AAPL", vec![bar(5, 11.0), bar(4, 9.0), bar(6, f64::NAN)]); // NaN closes are dropped
+        let closes: Vec<(NaiveDate, f64)> = history.get_bars("AAPL").iter().map(|bar| (bar.date, bar.close)).collect();
+        assert_eq!(closes, vec![(d(3), 8.0), (d(4), 9.0), (d(5), 11.0)]);
+        assert_eq!(history.get_last_date("AAPL"), Some(d(5)));
+    }
+
+    #[test]
+    fn overlap_matching_detects_an_adjustment() {
+        let mut history = history_with_closes("AAPL", &[100.0, 110.0]);
+        assert!(history.is_overlap_matching("AAPL", &[bar(2, 110.0), bar(3, 120.0)]));
+        assert!(!history.is_overlap_matching("AAPL", &[bar(2, 11.0), bar(3, 12.0)])); // a 10:1 split
+        history.replace_bars("AAPL", vec![bar(1, 10.0), bar(2, 11.0)]);
+        assert_eq!(history.get_bars("AAPL").len(), 2);
+        assert_eq!(history.get_bars("AAPL")[0].close, 10.0);
+    }
+
+    #[test]
+    fn prev_close_is_the_last_bar_before_the_date() {
+        let history = history_with_closes("AAPL", &[100.0, 110.0, 120.0]); // March 1-3
+        assert_eq!(history.get_prev_close("AAPL", d(3)), 110.0);
+        assert_eq!(history.get_prev_close("AAPL", d(10)), 120.0);
+        assert!(history.get_prev_close("AAPL", d(1)).is_nan());
+        assert!(history.get_prev_close("TSLA", d(3)).is_nan());
+    }
+
+    #[test]
+    fn n_day_return_on_a_known_series() {
+        let history = history_with_closes("AAPL", &[100.0, 110.0, 99.0, 120.0]); // March 1-4
+        assert!((history.get_n_day_return("AAPL", 1, d(4)) - (120.0 / 99.0 - 1.0)).abs() < 1e-12);
+        assert!((history.get_n_day_return("AAPL", 3, d(4)) - 0.2).abs() < 1e-12);
+        assert!((history.get_n_day_return("AAPL", 1, d(2)) - 0.1).abs() < 1e-12); // as_of cuts the later bars
+        assert!(history.get_n_day_return("AAPL", 4, d(4)).is_nan()); // short history
+        assert!(history.get_n_day_return("AAPL", 0, d(4)).is_nan());
+    }
+
+    #[test]
+    fn volatility_on_a_known_series() {
+        // log returns: +r, -r, +r, -r => mean 0, sample variance 4r²/3
+        let r: f64 = 0.01;
+        let closes = [100.0, 100.0 * r.exp(), 100.0, 100.0 * r.exp(), 100.0];
+        let history = history_with_closes("AAPL", &closes);
+        let expected = (4.0 * r * r / 3.0 * TRADING_DAYS_PER_YEAR).sqrt();
+        assert!((history.get_volatility("AAPL", 4, d(5)) - expected).abs() < 1e-12);
+        assert!(history.get_volatility("AAPL", 5, d(5)).is_nan()); // short history
+        assert!(history.get_volatility("AAPL", 1, d(5)).is_nan()); // 1 return has no deviation
+        assert!(history.get_volatility("TSLA", 4, d(5)).is_nan());
+    }
+}
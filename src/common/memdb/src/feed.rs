@@ -0,0 +1,128 @@
+use std::time::Duration;
+use chrono::Utc;
+use serde::Serialize;
+use tokio::{sync::mpsc, task::JoinHandle};
+use yfinance_rs::{StreamBuilder, StreamMethod, YfClient};
+
+use crate::mark_value_cache::{MarkSource, MarkTime};
+
+// Real-time feeds of the MarkValueCache. Each feed streams ticks of the universe into one channel, and the cache merges them: the tick with the latest time wins (see MarkValueCache::apply_feed_tick()).
+// The YF feed is here. The IB feed is in broker-common (ib_feed.rs), because that owns the IB connections. Register it with MarkValueCache::register_feed().
+
+#[derive(Debug, Clone)]
+pub enum FeedTickKind {
+    // volume_delta: only from the feed that the cumulative daily volume is built from (YF). Other feeds give None, so no volume is counted twice.
+    // size: the traded volume of this tick (IB). The bars use size, or volume_delta if no size.
+    Trade { price: f64, size: Option<u64>, volume_delta: Option<u64> },
+    BidAsk { bid: f64, ask: f64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct FeedTick {
+    pub ticker: String,
+    pub kind: FeedTickKind,
+    pub source: MarkSource,
+    pub time: MarkTime,        // the exchange time of the trade or quote
+    pub received_at: MarkTime, // the local time of the arrival. received_at - time is the latency of the feed.
+}
+
+pub trait MarkValueFeed: Send + Sync {
+    fn source(&self) -> MarkSource;
+
+    // Starts streaming the symbols into tick_sender. Aborting the returned task stops the feed. The task ends if the feed ends (e.g. disconnection).
+    // Called with the MarkValueCache lock held, so it must not block or lock the cache.
+    fn start(&self, symbols: Vec<String>, tick_sender: mpsc::Sender<FeedTick>) -> Result<JoinHandle<()>, String>;
+
+    // The symbols that start() subscribes of the given ones (e.g. a feed with a subscription limit subscribes only some of them)
+    fn subscribed_symbols(&self, symbols: &[String]) -> Vec<String> {
+        symbols.to_vec()
+    }
+}
+
+// Latency of a feed: received_at - time. It includes the error of the local clock (Windows time can be seconds early), so compare the feeds with each other, not the absolute values.
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedStats {
+    pub source: String,
+    pub tick_count: u64,
+    pub freshest_count: u64,   // the ticks that updated the quote: they were newer than what the cache had from any feed
+    pub superseded_count: u64, // the ticks that arrived after a newer tick of the same ticker from another feed
+    pub latency_ms_mean: f64,
+    pub latency_ms_min: i64,
+    pub latency_ms_max: i64,
+    pub latency_ms_last: i64,
+    pub last_tick_at: Option<MarkTime>,
+    #[serde(skip)]
+    latency_ms_sum: i64,
+}
+
+impl FeedStats {
+    pub fn new(source: MarkSource) -> Self {
+        FeedStats {
+            source: source.to_string(),
+            tick_count: 0,
+            freshest_count: 0,
+            superseded_count: 0,
+            latency_ms_mean: f64::NAN, // null in JSON
+            latency_ms_min: 0,
+            latency_ms_max: 0,
+            latency_ms_last: 0,
+            last_tick_at: None,
+            latency_ms_sum: 0,
+        }
+    }
+
+    pub fn record(&mut self, tick: &FeedTick, is_freshest: bool) {
+        let latency_ms = (tick.received_at - tick.time).num_milliseconds();
+        self.tick_count += 1;
+        if is_freshest {
+            self.freshest_count += 1;
+        } else {
+            self.superseded_count += 1;
+        }
+        self.latency_ms_sum += latency_ms;
+        self.latency_ms_mean = self.latency_ms_sum as f64 / self.tick_count as f64;
+        self.latency_ms_min = if self.tick_count == 1 { latency_ms } else { self.latency_ms_min.min(latency_ms) };
+        self.latency_ms_max = if self.tick_count == 1 { latency_ms } else { self.latency_ms_max.max(latency_ms) };
+        self.latency_ms_last = latency_ms;
+        self.last_tick_at = Some(tick.received_at);
+    }
+}
+
+// ---------- YahooFeed ----------
+// YF websocket: about 1.5 seconds late, and only trades (no bid/ask). An untraded ticker gets no tick at all (the REST snapshots of the cache cover those).
+pub struct YahooFeed;
+
+impl MarkValueFeed for YahooFeed {
+    fn source(&self) -> MarkSource { MarkSource::Stream }
+
+    fn start(&self, symbols: Vec<String>, tick_sender: mpsc::Sender<FeedTick>) -> Result<JoinHandle<()>, String> {
+        let client = YfClient::default();
+        // Don't compare the YF timestamps to Windows time (4 seconds early), but IB TWS time.
+        let (handle, mut receiver) = StreamBuilder::new(&client)
+            .symbols(symbols)
+            .method(StreamMethod::WebsocketWithFallback)
+            .interval(Duration::from_secs(1))
+            .diff_only(true)
+            .start()
+            .map_err(|err| format!("YF stream start failed: {err}"))?;
+
+        Ok(tokio::spawn(async move {
+            let _stream_handle = handle; // dropped when the task is aborted, which closes the websocket
+
+            while let Some(update) = receiver.recv().await {
+                let price = update.price.as_ref().map(yfinance_rs::core::conversions::money_to_f64).unwrap_or(f64::NAN);
+                if price.is_nan() { // if NaN, there is no point storing it.
+                    continue;
+                }
+                let vol = update.volume.map(|v| format!(" (vol Δ: {v})")).unwrap_or_default();
+                println!("{}: ${:.2}{} (timestamp: {})", update.symbol, price, vol, update.ts.format("%Y-%m-%d %H:%M:%S"));
+
+                let tick = FeedTick { ticker: update.symbol.to_string(), kind: FeedTickKind::Trade { price, size: None, volume_delta: update.volume }, source: MarkSource::Stream, time: update.ts, received_at: Utc::now() };
+                if tick_sender.send(tick).await.is_err() { // the cache stopped the stream
+                    break;
+                }
+            }
+            log::warn!("YahooFeed: stream receiver ended.");
+        }))
+    }
+}
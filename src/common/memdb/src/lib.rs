@@ -1,2 +1,10 @@
 // keep root lib.rs minimal; all code should go in other files
 pub mod mark_value_cache; // publicly re-export submodules
+pub mod quote;
+pub mod quote_book;
+#[cfg(test)]
+mod quote_book_bench; // the benchmark of the QuoteBook: an #[ignore] test
+pub mod quote_stream_health;
+pub mod feed;
+pub mod bars;
+pub mod daily_history;
@@ -5,28 +5,93 @@
 // MarkPrice (EstPrice) can be calculated from Ask/Bid, even if there is no Last Trade price (as Options may not trade even 1 contracts for days, so there is no Last Trade, but we estimate the price from RT Ask/Bid)
 // MarkValue is a similar concept to IB's MarkPrice. An estimated price (Mark-to-Mark) that is used for margin calculations. It has a discretionary calculation, and can based on Ask/Bid/LastTrade (if happened recently)
 
+// The real-time ticks come from MarkValueFeeds (see feed.rs): the YF websocket (always), and the IB ticks (if registered). The freshest tick wins.
 // yfinance-rs's StreamBuilder Websocket implementation.
 // YF websocket only gives data if there was a real volume traded (non-liquid stocks might not get data, no matter how long we wait. E.g. PRE has about 1 trade per 10 minutes)
 // For 95% of the stocks, 5sec warmup is enough. However, consider 20 sec warmup to have some chance to get data for non-liquid stocks.
-// TODO: consider using yfinance-rs's YF REST API to get the initial MarkValue as a snapshot, and then use Websocket stream to update it in real-time during market hours.
-// This way we can have MarkValue for non-liquid stocks as well, even if there is hardly any trade during the day.
-use std::{collections::HashMap, sync::{LazyLock, Mutex}, time::Duration};
-use tokio::task::JoinHandle;
+// So while the stream runs, a REST quote batch (YF quotes API) seeds every ticker at the start (snapshot), and refreshes the stale ones periodically.
+// This way we have MarkValue for non-liquid stocks as well, even if there is hardly any trade during the day. Each value records its MarkSource, so users can judge its freshness.
+// The quotes are in the QuoteBook (quote_book.rs): read them with RQ_QUOTE_BOOK, lock-free. This Mutex is for the universe, the feeds, the bars and the writes.
+// While the stream has users, a watchdog task restarts the ended feeds (e.g. a closed YF websocket) with backoff. get_quote_stream_health() reports the staleness (see quote_stream_health.rs).
+use std::{collections::{BTreeMap, BTreeSet, HashMap, HashSet}, fmt, sync::{Arc, LazyLock, Mutex}, time::Duration};
+use tokio::{sync::{broadcast, mpsc}, task::JoinHandle};
 use chrono::{DateTime, Utc};
-use yfinance_rs::{StreamBuilder, StreamMethod, YfClient};
+use yfinance_rs::{QuotesBuilder, YfClient};
 
-use rqcommon::log_and_println;
+use rqcommon::{log_and_println, rqhelper::MutexExt};
+use crate::{bars::{Bar, BarPeriod, TickerBars}, feed::{FeedStats, FeedTick, FeedTickKind, MarkValueFeed, YahooFeed}, quote_book::RQ_QUOTE_BOOK, quote_stream_health::{FeedHealth, FeedRestartState, QuoteStreamCounters, QuoteStreamHealth, TickRateCounter}};
 
 pub type MarkValue = f64;
 pub type MarkTime = DateTime<Utc>;
 
+const MARK_VALUE_UPDATE_CHANNEL_CAPACITY: usize = 4096; // 1 tick/sec/ticker, so a few seconds of all the tickers
+const FEED_TICK_CHANNEL_CAPACITY: usize = 4096;
+const MARK_VALUE_SNAPSHOT_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+const QUOTE_STREAM_WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+pub const MARK_VALUE_STALE_AFTER_SEC: i64 = 60; // a value older than this is refreshed from a REST snapshot (while the stream runs)
+pub const TICKER_GROUP_MANUAL: &str = "manual"; // the tickers from the config (mark_value_ticker_universe) and the universe API
+pub const TICKER_GROUP_FASTRUNNER: &str = "fastrunner"; // the FastRunner candidates and positions, replaced before each FastRunner run
+
 pub static RQ_MARK_VALUE_CACHE: LazyLock<Mutex<MarkValueCache>> = LazyLock::new(|| Mutex::new(MarkValueCache::new()));
 
+// The Mutex is held only to copy the counters. The marks of the tickers are checked in the lock-free RQ_QUOTE_BOOK, so a large universe doesn't block the tick merge task.
+pub fn get_quote_stream_health() -> QuoteStreamHealth {
+    let counters = RQ_MARK_VALUE_CACHE.lock_ignore_poison().get_quote_stream_counters();
+    let (mark_policy, now) = (RQ_QUOTE_BOOK.get_mark_policy(), Utc::now());
+    let marks = RQ_QUOTE_BOOK.get_quotes().into_iter().map(|(ticker, quote)| {
+        let (mark_value, mark_time, _source) = quote.mark(mark_policy, now);
+        (ticker, mark_value, mark_time)
+    });
+    QuoteStreamHealth::new(counters, marks, now)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MarkSource {
+    None,     // no value yet (NaN)
+    Snapshot, // REST quote. MarkTime is the fetch time, because YF quotes don't tell the time of the last trade. So place_orders() doesn't trade on it as a fresh price.
+    Stream,   // YF websocket tick (a real trade). MarkTime is the trade time.
+    IbStream, // IB tick-by-tick trade or bid/ask. MarkTime is the exchange time.
+}
+
+impl fmt::Display for MarkSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MarkSource::None => "none",
+            MarkSource::Snapshot => "snapshot",
+            MarkSource::Stream => "stream",
+            MarkSource::IbStream => "ib_stream",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// The derived mark (see MarkPolicy) after a quote change
+#[derive(Debug, Clone)]
+pub struct MarkValueUpdate {
+    pub ticker: String,
+    pub mark_value: MarkValue,
+    pub mark_time: MarkTime,
+    pub source: MarkSource,
+}
+
+const BAR_SOURCE_PRIORITY: [MarkSource; 2] = [MarkSource::IbStream, MarkSource::Stream];
+
 pub struct MarkValueCache {
-    pub ticker_universe_csv: String,
-    pub mark_timevalues: HashMap<String, (MarkValue, MarkTime)>,
-    quote_stream_task: Option<JoinHandle<()>>, // parrallel stream task to update mark values in real-time during market hours.
+    ticker_groups: BTreeMap<String, BTreeSet<String>>, // group => tickers. The universe (the streamed tickers) is their union, so a group update doesn't remove the tickers of other users.
+    bars: HashMap<String, TickerBars>, // 1-second and 1-minute bars of the trades (see bars.rs)
+    feeds: Vec<Arc<dyn MarkValueFeed>>,
+    feed_tasks: HashMap<MarkSource, JoinHandle<()>>, // a task per feed, to update mark values in real-time during market hours
+    feed_symbols: HashMap<MarkSource, HashSet<String>>, // the symbols subscribed by the running feeds: for the bar source of a ticker
+    feed_restarts: HashMap<MarkSource, FeedRestartState>, // the watchdog's backoff per feed
+    tick_sender: Option<mpsc::Sender<FeedTick>>, // kept while the stream runs: a restarted feed sends into the same tick merge task
+    tick_merge_task: Option<JoinHandle<()>>, // applies the ticks of all feeds to the quotes
+    feed_stats: HashMap<MarkSource, FeedStats>,
+    tick_rate: TickRateCounter,
+    last_tick_at: Option<MarkTime>, // the arrival of the last tick of any feed
+    snapshot_task: Option<JoinHandle<()>>, // REST snapshot seeding and refreshing while the stream runs
+    watchdog_task: Option<JoinHandle<()>>,
     quote_stream_users: u16,
+    update_sender: broadcast::Sender<MarkValueUpdate>, // every received tick is sent to the subscribers (e.g. the /ws/markvalues websockets)
 }
 
 impl MarkValueCache {
@@ -34,92 +99,275 @@ impl MarkValueCache {
         Self {
             // When 65 tickers was given to YF websocket, in 20 seconds, 58 unique tickers received prices (which is OK, because if there is no trade, then no update is given).
             // So, if there is a YF limit for the number of tickers, it is greater than 60
-            // collect tickers. Potential Sells from SA Portfolio page. Potential Buys from Balazs.
-            // ticker_universe_csv: "ARREF,NESR,ORLA,IRS,CSTM,ALGT,SUHJY,NOAH,LASR,FXC,NLCP,FRO,STTK,EGO,RY,GM,FN,AIR,CRDO,PRE,CIEN,GRAL,PARR".to_string(), // 2026-02-23 (23)
-            // ticker_universe_csv: "LQDA,DTEGY,KGC,NATR,VFF,NESR,NEXA,IRS,SPHR,ORLA,LUV,RFIL,DRD,PSTL,CSTM,ALGT,SUHJY,ENLAY,EGO,ALPMY,AIR,SDVKY,BBVA,BOLSY,NLCP,LYG,TKC,MT,FN,BBSEY,IBDRY,NTIOF,CTRN,DELL,KNOP,ICR,TRX,ICHR,INNV,SNEX,SHIP,DHT,KMT,DAN,EZPW,LITE,NGD,SBSW,IAG,CGAU,AU,CRDO,DB,RY,CLS,CM,ENVA,PNC,IVZ,AMD,FHN,KEY,MFC,TSEM,CUBI".to_string(), // 2026-03-02 (#65)
-            ticker_universe_csv: "NATR,UNFI,VFF,TRX,IRS,SPHR,INNV,IMPUY,BNPQY,PSTL,RFIL,CSTM,ATI,LUV,SUHJY,ENLAY,SHIP,SOBO,DRD,AGFMF,SNEX,FSLY,VICR,ICHR,BIOA,CODA,GPRE,DHT,PINE,EZPW".to_string(), // 2026-03-09 (#30)
-            mark_timevalues: HashMap::new(),
-            quote_stream_task: None,
+            ticker_groups: BTreeMap::new(),
+            bars: HashMap::new(),
+            feeds: vec![Arc::new(YahooFeed)],
+            feed_tasks: HashMap::new(),
+            feed_symbols: HashMap::new(),
+            feed_restarts: HashMap::new(),
+            tick_sender: None,
+            tick_merge_task: None,
+            feed_stats: HashMap::new(),
+            tick_rate: TickRateCounter::new(),
+            last_tick_at: None,
+            snapshot_task: None,
+            watchdog_task: None,
             quote_stream_users: 0,
+            update_sender: broadcast::channel(MARK_VALUE_UPDATE_CHANNEL_CAPACITY).0,
         }
     }
 
     pub fn init(&mut self) {
-        self.mark_timevalues.clear();
+        RQ_QUOTE_BOOK.clear();
+        RQ_QUOTE_BOOK.set_tickers(&self.get_ticker_universe());
+    }
+
+    // ---------- Ticker universe ----------
+    pub fn get_ticker_universe(&self) -> BTreeSet<String> {
+        self.ticker_groups.values().flatten().cloned().collect()
+    }
 
-        for ticker in self
-            .ticker_universe_csv
-            .split(',')
-            .map(|ticker| ticker.trim())
-            .filter(|ticker| !ticker.is_empty())
-        {
-            self.mark_timevalues.insert(ticker.to_string(), (f64::NAN, DateTime::<Utc>::UNIX_EPOCH));
+    pub fn get_ticker_groups(&self) -> &BTreeMap<String, BTreeSet<String>> {
+        &self.ticker_groups
+    }
+
+    // Returns the tickers that are new in the group
+    pub fn add_tickers(&mut self, group: &str, tickers: &[String]) -> Vec<String> {
+        self.change_tickers(group, tickers, &[]).0
+    }
+
+    // Returns the tickers that were in the group. A ticker stays in the universe if another group has it.
+    pub fn remove_tickers(&mut self, group: &str, tickers: &[String]) -> Vec<String> {
+        self.change_tickers(group, &[], tickers).1
+    }
+
+    // Removes, then adds, with one universe change: a running stream resubscribes only once. Returns (added, removed).
+    pub fn change_tickers(&mut self, group: &str, add: &[String], remove: &[String]) -> (Vec<String>, Vec<String>) {
+        let old_universe = self.get_ticker_universe();
+        let group_tickers = self.ticker_groups.entry(group.to_string()).or_default();
+        let removed: Vec<String> = normalize_tickers(remove).filter(|ticker| group_tickers.remove(ticker)).collect();
+        let added: Vec<String> = normalize_tickers(add).filter(|ticker| group_tickers.insert(ticker.clone())).collect();
+        if group_tickers.is_empty() {
+            self.ticker_groups.remove(group);
+        }
+        self.on_ticker_universe_changed(old_universe);
+        (added, removed)
+    }
+
+    pub fn set_ticker_group(&mut self, group: &str, tickers: &[String]) {
+        let old_universe = self.get_ticker_universe();
+        let group_tickers: BTreeSet<String> = normalize_tickers(tickers).collect();
+        if group_tickers.is_empty() {
+            self.ticker_groups.remove(group);
+        } else {
+            self.ticker_groups.insert(group.to_string(), group_tickers);
         }
+        self.on_ticker_universe_changed(old_universe);
     }
 
+    // The quotes of the remaining tickers are kept. A running stream resubscribes with the new universe.
+    fn on_ticker_universe_changed(&mut self, old_universe: BTreeSet<String>) {
+        let new_universe = self.get_ticker_universe();
+        if new_universe == old_universe {
+            return;
+        }
+        RQ_QUOTE_BOOK.set_tickers(&new_universe);
+        self.bars.retain(|ticker, _| new_universe.contains(ticker));
+        log::info!("MarkValueCache: ticker universe changed: {} => {} ticker(s).", old_universe.len(), new_universe.len());
+
+        if self.quote_stream_users > 0 {
+            self.abort_stream_tasks();
+            self.spawn_stream_tasks();
+        }
+    }
+
+    // ---------- Quote stream ----------
     pub fn start_quote_stream(&mut self) {
         log_and_println!("MarkValueCache.start_quote_stream(): ! Only receives prices during Market Hours...");
         self.quote_stream_users = self.quote_stream_users.saturating_add(1);
 
-        if let Some(task) = self.quote_stream_task.as_ref() {
-            if !task.is_finished() {
-                log::info!("MarkValueCache.start_quote_stream(): stream already running (users: {}).", self.quote_stream_users);
-                return;
-            }
+        if self.tick_sender.is_some() { // running, even if a feed is down for a moment: the watchdog restarts it
+            log::info!("MarkValueCache.start_quote_stream(): stream already running (users: {}).", self.quote_stream_users);
+            return;
         }
+        self.abort_stream_tasks();
+        self.spawn_stream_tasks();
+    }
 
-        self.quote_stream_task = None;
-
-        let symbols: Vec<String> = self.ticker_universe_csv.split(',').map(|ticker| ticker.trim())
-            .filter(|ticker| !ticker.is_empty()).map(|ticker| ticker.to_string()).collect();
+    // A feed of the same source is replaced. A running stream restarts with the new feed.
+    pub fn register_feed(&mut self, feed: Arc<dyn MarkValueFeed>) {
+        self.feeds.retain(|f| f.source() != feed.source());
+        log::info!("MarkValueCache.register_feed(): {}", feed.source());
+        self.feeds.push(feed);
+        if self.quote_stream_users > 0 {
+            self.abort_stream_tasks();
+            self.spawn_stream_tasks();
+        }
+    }
 
+    // The user is counted even if this fails (e.g. empty universe): adding tickers later starts the stream for the users.
+    fn spawn_stream_tasks(&mut self) {
+        let symbols: Vec<String> = self.get_ticker_universe().into_iter().collect();
         if symbols.is_empty() {
-            log::warn!("MarkValueCache.start_quote_stream(): ticker_universe_csv is empty. Not starting background task.");
-            self.quote_stream_users = self.quote_stream_users.saturating_sub(1);
+            log::warn!("MarkValueCache.start_quote_stream(): the ticker universe is empty. Not starting background task.");
             return;
         }
 
-        let client = YfClient::default();
-
-        // YF Websocket stream is about 1.5 seconds late only. Don't compare it to Windows time (4 seconds early), but IB TWS time.
-        let (handle, mut receiver) = match StreamBuilder::new(&client)
-            .symbols(symbols.clone())
-            .method(StreamMethod::WebsocketWithFallback)
-            .interval(Duration::from_secs(1))
-            .diff_only(true)
-            .start() {
-                Ok((handle, receiver)) => (handle, receiver),
-                Err(err) => {
-                    log::error!("MarkValueCache.start_quote_stream(): failed to start stream: {err}");
-                    self.quote_stream_users = self.quote_stream_users.saturating_sub(1);
-                    return;
+        let (tick_sender, mut tick_receiver) = mpsc::channel::<FeedTick>(FEED_TICK_CHANNEL_CAPACITY);
+        self.tick_sender = Some(tick_sender);
+        self.feed_restarts.clear();
+        for feed in self.feeds.clone() {
+            self.start_feed(&feed, symbols.clone()); // a failed start is retried by the watchdog
+        }
+
+        let update_sender = self.update_sender.clone();
+        self.tick_merge_task = Some(tokio::spawn(async move {
+            while let Some(tick) = tick_receiver.recv().await {
+                let mark_update = RQ_MARK_VALUE_CACHE.lock_ignore_poison().apply_feed_tick(tick);
+                if let Some(mark_update) = mark_update {
+                    let _ = update_sender.send(mark_update); // fails only if there is no subscriber
                 }
-            };
+            }
+            log::warn!("MarkValueCache: the tick channel closed."); // only if the stream was stopped: the cache holds a sender while it runs
+        }));
+
+        self.snapshot_task = Some(tokio::spawn(run_snapshot_refresher(YfClient::default(), symbols.clone(), self.update_sender.clone())));
+        self.watchdog_task = Some(tokio::spawn(run_quote_stream_watchdog()));
+        log::info!("MarkValueCache.start_quote_stream(): started {} feed(s) for {} ticker(s) (users: {}).", self.feed_tasks.len(), symbols.len(), self.quote_stream_users);
+    }
 
-        let stream_task = tokio::spawn(async move {
-            let _stream_handle = handle;
+    fn start_feed(&mut self, feed: &Arc<dyn MarkValueFeed>, symbols: Vec<String>) {
+        let Some(tick_sender) = self.tick_sender.clone() else {
+            return;
+        };
+        let restart_state = self.feed_restarts.entry(feed.source()).or_insert_with(FeedRestartState::new);
+        let subscribed_symbols: HashSet<String> = feed.subscribed_symbols(&symbols).into_iter().collect();
+        match feed.start(symbols, tick_sender) {
+            Ok(feed_task) => {
+                restart_state.on_started(Utc::now());
+                self.feed_tasks.insert(feed.source(), feed_task);
+                self.feed_symbols.insert(feed.source(), subscribed_symbols);
+            }
+            Err(err) => {
+                let backoff = restart_state.on_failed(Utc::now(), err.clone());
+                log::error!("MarkValueCache: failed to start the {} feed: {err}. Retrying in {}s.", feed.source(), backoff.num_seconds());
+            }
+        }
+    }
 
-            while let Some(update) = receiver.recv().await {
-                let symbol = update.symbol.to_string();
-                let mark_value = update.price.as_ref().map(yfinance_rs::core::conversions::money_to_f64).unwrap_or(f64::NAN);
-                if mark_value.is_nan() { // if NaN, there is no point storing it.
-                    continue;
-                }
-                let mark_time = update.ts;
+    // The watchdog: restarts the ended feeds (e.g. the YF websocket closed by the server, an IB disconnection) after their backoff. Only while the stream has users.
+    fn check_feeds(&mut self) {
+        if self.quote_stream_users == 0 || self.tick_sender.is_none() {
+            return;
+        }
+        let now = Utc::now();
+        for feed in self.feeds.clone() {
+            let source = feed.source();
+            if self.feed_tasks.get(&source).is_some_and(|feed_task| !feed_task.is_finished()) {
+                continue;
+            }
+            let restart_state = self.feed_restarts.entry(source).or_insert_with(FeedRestartState::new);
+            if self.feed_tasks.remove(&source).is_some() {
+                let backoff = restart_state.on_failed(now, "the feed ended".to_string());
+                log::warn!("MarkValueCache watchdog: the {} feed ended. Restarting in {}s.", source, backoff.num_seconds());
+            }
+            if restart_state.is_restart_due(now) {
+                restart_state.restart_count += 1;
+                log::info!("MarkValueCache watchdog: restarting the {} feed (restart #{}).", source, restart_state.restart_count);
+                let symbols: Vec<String> = self.get_ticker_universe().into_iter().collect();
+                self.start_feed(&feed, symbols);
+            }
+        }
+        RQ_QUOTE_BOOK.set_feeds_health(self.get_feeds_health()); // for the lock-free readers (the pre-trade check of place_orders)
+    }
 
-                if let Ok(mut mark_value_cache) = RQ_MARK_VALUE_CACHE.lock() {
-                    mark_value_cache.mark_timevalues.insert(symbol.clone(), (mark_value, mark_time));
+    fn get_feeds_health(&self) -> Vec<FeedHealth> {
+        self.feeds.iter()
+            .map(|feed| {
+                let restart_state = self.feed_restarts.get(&feed.source());
+                FeedHealth {
+                    source: feed.source().to_string(),
+                    is_running: self.feed_tasks.get(&feed.source()).is_some_and(|feed_task| !feed_task.is_finished()),
+                    restart_count: restart_state.map(|state| state.restart_count).unwrap_or(0),
+                    next_restart_at: restart_state.and_then(|state| state.next_restart_at),
+                    last_error: restart_state.and_then(|state| state.last_error.clone()),
                 }
+            })
+            .collect()
+    }
+
+    // A copy of the counters for get_quote_stream_health(): the per-ticker staleness is computed outside the lock
+    pub fn get_quote_stream_counters(&self) -> QuoteStreamCounters {
+        QuoteStreamCounters {
+            users: self.quote_stream_users,
+            last_tick_at: self.last_tick_at,
+            ticks_last_minute: self.tick_rate.ticks_last_minute(Utc::now()),
+            feeds: self.get_feeds_health(),
+        }
+    }
 
-                let vol = update.volume.map(|v| format!(" (vol Δ: {v})")).unwrap_or_default();
-                println!("{}: ${:.2}{} (timestamp: {})", symbol, mark_value, vol, mark_time.format("%Y-%m-%d %H:%M:%S"));
+    // A tick older than the quote's field (another feed was faster) is only counted in the stats. Returns the update of the derived mark, if the quote changed.
+    fn apply_feed_tick(&mut self, tick: FeedTick) -> Option<MarkValueUpdate> {
+        self.tick_rate.record(tick.received_at);
+        self.last_tick_at = Some(tick.received_at);
+        let mut bar_trade: Option<(f64, u64)> = None; // (price, volume) for the bars
+        let is_freshest = RQ_QUOTE_BOOK.update(&tick.ticker, |quote| match tick.kind { // None: removed from the universe, and this tick arrived before the resubscribe
+            FeedTickKind::Trade { price, size, volume_delta } => {
+                if let (Some(volume), Some(volume_delta)) = (quote.volume, volume_delta) { // the YF stream sends the volume change. Cumulative only if a snapshot gave the day volume.
+                    quote.volume = Some(volume + volume_delta);
+                    quote.volume_time = tick.time;
+                }
+                bar_trade = Some((price, size.or(volume_delta).unwrap_or(0)));
+                let is_freshest = tick.time >= quote.last_time || quote.source == MarkSource::Snapshot; // a snapshot's time is the fetch time, not a trade time: a real trade replaces it
+                if is_freshest {
+                    quote.last = price;
+                    quote.last_time = tick.time;
+                    quote.source = tick.source;
+                }
+                is_freshest
             }
+            FeedTickKind::BidAsk { bid, ask } => {
+                let is_freshest = tick.time >= quote.bid_time;
+                if is_freshest {
+                    quote.bid = bid;
+                    quote.bid_time = tick.time;
+                    quote.ask = ask;
+                    quote.ask_time = tick.time;
+                    quote.bid_ask_source = tick.source;
+                }
+                is_freshest
+            }
+        })?;
+        if let Some((price, volume)) = bar_trade.filter(|_| tick.source == self.get_bar_source(&tick.ticker)) {
+            self.bars.entry(tick.ticker.clone()).or_insert_with(TickerBars::new).add_trade(tick.time, price, volume);
+        }
+        self.feed_stats.entry(tick.source).or_insert_with(|| FeedStats::new(tick.source)).record(&tick, is_freshest);
+        is_freshest.then(|| self.get_mark_update(&tick.ticker))
+    }
 
-            log::warn!("MarkValueCache.start_quote_stream(): stream receiver ended.");
-        });
+    // The bars are aggregated from 1 feed per ticker, independently of the freshest-wins mark: the feeds send the same trades with different
+    // timestamps (IB's are truncated to seconds), so mixing them would count a trade twice, or drop a distinct trade as 'not the freshest'.
+    // IB if it subscribes the ticker and runs (exchange times, trade sizes), otherwise YF.
+    fn get_bar_source(&self, ticker: &str) -> MarkSource {
+        let is_subscribed_by = |source: MarkSource| self.feed_symbols.get(&source).is_some_and(|symbols| symbols.contains(ticker))
+            && self.feed_tasks.get(&source).is_some_and(|feed_task| !feed_task.is_finished());
+        BAR_SOURCE_PRIORITY.into_iter().find(|source| is_subscribed_by(*source)).unwrap_or(MarkSource::Stream)
+    }
 
-        self.quote_stream_task = Some(stream_task);
-        log::info!("MarkValueCache.start_quote_stream(): started for {} ticker(s) (users: {}).", symbols.len(), self.quote_stream_users);
+    // The last n bars, the oldest first. Only the periods with trades have a bar.
+    pub fn get_last_bars(&self, ticker: &str, period: BarPeriod, n: usize) -> Vec<Bar> {
+        self.bars.get(ticker).map(|ticker_bars| ticker_bars.series(period).last_bars(n)).unwrap_or_default()
+    }
+
+    // NaN if there was no trade with volume since then
+    pub fn get_vwap_since(&self, ticker: &str, since: MarkTime) -> f64 {
+        self.bars.get(ticker).map(|ticker_bars| ticker_bars.vwap_since(since)).unwrap_or(f64::NAN)
+    }
+
+    pub fn get_feed_stats(&self) -> Vec<FeedStats> {
+        let mut feed_stats: Vec<FeedStats> = self.feed_stats.values().cloned().collect();
+        feed_stats.sort_by(|a, b| a.source.cmp(&b.source));
+        feed_stats
     }
 
     pub fn stop_quote_stream(&mut self) {
@@ -135,51 +383,146 @@ impl MarkValueCache {
             return;
         }
 
-        let Some(task) = self.quote_stream_task.take() else {
+        if self.tick_sender.is_none() {
             log::info!("MarkValueCache.stop_quote_stream(): no stream task to stop.");
             return;
-        };
-
-        task.abort();
+        }
+        self.abort_stream_tasks();
         log::info!("MarkValueCache.stop_quote_stream(): stop requested.");
     }
 
+    fn abort_stream_tasks(&mut self) {
+        if let Some(watchdog_task) = self.watchdog_task.take() {
+            watchdog_task.abort();
+        }
+        for (_source, feed_task) in self.feed_tasks.drain() {
+            feed_task.abort(); // drops the feed's stream handles (e.g. closes the YF websocket)
+        }
+        self.feed_symbols.clear();
+        self.tick_sender = None;
+        if let Some(tick_merge_task) = self.tick_merge_task.take() {
+            tick_merge_task.abort();
+        }
+        if let Some(snapshot_task) = self.snapshot_task.take() {
+            snapshot_task.abort();
+        }
+        RQ_QUOTE_BOOK.set_feeds_health(self.get_feeds_health());
+    }
+
+    // Only the updates after the subscription. Read the current values with RQ_QUOTE_BOOK.get_mark_timevalues().
+    pub fn subscribe_updates(&self) -> broadcast::Receiver<MarkValueUpdate> {
+        self.update_sender.subscribe()
+    }
+
     pub fn is_quote_stream_running(&self) -> bool {
-        self.quote_stream_task.as_ref().map(|task| !task.is_finished()).unwrap_or(false)
+        self.feed_tasks.values().any(|task| !task.is_finished())
     }
 
-    pub fn get_mark_value(&self, ticker: &str) -> MarkValue {
-        self.mark_timevalues
-            .get(ticker)
-            .map(|(value, _time)| *value)
-            .unwrap_or(f64::NAN)
+    // Sets bid/ask outside of the feeds (the feeds' ticks go through apply_feed_tick()). The MarkPolicy uses the mid when the last trade is old or missing.
+    pub fn update_bid_ask(&mut self, ticker: &str, bid: f64, ask: f64, quote_time: MarkTime, source: MarkSource) {
+        RQ_QUOTE_BOOK.update_or_insert(ticker, |quote| {
+            quote.bid = bid;
+            quote.bid_time = quote_time;
+            quote.ask = ask;
+            quote.ask_time = quote_time;
+            quote.bid_ask_source = source;
+        });
+        let _ = self.update_sender.send(self.get_mark_update(ticker));
     }
 
-    pub fn get_mark_timevalue(&self, ticker: &str) -> (MarkValue, MarkTime) {
-        self.mark_timevalues
-            .get(ticker)
-            .map(|(value, time)| (*value, time.clone()))
-            .unwrap_or((f64::NAN, DateTime::<Utc>::UNIX_EPOCH))
+    // A REST snapshot of the last price (and the day volume). None if a feed tick arrived during the fetch (that is a real trade or quote, keep it), or the ticker was removed from the universe.
+    fn apply_snapshot(&mut self, ticker: &str, last_price: f64, day_volume: Option<u64>, fetch_time: MarkTime) -> Option<MarkValueUpdate> {
+        if !is_stale(ticker, fetch_time) {
+            return None;
+        }
+        RQ_QUOTE_BOOK.update(ticker, |quote| {
+            quote.last = last_price;
+            quote.last_time = fetch_time;
+            quote.source = MarkSource::Snapshot;
+            if day_volume.is_some() {
+                quote.volume = day_volume;
+                quote.volume_time = fetch_time;
+            }
+        })?;
+        Some(self.get_mark_update(ticker))
     }
 
-    pub fn get_mark_values<'a, I>(&'a self, tickers: I) -> impl Iterator<Item = (&'a str, MarkValue)> + 'a
-    where
-        I: IntoIterator<Item = &'a str> + 'a,
-    {
-        tickers
-            .into_iter()
-            .map(move |ticker| (ticker, self.get_mark_value(ticker)))
+    fn get_mark_update(&self, ticker: &str) -> MarkValueUpdate {
+        let (mark_value, mark_time, source) = RQ_QUOTE_BOOK.get_mark_timevalue_source(ticker);
+        MarkValueUpdate { ticker: ticker.to_string(), mark_value, mark_time, source }
     }
+}
 
-    pub fn get_mark_timevalues<'a, I>(&'a self, tickers: I) -> impl Iterator<Item = (&'a str, MarkValue, MarkTime)> + 'a
-    where
-        I: IntoIterator<Item = &'a str> + 'a,
-    {
-        tickers
-            .into_iter()
-            .map(move |ticker| {
-                let (value, time) = self.get_mark_timevalue(ticker);
-                (ticker, value, time)
-            })
+fn is_stale(ticker: &str, now: DateTime<Utc>) -> bool {
+    let (value, time, _source) = RQ_QUOTE_BOOK.get_mark_timevalue_source(ticker);
+    value.is_nan() || time < now - chrono::Duration::seconds(MARK_VALUE_STALE_AFTER_SEC)
+}
+
+fn normalize_tickers(tickers: &[String]) -> impl Iterator<Item = String> + '_ {
+    tickers.iter().map(|ticker| ticker.trim().to_uppercase()).filter(|ticker| !ticker.is_empty())
+}
+
+// The first tick is immediate: the seeding of every ticker. Then only the stale tickers are fetched (the liquid ones get fresh stream ticks anyway).
+async fn run_snapshot_refresher(client: YfClient, symbols: Vec<String>, update_sender: broadcast::Sender<MarkValueUpdate>) {
+    let mut refresh_interval = tokio::time::interval(MARK_VALUE_SNAPSHOT_REFRESH_INTERVAL);
+    refresh_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        refresh_interval.tick().await;
+        let now = Utc::now();
+        let stale_symbols: Vec<String> = symbols.iter().filter(|symbol| is_stale(symbol, now)).cloned().collect();
+        if stale_symbols.is_empty() {
+            continue;
+        }
+
+        let quotes = match QuotesBuilder::new(client.clone()).symbols(stale_symbols.clone()).fetch().await {
+            Ok(quotes) => quotes,
+            Err(err) => {
+                log::warn!("MarkValueCache snapshot: REST quotes of {} ticker(s) failed: {err}", stale_symbols.len());
+                continue;
+            }
+        };
+        let fetch_time = Utc::now();
+        let mut num_updated = 0;
+        for rest_quote in quotes.iter() {
+            let symbol = rest_quote.symbol.to_string();
+            let last_price = rest_quote.price.as_ref().map(yfinance_rs::core::conversions::money_to_f64).unwrap_or(f64::NAN);
+            if last_price.is_nan() {
+                continue;
+            }
+            let Some(mark_update) = RQ_MARK_VALUE_CACHE.lock_ignore_poison().apply_snapshot(&symbol, last_price, rest_quote.day_volume, fetch_time) else {
+                continue;
+            };
+            let _ = update_sender.send(mark_update);
+            num_updated += 1;
+        }
+        log::info!("MarkValueCache snapshot: {} of {} stale ticker(s) updated from REST quotes.", num_updated, stale_symbols.len());
+    }
+}
+
+async fn run_quote_stream_watchdog() {
+    let mut watchdog_interval = tokio::time::interval(QUOTE_STREAM_WATCHDOG_INTERVAL);
+    watchdog_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        watchdog_interval.tick().await;
+        RQ_MARK_VALUE_CACHE.lock_ignore_poison().check_feeds();
+    }
+}
+
+// ---------- QuoteStreamGuard ----------
+// A start_quote_stream() user that calls stop_quote_stream() when dropped. So a user that exits early (e.g. a closed websocket) cannot keep the stream running.
+pub struct QuoteStreamGuard {
+    _private: (),
+}
+
+impl QuoteStreamGuard {
+    pub fn acquire() -> QuoteStreamGuard {
+        RQ_MARK_VALUE_CACHE.lock_ignore_poison().start_quote_stream();
+        QuoteStreamGuard { _private: () }
     }
-}
\ No newline at end of file
+}
+
+impl Drop for QuoteStreamGuard {
+    fn drop(&mut self) {
+        RQ_MARK_VALUE_CACHE.lock_ignore_poison().stop_quote_stream();
+    }
+}
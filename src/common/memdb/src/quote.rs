@@ -0,0 +1,204 @@
+use chrono::{DateTime, Utc};
+
+use crate::mark_value_cache::{MarkSource, MarkTime, MarkValue};
+
+// The quote of a ticker, with a timestamp per field, because the fields come from different messages (and feeds): trades update 'last', quotes update bid/ask.
+// NaN: unknown (the repo convention for the missing prices). UNIX_EPOCH: never updated.
+// The YF stream and REST quotes give only last (and volume). Bid/ask come from feeds that have them (IB ticks, MarkValueCache::update_bid_ask()).
+#[derive(Debug, Clone, Copy)]
+pub struct Quote {
+    pub bid: f64,
+    pub bid_time: MarkTime,
+    pub ask: f64,
+    pub ask_time: MarkTime,
+    pub last: f64, // last trade price
+    pub last_time: MarkTime,
+    pub volume: Option<u64>, // cumulative daily volume
+    pub volume_time: MarkTime,
+    pub source: MarkSource, // of the last 'last' update
+    pub bid_ask_source: MarkSource, // of the last bid/ask update
+}
+
+impl Quote {
+    pub fn empty() -> Self {
+        Quote {
+            bid: f64::NAN,
+            bid_time: DateTime::<Utc>::UNIX_EPOCH,
+            ask: f64::NAN,
+            ask_time: DateTime::<Utc>::UNIX_EPOCH,
+            last: f64::NAN,
+            last_time: DateTime::<Utc>::UNIX_EPOCH,
+            volume: None,
+            volume_time: DateTime::<Utc>::UNIX_EPOCH,
+            source: MarkSource::None,
+            bid_ask_source: MarkSource::None,
+        }
+    }
+
+    // None if bid or ask is missing, or the book is crossed (ask < bid: one side is outdated)
+    pub fn spread(&self) -> Option<f64> {
+        if self.bid.is_nan() || self.ask.is_nan() || self.bid <= 0.0 || self.ask < self.bid {
+            return None;
+        }
+        Some(self.ask - self.bid)
+    }
+
+    // The spread only if both sides are within the recency window of the policy: an old side gives a wrong spread (and limit price)
+    pub fn fresh_spread(&self, policy: MarkPolicy, now: DateTime<Utc>) -> Option<f64> {
+        let min_time = now - chrono::Duration::seconds(policy.recency_window_sec());
+        if self.bid_time < min_time || self.ask_time < min_time {
+            return None;
+        }
+        self.spread()
+    }
+
+    // The time of the older side: the mid is only as fresh as that
+    pub fn mid(&self) -> Option<(MarkValue, MarkTime)> {
+        self.spread().map(|_| ((self.bid + self.ask) / 2.0, self.bid_time.min(self.ask_time)))
+    }
+
+    // The source is the source of the used field (last or bid/ask).
+    // A REST snapshot 'last' never wins over a mid: its time is the fetch time, so it would look like a recent trade, while the last trade can be hours old.
+    pub fn mark(&self, policy: MarkPolicy, now: DateTime<Utc>) -> (MarkValue, MarkTime, MarkSource) {
+        let last = if self.last.is_nan() { None } else { Some((self.last, self.last_time, self.source)) };
+        let mid = self.mid().map(|(mid, mid_time)| (mid, mid_time, self.bid_ask_source));
+        if let (MarkSource::Snapshot, Some(mid)) = (self.source, mid) {
+            return mid;
+        }
+        let mark = match policy {
+            MarkPolicy::Mid => mid.or(last),
+            MarkPolicy::Last => last.or(mid),
+            MarkPolicy::LastIfRecentElseMid { max_last_age_sec } => match last {
+                Some((_, last_time, _)) if last_time >= now - chrono::Duration::seconds(max_last_age_sec) => last,
+                _ => mid.or(last), // an old last trade is still better than nothing
+            },
+        };
+        mark.unwrap_or((f64::NAN, DateTime::<Utc>::UNIX_EPOCH, MarkSource::None))
+    }
+}
+
+// How the MarkValue is derived from the Quote. If the preferred field is missing, the other one is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkPolicy {
+    Mid,  // (bid + ask) / 2
+    Last, // last trade
+    LastIfRecentElseMid { max_last_age_sec: i64 }, // last trade, if it is not older than max_last_age_sec, otherwise mid. Illiquid tickers: the last trade can be hours old.
+}
+
+const MARK_POLICY_DEFAULT_RECENCY_SEC: i64 = 60;
+
+impl MarkPolicy {
+    pub const DEFAULT: MarkPolicy = MarkPolicy::LastIfRecentElseMid { max_last_age_sec: MARK_POLICY_DEFAULT_RECENCY_SEC };
+
+    // The max age of a field that counts as recent. Mid and Last have no window of their own: the default one.
+    pub fn recency_window_sec(&self) -> i64 {
+        match self {
+            MarkPolicy::LastIfRecentElseMid { max_last_age_sec } => *max_last_age_sec,
+            MarkPolicy::Mid | MarkPolicy::Last => MARK_POLICY_DEFAULT_RECENCY_SEC,
+        }
+    }
+
+    // "mid", "last", "last_if_recent" (60 sec) or "last_if_recent:<max_last_age_sec>"
+    pub fn parse(policy: &str) -> Option<MarkPolicy> {
+        match policy.trim().split_once(':') {
+            None if policy.trim() == "mid" => Some(MarkPolicy::Mid),
+            None if policy.trim() == "last" => Some(MarkPolicy::Last),
+            None if policy.trim() == "last_if_recent" => Some(MarkPolicy::DEFAULT),
+            Some(("last_if_recent", max_age)) => max_age.trim().parse::<i64>().ok().filter(|sec| *sec > 0).map(|max_last_age_sec| MarkPolicy::LastIfRecentElseMid { max_last_age_sec }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(sec: i64) -> MarkTime {
+        DateTime::<Utc>::from_timestamp(1_760_000_000 + sec, 0).unwrap()
+    }
+
+    fn quote(bid: f64, ask: f64, bid_ask_time: MarkTime, last: f64, last_time: MarkTime, source: MarkSource) -> Quote {
+        Quote { bid, bid_time: bid_ask_time, ask, ask_time: bid_ask_time, last, last_time, source, bid_ask_source: MarkSource::IbStream, ..Quote::empty() }
+    }
+
+    #[test]
+    fn mark_prefers_the_mid_over_a_snapshot_last() {
+        // The stream start seed: a snapshot 'last' fetched 1 sec ago, and a fresh IB bid/ask of an illiquid ticker
+        let q = quote(10.0, 10.5, t(95), 9.0, t(99), MarkSource::Snapshot);
+        for policy in [MarkPolicy::DEFAULT, MarkPolicy::Last, MarkPolicy::Mid] {
+            assert_eq!(q.mark(policy, t(100)), (10.25, t(95), MarkSource::IbStream), "{:?}", policy);
+        }
+        let no_bid_ask = quote(f64::NAN, f64::NAN, DateTime::<Utc>::UNIX_EPOCH, 9.0, t(99), MarkSource::Snapshot);
+        assert_eq!(no_bid_ask.mark(MarkPolicy::DEFAULT, t(100)), (9.0, t(99), MarkSource::Snapshot)); // better than nothing
+    }
+
+    #[test]
+    fn mark_by_policy() {
+        let now = t(100);
+        let none = (f64::NAN, DateTime::<Utc>::UNIX_EPOCH, MarkSource::None);
+        let (fresh_last, old_last) = ((11.0, t(90), MarkSource::Stream), (11.0, t(30), MarkSource::Stream)); // 10 and 70 sec old
+        let mid = (10.25, t(95), MarkSource::IbStream);
+        let cases = [
+            // (quote, policy, expected mark)
+            (quote(10.0, 10.5, t(95), 11.0, t(90), MarkSource::Stream), MarkPolicy::Mid, mid),
+            (quote(10.0, 10.5, t(95), 11.0, t(90), MarkSource::Stream), MarkPolicy::Last, fresh_last),
+            (quote(10.0, 10.5, t(95), 11.0, t(90), MarkSource::Stream), MarkPolicy::DEFAULT, fresh_last),
+            (quote(10.0, 10.5, t(95), 11.0, t(30), MarkSource::Stream), MarkPolicy::DEFAULT, mid), // the last trade is older than 60 sec
+            (quote(10.0, 10.5, t(95), 11.0, t(30), MarkSource::Stream), MarkPolicy::LastIfRecentElseMid { max_last_age_sec: 120 }, old_last),
+            (quote(f64::NAN, f64::NAN, t(95), 11.0, t(30), MarkSource::Stream), MarkPolicy::DEFAULT, old_last), // an old last is better than nothing
+            (quote(f64::NAN, f64::NAN, t(95), 11.0, t(90), MarkSource::Stream), MarkPolicy::Mid, fresh_last),
+            (quote(10.0, 10.5, t(95), f64::NAN, t(90), MarkSource::None), MarkPolicy::Last, mid),
+            (quote(10.5, 10.0, t(95), 11.0, t(30), MarkSource::Stream), MarkPolicy::Mid, old_last), // crossed book: no mid
+            (quote(0.0, 10.5, t(95), f64::NAN, t(30), MarkSource::None), MarkPolicy::Mid, none), // no bid: no mid
+            (Quote::empty(), MarkPolicy::DEFAULT, none),
+        ];
+        for (i, (q, policy, expected)) in cases.iter().enumerate() {
+            let (value, time, source) = q.mark(*policy, now);
+            let is_same_value = value == expected.0 || (value.is_nan() && expected.0.is_nan());
+            assert!(is_same_value && (time, source) == (expected.1, expected.2), "case {}: {:?} gave {:?}, expected {:?}", i, policy, (value, time, source), expected);
+        }
+    }
+
+    #[test]
+    fn fresh_spread_needs_both_sides_in_the_recency_window() {
+        let now = t(100);
+        let mut q = quote(10.0, 10.5, t(50), f64::NAN, t(0), MarkSource::None);
+        let cases = [
+            // (bid_time, ask_time, policy, expected spread)
+            (t(50), t(50), MarkPolicy::DEFAULT, Some(0.5)),
+            (t(40), t(40), MarkPolicy::DEFAULT, Some(0.5)), // exactly 60 sec old
+            (t(39), t(50), MarkPolicy::DEFAULT, None),      // the bid is older
+            (t(50), t(39), MarkPolicy::DEFAULT, None),      // the ask is older
+            (t(39), t(39), MarkPolicy::Mid, None),          // Mid and Last use the default window
+            (t(39), t(39), MarkPolicy::LastIfRecentElseMid { max_last_age_sec: 120 }, Some(0.5)),
+        ];
+        for (bid_time, ask_time, policy, expected) in cases {
+            (q.bid_time, q.ask_time) = (bid_time, ask_time);
+            assert_eq!(q.fresh_spread(policy, now), expected, "bid {}, ask {}, {:?}", bid_time, ask_time, policy);
+        }
+        let crossed = quote(10.5, 10.0, t(90), f64::NAN, t(0), MarkSource::None);
+        assert_eq!(crossed.fresh_spread(MarkPolicy::DEFAULT, now), None);
+    }
+
+    #[test]
+    fn mark_policy_parse() {
+        let cases = [
+            ("mid", Some(MarkPolicy::Mid)),
+            (" last ", Some(MarkPolicy::Last)),
+            ("last_if_recent", Some(MarkPolicy::DEFAULT)),
+            ("last_if_recent:30", Some(MarkPolicy::LastIfRecentElseMid { max_last_age_sec: 30 })),
+            ("last_if_recent: 300 ", Some(MarkPolicy::LastIfRecentElseMid { max_last_age_sec: 300 })),
+            ("last_if_recent:0", None),
+            ("last_if_recent:-5", None),
+            ("last_if_recent:abc", None),
+            ("last_if_recent:", None),
+            ("mid:30", None),
+            ("Mid", None),
+            ("", None),
+        ];
+        for (policy, expected) in cases {
+            assert_eq!(MarkPolicy::parse(policy), expected, "'{}'", policy);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::{collections::{BTreeSet, HashMap}, sync::{Arc, LazyLock}};
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+
+use crate::{mark_value_cache::{MarkSource, MarkTime, MarkValue}, quote::{MarkPolicy, Quote}, quote_stream_health::FeedHealth};
+
+// The lock-free read path of the MarkValueCache quotes. Readers (place_orders, the APIs, the websockets) never take the RQ_MARK_VALUE_CACHE Mutex,
+// so they don't wait for the tick merge task, and the tick merge task doesn't wait for them during the critical trading seconds.
+// An ArcSwap slot per ticker: a writer copies the Quote, changes it and swaps in the new Arc. A reader gets a whole Quote (bid, ask, last from the same moment), never a half-updated one.
+// The ticker => slot map is swapped (copy-on-write) only when the universe changes, so a tick touches only its own slot, not a snapshot of all the tickers.
+// Consistency is per ticker: reading 2 tickers can see a tick of the second that arrived after the first was read.
+// The writes are pub(crate): only MarkValueCache writes, from its &mut self methods. So the writers are serialized by the RQ_MARK_VALUE_CACHE Mutex, and no update is lost.
+// See quote_book_bench.rs (an #[ignore] test) for the benchmark against the Mutex<HashMap> design.
+
+pub static RQ_QUOTE_BOOK: LazyLock<QuoteBook> = LazyLock::new(|| QuoteBook::new());
+
+type QuoteSlot = ArcSwap<Quote>;
+
+pub struct QuoteBook {
+    slots: ArcSwap<HashMap<String, Arc<QuoteSlot>>>, // a slot for each ticker of the universe
+    mark_policy: ArcSwap<MarkPolicy>, // how the MarkValue is derived from the Quote
+    feeds_health: ArcSwap<Vec<FeedHealth>>, // a snapshot of the feed states, refreshed by the MarkValueCache watchdog (every second while the stream runs)
+}
+
+impl QuoteBook {
+    pub fn new() -> Self {
+        QuoteBook { slots: ArcSwap::from_pointee(HashMap::new()), mark_policy: ArcSwap::from_pointee(MarkPolicy::DEFAULT), feeds_health: ArcSwap::from_pointee(Vec::new()) }
+    }
+
+    // ---------- Writes (MarkValueCache only) ----------
+    pub(crate) fn clear(&self) {
+        self.slots.store(Arc::new(HashMap::new()));
+    }
+
+    // The slots (and quotes) of the remaining tickers are kept, so a tick that is being written to a kept slot is not lost.
+    pub(crate) fn set_tickers(&self, tickers: &BTreeSet<String>) {
+        let old_slots = self.slots.load();
+        let new_slots: HashMap<String, Arc<QuoteSlot>> = tickers.iter()
+            .map(|ticker| (ticker.clone(), old_slots.get(ticker).cloned().unwrap_or_else(|| Arc::new(ArcSwap::from_pointee(Quote::empty())))))
+            .collect();
+        self.slots.store(Arc::new(new_slots));
+    }
+
+    // None if the ticker is not in the universe
+    pub(crate) fn update<R>(&self, ticker: &str, update: impl FnOnce(&mut Quote) -> R) -> Option<R> {
+        let slots = self.slots.load();
+        let slot = slots.get(ticker)?;
+        let mut quote = **slot.load();
+        let result = update(&mut quote);
+        slot.store(Arc::new(quote));
+        Some(result)
+    }
+
+    // A ticker outside of the universe gets a slot (until the next universe change)
+    pub(crate) fn update_or_insert<R>(&self, ticker: &str, update: impl FnOnce(&mut Quote) -> R) -> R {
+        if !self.slots.load().contains_key(ticker) {
+            let mut new_slots = HashMap::clone(&self.slots.load());
+            new_slots.insert(ticker.to_string(), Arc::new(ArcSwap::from_pointee(Quote::empty())));
+            self.slots.store(Arc::new(new_slots));
+        }
+        self.update(ticker, update).expect("the slot was inserted")
+    }
+
+    pub(crate) fn set_feeds_health(&self, feeds_health: Vec<FeedHealth>) {
+        self.feeds_health.store(Arc::new(feeds_health));
+    }
+
+    pub fn set_mark_policy(&self, mark_policy: MarkPolicy) {
+        self.mark_policy.store(Arc::new(mark_policy));
+    }
+
+    // ---------- Reads (lock-free) ----------
+    pub fn get_mark_policy(&self) -> MarkPolicy {
+        **self.mark_policy.load()
+    }
+
+    // Empty if the stream has not been started
+    pub fn get_feeds_health(&self) -> Arc<Vec<FeedHealth>> {
+        self.feeds_health.load_full()
+    }
+
+    pub fn contains(&self, ticker: &str) -> bool {
+        self.slots.load().contains_key(ticker)
+    }
+
+    pub fn get_quote(&self, ticker: &str) -> Quote {
+        self.slots.load().get(ticker).map(|slot| **slot.load()).unwrap_or(Quote::empty())
+    }
+
+    // Sorted by ticker
+    pub fn get_quotes(&self) -> Vec<(String, Quote)> {
+        let mut quotes: Vec<(String, Quote)> = self.slots.load().iter().map(|(ticker, slot)| (ticker.clone(), **slot.load())).collect();
+        quotes.sort_by(|a, b| a.0.cmp(&b.0));
+        quotes
+    }
+
+    // The derived mark of the Quote, according to the mark_policy
+    pub fn get_mark_value(&self, ticker: &str) -> MarkValue {
+        self.get_mark_timevalue_source(ticker).0
+    }
+
+    pub fn get_mark_timevalue(&self, ticker: &str) -> (MarkValue, MarkTime) {
+        let (value, time, _source) = self.get_mark_timevalue_source(ticker);
+        (value, time)
+    }
+
+    pub fn get_mark_timevalue_source(&self, ticker: &str) -> (MarkValue, MarkTime, MarkSource) {
+        match self.slots.load().get(ticker) {
+            Some(slot) => slot.load().mark(self.get_mark_policy(), Utc::now()),
+            None => (f64::NAN, DateTime::<Utc>::UNIX_EPOCH, MarkSource::None),
+        }
+    }
+
+    pub fn get_mark_values<'a, I>(&'a self, tickers: I) -> impl Iterator<Item = (&'a str, MarkValue)> + 'a
+    where
+        I: IntoIterator<Item = &'a str> + 'a,
+    {
+        tickers
+            .into_iter()
+            .map(move |ticker| (ticker, self.get_mark_value(ticker)))
+    }
+
+    pub fn get_mark_timevalues<'a, I>(&'a self, tickers: I) -> impl Iterator<Item = (&'a str, MarkValue, MarkTime)> + 'a
+    where
+        I: IntoIterator<Item = &'a str> + 'a,
+    {
+        tickers
+            .into_iter()
+            .map(move |ticker| {
+                let (value, time) = self.get_mark_timevalue(ticker);
+                (ticker, value, time)
+            })
+    }
+}
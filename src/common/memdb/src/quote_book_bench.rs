@@ -0,0 +1,225 @@
+use std::{collections::{BTreeSet, HashMap}, fmt::Write, sync::{atomic::{AtomicBool, Ordering}, Mutex}, thread, time::{Duration, Instant}};
+use chrono::Utc;
+
+use rqcommon::rqhelper::MutexExt;
+use crate::{mark_value_cache::MarkSource, quote::{MarkPolicy, Quote}, quote_book::QuoteBook};
+
+// Benchmark of the MarkValue read path under a simulated tick stream: the old Mutex<HashMap<String, Quote>> vs the lock-free QuoteBook.
+// 1 writer thread applies trade ticks at a fixed rate, round robin over the tickers (as the tick merge task). The reader threads read the marks of
+// READ_BATCH_TICKERS tickers in a loop (as place_orders), as fast as they can: the worst case of the contention. Local instances, the RQ_ statics are not touched.
+// The latencies are recorded in a log-linear histogram, so a long run needs no memory per sample.
+// Test-only (not in the memdb library). Run: cargo test -p memdb --release quote_book_bench -- --ignored --nocapture
+
+const READ_BATCH_TICKERS: usize = 20; // the number of orders of a FastRunner run
+const WRITER_PACING_SLEEP: Duration = Duration::from_micros(100);
+
+struct QuoteBookBenchParams {
+    num_tickers: usize,
+    ticks_per_sec: u64,
+    num_readers: usize,
+    duration: Duration,
+}
+
+impl QuoteBookBenchParams {
+    const DEFAULT: QuoteBookBenchParams = QuoteBookBenchParams { num_tickers: 500, ticks_per_sec: 20_000, num_readers: 4, duration: Duration::from_secs(5) };
+}
+
+// The 2 designs behind the same operations
+trait QuoteStore: Sync {
+    fn apply_trade(&self, ticker: &str, price: f64);
+    fn read_marks(&self, tickers: &[String]) -> f64; // the sum, so the reads are not optimized away
+}
+
+struct MutexQuoteStore {
+    quotes: Mutex<HashMap<String, Quote>>,
+}
+
+impl QuoteStore for MutexQuoteStore {
+    fn apply_trade(&self, ticker: &str, price: f64) {
+        if let Some(quote) = self.quotes.lock_ignore_poison().get_mut(ticker) {
+            quote.last = price;
+            quote.last_time = Utc::now();
+            quote.source = MarkSource::Stream;
+        }
+    }
+
+    fn read_marks(&self, tickers: &[String]) -> f64 {
+        let quotes = self.quotes.lock_ignore_poison(); // 1 lock for the batch, as place_orders did
+        let now = Utc::now();
+        tickers.iter().filter_map(|ticker| quotes.get(ticker)).map(|quote| quote.mark(MarkPolicy::DEFAULT, now).0).sum()
+    }
+}
+
+impl QuoteStore for QuoteBook {
+    fn apply_trade(&self, ticker: &str, price: f64) {
+        self.update(ticker, |quote| {
+            quote.last = price;
+            quote.last_time = Utc::now();
+            quote.source = MarkSource::Stream;
+        });
+    }
+
+    fn read_marks(&self, tickers: &[String]) -> f64 {
+        let (mark_policy, now) = (self.get_mark_policy(), Utc::now()); // as place_orders
+        tickers.iter().map(|ticker| self.get_quote(ticker).mark(mark_policy, now).0).sum()
+    }
+}
+
+// Returns the report: per design the achieved tick rate, the writer's tick latency, and the readers' batch latency and throughput
+fn benchmark_quote_book(params: &QuoteBookBenchParams) -> String {
+    let tickers: Vec<String> = (0..params.num_tickers).map(|i| format!("T{:04}", i)).collect();
+    let mut report = format!("QuoteBook benchmark: {} tickers, {} ticks/sec, {} readers x {} tickers/read, {:?} per design\n", params.num_tickers, params.ticks_per_sec, params.num_readers, READ_BATCH_TICKERS, params.duration);
+    let logical_cpus = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    if logical_cpus <= params.num_readers {
+        writeln!(report, "  Only {} logical CPU(s) for {} threads: the tail latencies include the OS time slices of the preempted threads.", logical_cpus, params.num_readers + 1).ok();
+    }
+
+    let mutex_store = MutexQuoteStore { quotes: Mutex::new(tickers.iter().map(|ticker| (ticker.clone(), Quote::empty())).collect()) };
+    run_design(&mut report, "Mutex<HashMap>", &mutex_store, &tickers, params);
+
+    let quote_book = QuoteBook::new();
+    quote_book.set_tickers(&tickers.iter().cloned().collect::<BTreeSet<String>>());
+    run_design(&mut report, "QuoteBook (ArcSwap)", &quote_book, &tickers, params);
+    report
+}
+
+fn run_design(report: &mut String, name: &str, store: &dyn QuoteStore, tickers: &[String], params: &QuoteBookBenchParams) {
+    let is_running = AtomicBool::new(true);
+    let (writer_result, reader_results) = thread::scope(|scope| {
+        let writer = scope.spawn(|| run_writer(store, tickers, params, &is_running));
+        let readers: Vec<_> = (0..params.num_readers).map(|reader_idx| scope.spawn({
+            let is_running = &is_running;
+            move || run_reader(store, tickers, reader_idx, is_running)
+        })).collect();
+        let writer_result = writer.join().expect("writer thread panicked"); // the writer stops the readers after the duration
+        (writer_result, readers.into_iter().map(|reader| reader.join().expect("reader thread panicked")).collect::<Vec<_>>())
+    });
+
+    let (num_ticks, elapsed, tick_latencies) = writer_result;
+    let mut read_latencies = LatencyHistogram::new();
+    for reader_latencies in reader_results.iter() {
+        read_latencies.merge(reader_latencies);
+    }
+    let secs = elapsed.as_secs_f64();
+    writeln!(report, "{}:", name).ok();
+    writeln!(report, "  writer: {:.0} ticks/sec achieved, tick latency p50: {}, p99: {}, p99.9: {}, max: {}",
+        num_ticks as f64 / secs, format_ns(tick_latencies.percentile(0.50)), format_ns(tick_latencies.percentile(0.99)), format_ns(tick_latencies.percentile(0.999)), format_ns(tick_latencies.max_ns)).ok();
+    writeln!(report, "  readers: {:.0} reads/sec, read latency p50: {}, p99: {}, p99.9: {}, max: {}",
+        read_latencies.count as f64 / secs, format_ns(read_latencies.percentile(0.50)), format_ns(read_latencies.percentile(0.99)), format_ns(read_latencies.percentile(0.999)), format_ns(read_latencies.max_ns)).ok();
+}
+
+// Paced by the elapsed time: it catches up after a slow tick (e.g. waiting for the lock), as a real feed's backlog would
+fn run_writer(store: &dyn QuoteStore, tickers: &[String], params: &QuoteBookBenchParams, is_running: &AtomicBool) -> (u64, Duration, LatencyHistogram) {
+    let mut tick_latencies = LatencyHistogram::new();
+    let mut num_ticks: u64 = 0;
+    let start = Instant::now();
+    while start.elapsed() < params.duration {
+        let target_ticks = (start.elapsed().as_secs_f64() * params.ticks_per_sec as f64) as u64;
+        while num_ticks < target_ticks {
+            let ticker = &tickers[num_ticks as usize % tickers.len()];
+            let price = 100.0 + (num_ticks % 100) as f64 * 0.01;
+            let tick_start = Instant::now();
+            store.apply_trade(ticker, price);
+            tick_latencies.record(tick_start.elapsed());
+            num_ticks += 1;
+        }
+        thread::sleep(WRITER_PACING_SLEEP);
+    }
+    is_running.store(false, Ordering::Relaxed);
+    (num_ticks, start.elapsed(), tick_latencies)
+}
+
+fn run_reader(store: &dyn QuoteStore, tickers: &[String], reader_idx: usize, is_running: &AtomicBool) -> LatencyHistogram {
+    let mut read_latencies = LatencyHistogram::new();
+    let mut checksum = 0.0;
+    let mut batch_start_idx = reader_idx * READ_BATCH_TICKERS;
+    while is_running.load(Ordering::Relaxed) {
+        let batch_start_idx_wrapped = batch_start_idx % tickers.len().saturating_sub(READ_BATCH_TICKERS).max(1);
+        let batch = &tickers[batch_start_idx_wrapped..(batch_start_idx_wrapped + READ_BATCH_TICKERS).min(tickers.len())];
+        let read_start = Instant::now();
+        checksum += store.read_marks(batch);
+        read_latencies.record(read_start.elapsed());
+        batch_start_idx += READ_BATCH_TICKERS;
+    }
+    std::hint::black_box(checksum);
+    read_latencies
+}
+
+// ---------- LatencyHistogram ----------
+// Log-linear buckets: 8 sub-buckets per power of 2 nanoseconds, so a percentile is within 12.5% (the lower bound of its bucket).
+const LATENCY_SUB_BUCKETS: usize = 8;
+
+struct LatencyHistogram {
+    buckets: Vec<u64>,
+    count: u64,
+    max_ns: u64,
+}
+
+impl LatencyHistogram {
+    fn new() -> Self {
+        LatencyHistogram { buckets: vec![0; 64 * LATENCY_SUB_BUCKETS], count: 0, max_ns: 0 }
+    }
+
+    fn bucket_index(ns: u64) -> usize {
+        if ns < LATENCY_SUB_BUCKETS as u64 {
+            return ns as usize;
+        }
+        let log2 = 63 - ns.leading_zeros() as usize; // >= 3
+        let sub_bucket = ((ns >> (log2 - 3)) & (LATENCY_SUB_BUCKETS as u64 - 1)) as usize;
+        (log2 - 2) * LATENCY_SUB_BUCKETS + sub_bucket
+    }
+
+    fn bucket_lower_bound(index: usize) -> u64 {
+        if index < LATENCY_SUB_BUCKETS {
+            return index as u64;
+        }
+        let log2 = index / LATENCY_SUB_BUCKETS + 2;
+        let sub_bucket = (index % LATENCY_SUB_BUCKETS) as u64;
+        (1u64 << log2) + (sub_bucket << (log2 - 3))
+    }
+
+    fn record(&mut self, latency: Duration) {
+        let ns = latency.as_nanos().min(u64::MAX as u128) as u64;
+        self.buckets[Self::bucket_index(ns)] += 1;
+        self.count += 1;
+        self.max_ns = self.max_ns.max(ns);
+    }
+
+    fn merge(&mut self, other: &LatencyHistogram) {
+        for (bucket, other_bucket) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += other_bucket;
+        }
+        self.count += other.count;
+        self.max_ns = self.max_ns.max(other.max_ns);
+    }
+
+    fn percentile(&self, quantile: f64) -> u64 {
+        let rank = ((self.count as f64 * quantile).ceil() as u64).max(1);
+        let mut cumulative = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket;
+            if cumulative >= rank {
+                return Self::bucket_lower_bound(index);
+            }
+        }
+        self.max_ns
+    }
+}
+
+fn format_ns(ns: u64) -> String {
+    match ns {
+        0..1_000 => format!("{}ns", ns),
+        1_000..1_000_000 => format!("{:.1}µs", ns as f64 / 1_000.0),
+        _ => format!("{:.2}ms", ns as f64 / 1_000_000.0),
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "benchmark: CPU-bound threads for ~10 sec"]
+    fn quote_book_bench() {
+        println!("{}", benchmark_quote_book(&QuoteBookBenchParams::DEFAULT));
+    }
+}
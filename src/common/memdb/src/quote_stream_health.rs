@@ -0,0 +1,280 @@
+use chrono::{Duration, Utc};
+use serde::Serialize;
+
+use crate::mark_value_cache::{MarkTime, MarkValue};
+
+// The health of the MarkValueCache quote stream: for the diagnostics (API, /serverdiagnostics) and the pre-trade check of place_orders.
+// The watchdog of the cache (see MarkValueCache::check_feeds()) restarts the ended feeds with an exponential backoff, while the stream has users.
+
+const FEED_RESTART_BACKOFF_MIN: Duration = Duration::seconds(1);
+const FEED_RESTART_BACKOFF_MAX: Duration = Duration::seconds(60);
+const FEED_RESTART_BACKOFF_RESET_AFTER: Duration = Duration::seconds(60); // a feed that ran this long before it ended restarts with the min backoff again
+pub const QUOTE_STREAM_STALE_TICKER_SEC: i64 = 120; // a mark older than this is stale: the snapshots refresh every minute, so both the feeds and the REST refresh failed
+pub const QUOTE_STREAM_SILENT_AFTER_SEC: i64 = 60; // no tick from any feed. Normal outside of the market hours.
+
+// A NaN mark has no data: not stale, but missing
+pub fn is_mark_stale(mark_time: MarkTime, now: MarkTime) -> bool {
+    mark_time < now - Duration::seconds(QUOTE_STREAM_STALE_TICKER_SEC)
+}
+
+// ---------- FeedRestartState ----------
+#[derive(Debug, Clone)]
+pub struct FeedRestartState {
+    pub restart_count: u32, // since the stream (re)start
+    consecutive_failures: u32,
+    started_at: Option<MarkTime>, // None while the feed is down
+    pub next_restart_at: Option<MarkTime>,
+    pub last_error: Option<String>,
+}
+
+impl FeedRestartState {
+    pub fn new() -> Self {
+        FeedRestartState { restart_count: 0, consecutive_failures: 0, started_at: None, next_restart_at: None, last_error: None }
+    }
+
+    pub fn on_started(&mut self, now: MarkTime) {
+        self.started_at = Some(now);
+        self.next_restart_at = None;
+    }
+
+    // The feed ended, or its start failed. Returns the backoff until the next restart: 1, 2, 4 ... 60 sec.
+    pub fn on_failed(&mut self, now: MarkTime, error: String) -> Duration {
+        if self.started_at.is_some_and(|started_at| now - started_at >= FEED_RESTART_BACKOFF_RESET_AFTER) {
+            self.consecutive_failures = 0;
+        }
+        self.consecutive_failures += 1;
+        let backoff = (FEED_RESTART_BACKOFF_MIN * 2i32.saturating_pow(self.consecutive_failures.min(16) - 1)).min(FEED_RESTART_BACKOFF_MAX);
+        self.started_at = None;
+        self.next_restart_at = Some(now + backoff);
+        self.last_error = Some(error);
+        backoff
+    }
+
+    pub fn is_restart_due(&self, now: MarkTime) -> bool {
+        self.next_restart_at.is_some_and(|next_restart_at| now >= next_restart_at)
+    }
+}
+
+// ---------- TickRateCounter ----------
+// Per-second tick counts of the last minute, in a ring of 60 buckets: no memory per tick at any tick rate
+#[derive(Debug, Clone)]
+pub struct TickRateCounter {
+    counts: [u64; 60],
+    seconds: [i64; 60], // the unix second of each bucket's counts
+}
+
+impl TickRateCounter {
+    pub fn new() -> Self {
+        TickRateCounter { counts: [0; 60], seconds: [i64::MIN; 60] }
+    }
+
+    pub fn record(&mut self, time: MarkTime) {
+        let second = time.timestamp();
+        let idx = second.rem_euclid(60) as usize;
+        if second < self.seconds[idx] { // older than the bucket's minute
+            return;
+        }
+        if self.seconds[idx] != second {
+            self.seconds[idx] = second;
+            self.counts[idx] = 0;
+        }
+        self.counts[idx] += 1;
+    }
+
+    pub fn ticks_last_minute(&self, now: MarkTime) -> u64 {
+        let now_second = now.timestamp();
+        self.seconds.iter().zip(self.counts.iter()).filter(|(second, _)| **second > now_second - 60 && **second <= now_second).map(|(_, count)| count).sum()
+    }
+}
+
+// ---------- QuoteStreamHealth ----------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuoteStreamStatus {
+    Stopped,  // no user: the stream is not needed
+    Ok,
+    Degraded, // a feed is down (being restarted), or some tickers have no or stale marks
+    Down,     // no feed runs
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedHealth {
+    pub source: String,
+    pub is_running: bool,
+    pub restart_count: u32,
+    pub next_restart_at: Option<MarkTime>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QuoteStreamHealth {
+    pub status: QuoteStreamStatus,
+    pub users: u16,
+    pub last_tick_at: Option<MarkTime>, // the arrival of the last feed tick
+    pub is_silent: bool,                // no tick for QUOTE_STREAM_SILENT_AFTER_SEC (normal outside of the market hours)
+    pub ticks_last_minute: u64,
+    pub num_tickers: usize,
+    pub no_data_tickers: Vec<String>, // no mark at all (NaN)
+    pub stale_tickers: Vec<String>,   // the mark is older than QUOTE_STREAM_STALE_TICKER_SEC
+    pub feeds: Vec<FeedHealth>,
+}
+
+// Copied from the MarkValueCache under its lock (see get_quote_stream_counters())
+#[derive(Debug, Clone)]
+pub struct QuoteStreamCounters {
+    pub users: u16,
+    pub last_tick_at: Option<MarkTime>,
+    pub ticks_last_minute: u64,
+    pub feeds: Vec<FeedHealth>,
+}
+
+// e.g. "ib_stream (restart in 4s)", or "none"
+pub fn feeds_down_summary(feeds: &[FeedHealth], now: MarkTime) -> String {
+    let feeds_down: Vec<String> = feeds.iter().filter(|feed| !feed.is_running)
+        .map(|feed| match feed.next_restart_at {
+            Some(next_restart_at) => format!("{} (restart in {}s)", feed.source, (next_restart_at - now).num_seconds().max(0)),
+            None => feed.source.clone(),
+        })
+        .collect();
+    if feeds_down.is_empty() { "none".to_string() } else { feeds_down.join(", ") }
+}
+
+impl QuoteStreamHealth {
+    // Staleness per ticker: the marks from any source (feeds, snapshots, update_bid_ask()). The marks are of the QuoteBook tickers: the universe,
+    // and a ticker that only got an update_bid_ask() (until the next universe change).
+    pub fn new(counters: QuoteStreamCounters, marks: impl IntoIterator<Item = (String, MarkValue, MarkTime)>, now: MarkTime) -> Self {
+        let mut num_tickers = 0;
+        let mut no_data_tickers: Vec<String> = Vec::new();
+        let mut stale_tickers: Vec<String> = Vec::new();
+        for (ticker, mark_value, mark_time) in marks {
+            num_tickers += 1;
+            if mark_value.is_nan() {
+                no_data_tickers.push(ticker);
+            } else if is_mark_stale(mark_time, now) {
+                stale_tickers.push(ticker);
+            }
+        }
+        let feeds = counters.feeds;
+        let status = if counters.users == 0 {
+            QuoteStreamStatus::Stopped
+        } else if !feeds.iter().any(|feed| feed.is_running) {
+            QuoteStreamStatus::Down
+        } else if feeds.iter().any(|feed| !feed.is_running) || !no_data_tickers.is_empty() || !stale_tickers.is_empty() {
+            QuoteStreamStatus::Degraded
+        } else {
+            QuoteStreamStatus::Ok
+        };
+        QuoteStreamHealth {
+            status,
+            users: counters.users,
+            last_tick_at: counters.last_tick_at,
+            is_silent: counters.last_tick_at.is_none_or(|last_tick_at| last_tick_at < now - Duration::seconds(QUOTE_STREAM_SILENT_AFTER_SEC)),
+            ticks_last_minute: counters.ticks_last_minute,
+            num_tickers,
+            no_data_tickers,
+            stale_tickers,
+            feeds,
+        }
+    }
+
+    pub fn is_ticker_ok(&self, ticker: &str) -> bool {
+        !self.no_data_tickers.iter().chain(self.stale_tickers.iter()).any(|t| t == ticker)
+    }
+
+    // One line for the logs, e.g. "Degraded | 2 user(s) | last tick: 3s ago | 1250 ticks/min | no data: 1/120 | stale: 0/120 | feeds down: ib_stream (restart in 4s)"
+    pub fn summary(&self) -> String {
+        let now = Utc::now();
+        let last_tick = match self.last_tick_at {
+            Some(last_tick_at) => format!("{}s ago", (now - last_tick_at).num_seconds()),
+            None => "never".to_string(),
+        };
+        format!("{:?} | {} user(s) | last tick: {} | {} ticks/min | no data: {}/{} | stale: {}/{} | feeds down: {}", self.status, self.users, last_tick, self.ticks_last_minute,
+            self.no_data_tickers.len(), self.num_tickers, self.stale_tickers.len(), self.num_tickers, feeds_down_summary(&self.feeds, now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+
+    fn t(sec: i64) -> MarkTime {
+        DateTime::<Utc>::from_timestamp(1_760_000_000 + sec, 0).unwrap()
+    }
+
+    #[test]
+    fn feed_restart_backoff() {
+        let mut state = FeedRestartState::new();
+        let cases = [
+            // (started_at, failed_at, expected backoff sec)
+            (None, 0, 1), // the start failed
+            (None, 1, 2),
+            (Some(3), 5, 4), // ran 2 sec: the backoff keeps growing
+            (Some(9), 10, 8),
+            (Some(18), 20, 16),
+            (Some(36), 40, 32),
+            (Some(72), 80, 60), // capped
+            (Some(140), 150, 60),
+            (Some(210), 270, 1), // ran 60 sec: reset
+            (Some(271), 272, 2),
+        ];
+        for (started_at, failed_at, expected_backoff_sec) in cases {
+            if let Some(started_at) = started_at {
+                state.on_started(t(started_at));
+                assert!(!state.is_restart_due(t(started_at)));
+            }
+            let backoff = state.on_failed(t(failed_at), format!("failed at {}", failed_at));
+            assert_eq!(backoff, Duration::seconds(expected_backoff_sec), "failed at {}", failed_at);
+            assert_eq!(state.next_restart_at, Some(t(failed_at + expected_backoff_sec)));
+            assert!(!state.is_restart_due(t(failed_at + expected_backoff_sec - 1)) && state.is_restart_due(t(failed_at + expected_backoff_sec)));
+        }
+        assert_eq!(state.last_error.as_deref(), Some("failed at 272"));
+        assert_eq!(state.restart_count, 0); // counted by the watchdog at the restart
+    }
+
+    #[test]
+    fn tick_rate_counts_the_last_60_seconds() {
+        let mut counter = TickRateCounter::new();
+        assert_eq!(counter.ticks_last_minute(t(0)), 0);
+        for sec in 0..90 {
+            counter.record(t(sec));
+            counter.record(t(sec));
+        }
+        counter.record(t(20)); // older than its bucket's second (80): dropped
+        let cases = [
+            // (now, expected ticks: 2 per second)
+            (t(89), 120),
+            (t(100), 2 * 49), // 41..89 sec
+            (t(149), 0),
+        ];
+        for (now, expected) in cases {
+            assert_eq!(counter.ticks_last_minute(now), expected, "now: {}", now);
+        }
+    }
+
+    #[test]
+    fn quote_stream_health_status() {
+        let feed = |source: &str, is_running: bool| FeedHealth { source: source.to_string(), is_running, restart_count: 0, next_restart_at: None, last_error: None };
+        let counters = |users: u16, feeds: Vec<FeedHealth>| QuoteStreamCounters { users, last_tick_at: Some(t(95)), ticks_last_minute: 10, feeds };
+        let fresh_marks = || vec![("AAPL".to_string(), 100.0, t(90)), ("MSFT".to_string(), 200.0, t(80))];
+        let cases = [
+            // (counters, marks, expected status)
+            (counters(0, vec![feed("yf_stream", false)]), vec![], QuoteStreamStatus::Stopped),
+            (counters(1, vec![feed("yf_stream", true), feed("ib_stream", true)]), fresh_marks(), QuoteStreamStatus::Ok),
+            (counters(1, vec![feed("yf_stream", true), feed("ib_stream", false)]), fresh_marks(), QuoteStreamStatus::Degraded),
+            (counters(1, vec![feed("yf_stream", false)]), fresh_marks(), QuoteStreamStatus::Down),
+            (counters(1, vec![feed("yf_stream", true)]), vec![("AAPL".to_string(), f64::NAN, DateTime::<Utc>::UNIX_EPOCH)], QuoteStreamStatus::Degraded),
+            (counters(1, vec![feed("yf_stream", true)]), vec![("AAPL".to_string(), 100.0, t(100 - QUOTE_STREAM_STALE_TICKER_SEC - 1))], QuoteStreamStatus::Degraded),
+        ];
+        for (i, (counters, marks, expected)) in cases.into_iter().enumerate() {
+            assert_eq!(QuoteStreamHealth::new(counters, marks, t(100)).status, expected, "case {}", i);
+        }
+
+        let marks = vec![("AAPL".to_string(), 100.0, t(90)), ("PRE".to_string(), f64::NAN, DateTime::<Utc>::UNIX_EPOCH), ("VXX".to_string(), 30.0, t(-100))];
+        let health = QuoteStreamHealth::new(counters(2, vec![feed("yf_stream", true)]), marks, t(100));
+        assert_eq!((health.num_tickers, health.no_data_tickers, health.stale_tickers), (3, vec!["PRE".to_string()], vec!["VXX".to_string()]));
+        assert!(!health.is_silent);
+        assert!(QuoteStreamHealth::new(counters(2, vec![]), vec![], t(95 + QUOTE_STREAM_SILENT_AFTER_SEC + 1)).is_silent);
+    }
+}
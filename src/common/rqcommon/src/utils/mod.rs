@@ -3,4 +3,7 @@ pub mod runningenv;
 pub mod time;
 pub mod server_ip;
 pub mod rqemail;
-pub mod rqgsheets;
\ No newline at end of file
+pub mod rqgsheets;
+pub mod rqevent_hub;
+pub mod trading_calendar;
+pub mod rqschedule;
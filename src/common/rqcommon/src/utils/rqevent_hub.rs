@@ -0,0 +1,118 @@
+use std::{collections::{HashMap, VecDeque}, fmt, sync::{Arc, LazyLock, Mutex}};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+use crate::rqhelper::MutexExt;
+
+// Publish/subscribe hub for server-push events (RoboTrader, BrokersWatcher, FastRunner => the connected websocket clients).
+// Publishers don't know about websockets: RQ_EVENT_HUB.publish(RqEventTopic::Orders, "order_submitted", json!({...})). Publishing never blocks and never fails.
+// Subscribers get every event of every topic (tokio broadcast channel) and filter by their own topics.
+// The last RQ_EVENT_HISTORY_LEN events of each topic are kept, so a newly connected client gets a snapshot first (e.g. today's orders, the last gateway status).
+// A subscriber that is too slow (more than RQ_EVENT_CHANNEL_CAPACITY events behind) gets RecvError::Lagged, and should take a new snapshot.
+
+pub const RQ_EVENT_CHANNEL_CAPACITY: usize = 1024;
+pub const RQ_EVENT_HISTORY_LEN: usize = 200;
+
+// ---------- Global static variables ----------
+pub static RQ_EVENT_HUB: LazyLock<RqEventHub> = LazyLock::new(|| RqEventHub::new());
+
+// ---------- Class/struct definitions ----------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RqEventTopic {
+    FastRunner, // run started/ended, loop iteration status
+    Signals,    // detected trading signals (e.g. new SA PQP/AP transactions)
+    Orders,     // order submitted, order filled (executions)
+    Gateway,    // broker gateway connected/disconnected
+}
+
+impl RqEventTopic {
+    pub const ALL: [RqEventTopic; 4] = [RqEventTopic::FastRunner, RqEventTopic::Signals, RqEventTopic::Orders, RqEventTopic::Gateway];
+
+    pub fn parse(topic: &str) -> Option<RqEventTopic> {
+        match topic {
+            "fastrunner" => Some(RqEventTopic::FastRunner),
+            "signals" => Some(RqEventTopic::Signals),
+            "orders" => Some(RqEventTopic::Orders),
+            "gateway" => Some(RqEventTopic::Gateway),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RqEventTopic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RqEventTopic::FastRunner => "fastrunner",
+            RqEventTopic::Signals => "signals",
+            RqEventTopic::Orders => "orders",
+            RqEventTopic::Gateway => "gateway",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RqEvent {
+    pub id: u64, // increasing. Clients can drop the events they already got in the snapshot.
+    pub topic: RqEventTopic,
+    pub kind: String, // e.g. "order_submitted"
+    pub time: DateTime<Utc>,
+    pub data: Value,
+}
+
+struct RqEventHistory {
+    next_id: u64,
+    events: HashMap<RqEventTopic, VecDeque<Arc<RqEvent>>>,
+}
+
+pub struct RqEventHub {
+    sender: broadcast::Sender<Arc<RqEvent>>,
+    history: Mutex<RqEventHistory>,
+}
+
+impl RqEventHub {
+    fn new() -> Self {
+        let (sender, _) = broadcast::channel(RQ_EVENT_CHANNEL_CAPACITY); // the receiver is dropped: sending without subscribers is fine
+        RqEventHub { sender, history: Mutex::new(RqEventHistory { next_id: 1, events: HashMap::new() }) }
+    }
+
+    pub fn publish(&self, topic: RqEventTopic, kind: &str, data: Value) {
+        let mut history = self.history.lock_ignore_poison();
+        let event = Arc::new(RqEvent { id: history.next_id, topic, kind: kind.to_string(), time: Utc::now(), data });
+        history.next_id += 1;
+        let topic_events = history.events.entry(topic).or_default();
+        if topic_events.len() >= RQ_EVENT_HISTORY_LEN {
+            topic_events.pop_front();
+        }
+        topic_events.push_back(event.clone());
+        // Sent under the history lock, so subscribe_with_snapshot() cannot miss or duplicate an event. send() only fails if there is no subscriber.
+        let _ = self.sender.send(event);
+    }
+
+    // The snapshot (the kept events of the topics, in id order) and a receiver for the events after the snapshot.
+    pub fn subscribe_with_snapshot(&self, topics: &[RqEventTopic]) -> (Vec<Arc<RqEvent>>, broadcast::Receiver<Arc<RqEvent>>) {
+        let history = self.history.lock_ignore_poison();
+        let receiver = self.sender.subscribe();
+        (Self::snapshot_impl(&history, topics), receiver)
+    }
+
+    pub fn snapshot(&self, topics: &[RqEventTopic]) -> Vec<Arc<RqEvent>> {
+        Self::snapshot_impl(&self.history.lock_ignore_poison(), topics)
+    }
+
+    fn snapshot_impl(history: &RqEventHistory, topics: &[RqEventTopic]) -> Vec<Arc<RqEvent>> {
+        let mut events: Vec<Arc<RqEvent>> = topics.iter()
+            .filter_map(|topic| history.events.get(topic))
+            .flat_map(|topic_events| topic_events.iter().cloned())
+            .collect();
+        events.sort_by_key(|event| event.id);
+        events
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
@@ -6,6 +6,7 @@ macro_rules! log_and_println {
     ($($arg:tt)*) => {
         log::info!($($arg)*);
         println!($($arg)*);
+        let _ = $crate::utils::rqlog::RQ_LOG_CAPTURE.try_with(|sender| sender.send(format!($($arg)*))); // only if the current task captures (e.g. an admin action run from the web UI)
     };
 }
 
@@ -15,6 +16,13 @@ macro_rules! log_and_if_println {
         log::info!($($arg)*);
         if $is_print {
             println!($($arg)*);
+            let _ = $crate::utils::rqlog::RQ_LOG_CAPTURE.try_with(|sender| sender.send(format!($($arg)*)));
         }
     };
 }
+
+// The log_and_println!() lines of a task can be streamed somewhere else too (e.g. to the admin's browser), if the task runs inside RQ_LOG_CAPTURE.scope(sender, future).
+// Only that task is captured: tokio::spawn()-ed sub tasks are not, and plain println!() lines are not.
+tokio::task_local! {
+    pub static RQ_LOG_CAPTURE: tokio::sync::mpsc::UnboundedSender<String>;
+}
@@ -0,0 +1,241 @@
+use std::fmt;
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+
+use crate::utils::trading_calendar::is_nyse_trading_day;
+
+// Schedule expressions for the RqTasks, so a task declares when it runs instead of computing its trigger times by hand.
+// Syntax:
+//   "every <N>s|m|h"                          e.g. "every 10m": N after the previous trigger (the first one: N after the start)
+//   "<times> [<timezone>] [on <days>]"        e.g. "09:45:10, 11:59:10 ET on trading days"
+// times:    comma separated HH:MM or HH:MM:SS, local times in the timezone
+// timezone: ET (America/New_York), UTC (default) or any IANA name (e.g. Europe/Budapest)
+// days:     "every day" (default), "weekdays" (Mon-Fri), "trading days" (NYSE trading days, see trading_calendar.rs), "mon-fri", "mon,wed,fri", "sat"
+// DST: a local time that doesn't exist on the spring forward day (e.g. 02:30 in the US) triggers at the same offset after the gap (03:30).
+// A local time that happens twice on the fall back day (e.g. 01:30) triggers only once, at its first occurrence.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RqScheduleDays {
+    EveryDay,
+    Weekdays(Vec<Weekday>),
+    NyseTradingDays,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RqScheduleKind {
+    Interval(Duration),
+    DailyTimes { times: Vec<NaiveTime>, tz: Tz, days: RqScheduleDays }, // times are sorted
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RqSchedule {
+    expression: String, // as parsed. Display shows this.
+    kind: RqScheduleKind,
+}
+
+impl RqSchedule {
+    pub fn parse(expression: &str) -> Result<RqSchedule, String> {
+        let expression = expression.split_whitespace().collect::<Vec<_>>().join(" ");
+        let lower = expression.to_lowercase();
+        let kind = match lower.strip_prefix("every ") {
+            Some(interval_str) => RqScheduleKind::Interval(parse_interval(interval_str)?),
+            None => Self::parse_daily_times(&expression)?,
+        };
+        Ok(RqSchedule { expression, kind })
+    }
+
+    fn parse_daily_times(expression: &str) -> Result<RqScheduleKind, String> {
+        let (times_tz_str, days_str) = match expression.to_lowercase().find(" on ") {
+            Some(pos) => (&expression[..pos], Some(&expression[pos + 4..])),
+            None => (expression, None),
+        };
+        // The timezone is the last word, if it is not a time
+        let (times_str, tz) = match times_tz_str.rsplit_once(' ') {
+            Some((times_str, last_word)) if !last_word.contains(':') => (times_str, parse_timezone(last_word)?),
+            _ => (times_tz_str, Tz::UTC),
+        };
+        let mut times = times_str.split(',').map(|t| t.trim()).filter(|t| !t.is_empty())
+            .map(|t| NaiveTime::parse_from_str(t, "%H:%M:%S").or_else(|_| NaiveTime::parse_from_str(t, "%H:%M")).map_err(|_| format!("Invalid time '{}' in schedule '{}'. Expected HH:MM or HH:MM:SS", t, expression)))
+            .collect::<Result<Vec<NaiveTime>, String>>()?;
+        if times.is_empty() {
+            return Err(format!("No time in schedule '{}'", expression));
+        }
+        times.sort();
+        times.dedup();
+        let days = match days_str {
+            Some(days_str) => parse_days(days_str).map_err(|err| format!("{} in schedule '{}'", err, expression))?,
+            None => RqScheduleDays::EveryDay,
+        };
+        Ok(RqScheduleKind::DailyTimes { times, tz, days })
+    }
+
+    pub fn next_trigger_time(&self) -> DateTime<Utc> {
+        self.next_trigger_time_after(Utc::now())
+    }
+
+    // The first trigger time strictly after 'after'
+    pub fn next_trigger_time_after(&self, after: DateTime<Utc>) -> DateTime<Utc> {
+        match &self.kind {
+            RqScheduleKind::Interval(interval) => after + *interval,
+            RqScheduleKind::DailyTimes { times, tz, days } => {
+                let mut date = after.with_timezone(tz).date_naive();
+                for _ in 0..400 { // more than a year: any day filter finds a day in that
+                    if is_day_included(days, date) {
+                        if let Some(trigger_time) = times.iter().map(|t| resolve_local_time(tz, date.and_time(*t))).filter(|t| *t > after).min() {
+                            return trigger_time;
+                        }
+                    }
+                    date += Duration::days(1);
+                }
+                DateTime::<Utc>::MAX_UTC // unreachable with a parsed schedule
+            }
+        }
+    }
+}
+
+impl fmt::Display for RqSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+fn parse_interval(interval_str: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid interval 'every {}'. Expected e.g. 'every 30s', 'every 10m', 'every 2h'", interval_str);
+    let (num_str, unit) = interval_str.split_at(interval_str.len().saturating_sub(1));
+    let num: i64 = num_str.trim().parse().map_err(|_| invalid())?;
+    let interval = match unit {
+        "s" => Duration::seconds(num),
+        "m" => Duration::minutes(num),
+        "h" => Duration::hours(num),
+        _ => return Err(invalid()),
+    };
+    if interval <= Duration::zero() {
+        return Err(invalid());
+    }
+    Ok(interval)
+}
+
+fn parse_timezone(tz_str: &str) -> Result<Tz, String> {
+    match tz_str.to_uppercase().as_str() {
+        "ET" => Ok(chrono_tz::US::Eastern),
+        "UTC" => Ok(Tz::UTC),
+        _ => tz_str.parse::<Tz>().map_err(|_| format!("Unknown timezone '{}'. Use ET, UTC or an IANA name (e.g. Europe/Budapest)", tz_str)),
+    }
+}
+
+fn parse_days(days_str: &str) -> Result<RqScheduleDays, String> {
+    let days_str = days_str.trim().to_lowercase();
+    match days_str.as_str() {
+        "every day" | "all days" => return Ok(RqScheduleDays::EveryDay),
+        "weekdays" => return Ok(RqScheduleDays::Weekdays(vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri])),
+        "trading days" => return Ok(RqScheduleDays::NyseTradingDays),
+        _ => {}
+    }
+    let mut weekdays: Vec<Weekday> = Vec::new();
+    for part in days_str.split(',').map(|p| p.trim()) {
+        match part.split_once('-') {
+            Some((first, last)) => { // range, e.g. mon-fri. It can wrap around: sat-mon
+                let (first, last) = (parse_weekday(first)?, parse_weekday(last)?);
+                let mut day = first;
+                weekdays.push(day);
+                while day != last {
+                    day = day.succ();
+                    weekdays.push(day);
+                }
+            }
+            None => weekdays.push(parse_weekday(part)?),
+        }
+    }
+    Ok(RqScheduleDays::Weekdays(weekdays))
+}
+
+fn parse_weekday(day_str: &str) -> Result<Weekday, String> {
+    day_str.trim().parse::<Weekday>().map_err(|_| format!("Unknown day '{}'. Expected every day, weekdays, trading days, or weekday names like mon-fri, sat", day_str))
+}
+
+fn is_day_included(days: &RqScheduleDays, date: NaiveDate) -> bool {
+    match days {
+        RqScheduleDays::EveryDay => true,
+        RqScheduleDays::Weekdays(weekdays) => weekdays.contains(&date.weekday()),
+        RqScheduleDays::NyseTradingDays => is_nyse_trading_day(date),
+    }
+}
+
+// Local time => UTC. See the DST notes at the top.
+fn resolve_local_time(tz: &Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(dt) => dt.to_utc(),
+        LocalResult::Ambiguous(earliest, _) => earliest.to_utc(),
+        LocalResult::None => { // in the spring forward gap: use the UTC offset before the gap. E.g. 02:30 EST (UTC-5) = 07:30 UTC = 03:30 EDT
+            let offset_before_gap = tz.offset_from_utc_datetime(&(local - Duration::days(1))).fix(); // timezones don't change their offset twice a day
+            (local - Duration::seconds(offset_before_gap.local_minus_utc() as i64)).and_utc()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32, s: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, s).unwrap()
+    }
+
+    fn next_after(expression: &str, after: DateTime<Utc>) -> DateTime<Utc> {
+        RqSchedule::parse(expression).unwrap().next_trigger_time_after(after)
+    }
+
+    // 2025 US DST changes: spring forward on Mar 9 (02:00 EST => 03:00 EDT), fall back on Nov 2 (02:00 EDT => 01:00 EST)
+
+    #[test]
+    fn spring_forward_gap_time_triggers_after_the_gap() {
+        let local = NaiveDate::from_ymd_opt(2025, 3, 9).unwrap().and_hms_opt(2, 30, 0).unwrap();
+        assert_eq!(resolve_local_time(&chrono_tz::US::Eastern, local), utc(2025, 3, 9, 7, 30, 0)); // 03:30 EDT
+        assert_eq!(next_after("02:30 ET", utc(2025, 3, 9, 5, 0, 0)), utc(2025, 3, 9, 7, 30, 0));
+        assert_eq!(next_after("02:30 ET", utc(2025, 3, 9, 7, 30, 0)), utc(2025, 3, 10, 6, 30, 0)); // the next day: 02:30 EDT
+    }
+
+    #[test]
+    fn ambiguous_fall_back_time_triggers_once_at_the_earliest() {
+        let local = NaiveDate::from_ymd_opt(2025, 11, 2).unwrap().and_hms_opt(1, 30, 0).unwrap();
+        assert_eq!(resolve_local_time(&chrono_tz::US::Eastern, local), utc(2025, 11, 2, 5, 30, 0)); // 01:30 EDT, not 01:30 EST
+        assert_eq!(next_after("01:30 ET", utc(2025, 11, 2, 4, 0, 0)), utc(2025, 11, 2, 5, 30, 0));
+        assert_eq!(next_after("01:30 ET", utc(2025, 11, 2, 5, 30, 0)), utc(2025, 11, 3, 6, 30, 0)); // not again at 01:30 EST (06:30 UTC)
+    }
+
+    #[test]
+    fn next_trigger_time_across_dst_changes() {
+        assert_eq!(next_after("09:30 ET", utc(2025, 3, 8, 15, 0, 0)), utc(2025, 3, 9, 13, 30, 0)); // EST => EDT: 1 hour earlier in UTC
+        assert_eq!(next_after("09:30 ET", utc(2025, 11, 1, 14, 0, 0)), utc(2025, 11, 2, 14, 30, 0)); // EDT => EST: 1 hour later in UTC
+        assert_eq!(next_after("14:00 UTC", utc(2025, 3, 8, 15, 0, 0)), utc(2025, 3, 9, 14, 0, 0)); // UTC has no DST
+    }
+
+    #[test]
+    fn next_trigger_time_skips_weekends_and_holidays() {
+        let schedule = "09:45:10, 11:59:10 ET on trading days";
+        assert_eq!(next_after(schedule, utc(2025, 3, 14, 14, 0, 0)), utc(2025, 3, 14, 15, 59, 10)); // the second time of the same day
+        assert_eq!(next_after(schedule, utc(2025, 3, 14, 20, 0, 0)), utc(2025, 3, 17, 13, 45, 10)); // Friday => Monday
+        assert_eq!(next_after(schedule, utc(2025, 4, 17, 20, 0, 0)), utc(2025, 4, 21, 13, 45, 10)); // Thursday => over Good Friday and the weekend
+        assert_eq!(next_after("10:00 ET on weekdays", utc(2025, 4, 17, 20, 0, 0)), utc(2025, 4, 18, 14, 0, 0)); // weekdays include the holidays
+        assert_eq!(next_after("10:00 UTC on sat-sun", utc(2025, 4, 17, 20, 0, 0)), utc(2025, 4, 19, 10, 0, 0));
+        assert_eq!(next_after("every 10m", utc(2025, 4, 17, 20, 0, 0)), utc(2025, 4, 17, 20, 10, 0));
+    }
+
+    #[test]
+    fn parse_valid_expressions() {
+        let schedule = RqSchedule::parse("  11:59:10,09:45:10   ET on trading days").unwrap();
+        assert_eq!(schedule.to_string(), "11:59:10,09:45:10 ET on trading days");
+        let RqScheduleKind::DailyTimes { times, tz, days } = &schedule.kind else { panic!("not a daily times schedule") };
+        assert_eq!(times, &vec![NaiveTime::from_hms_opt(9, 45, 10).unwrap(), NaiveTime::from_hms_opt(11, 59, 10).unwrap()]); // sorted
+        assert_eq!((*tz, days), (chrono_tz::US::Eastern, &RqScheduleDays::NyseTradingDays));
+        assert_eq!(RqSchedule::parse("every 2h").unwrap().kind, RqScheduleKind::Interval(Duration::hours(2)));
+        assert!(RqSchedule::parse("09:30 Europe/Budapest on mon,wed,fri").is_ok());
+    }
+
+    #[test]
+    fn parse_errors() {
+        for expression in ["", "every 0m", "every -5s", "every 10x", "every m", "every 1.5h", "25:00 ET", "9.30 ET", "ET", "09:30 Mars/Phobos", "09:30 ET on funday", "09:30 ET on mon-xyz"] {
+            assert!(RqSchedule::parse(expression).is_err(), "'{}' should not parse", expression);
+        }
+    }
+}
@@ -1,16 +1,144 @@
-use std::{collections::HashMap, env, fs,};
+use std::{collections::HashMap, env, fs, sync::OnceLock};
 use log;
 
+use crate::rqhelper::RqError;
+
 pub type RqCoreConfig = HashMap<String, String>;
+
+// ---------- Running environment overrides (CLI args and env variables) ----------
+// Machine specific settings are looked up in built-in tables (USERDOMAIN => folder, client ID). That only works for the known developer machines.
+// A new developer or a Linux container can override them without code changes. Precedence: CLI arg > env variable > built-in table.
+// E.g. "cargo run -- --config-dir=/home/rquser/RQ/sensitive_data/ --ib-client-id=217 --https-port=9443 --enabled-tasks=FastRunnerPqpTask --simulation-only"
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunningEnvOverrides {
+    pub config_dir: Option<String>,         // --config-dir, RQCORE_CONFIG_DIR
+    pub ib_client_id: Option<i32>,          // --ib-client-id, RQCORE_IB_CLIENT_ID
+    pub http_port: Option<u16>,             // --http-port, RQCORE_HTTP_PORT
+    pub https_port: Option<u16>,            // --https-port, RQCORE_HTTPS_PORT
+    pub enabled_tasks: Option<Vec<String>>, // --enabled-tasks, RQCORE_ENABLED_TASKS (comma separated task names). None: use the built-in machine check.
+    pub simulation_only: bool,              // --simulation-only, RQCORE_SIMULATION_ONLY. If true, no real orders are sent to the brokers.
+    pub production: Option<bool>,           // --production=<true|false>, RQCORE_PRODUCTION. None: Linux is production (the live server), Windows is development.
+}
+
+pub static RUNNING_ENV_OVERRIDES: OnceLock<RunningEnvOverrides> = OnceLock::new();
+
+pub const RUNNING_ENV_OVERRIDES_USAGE: &str = "Usage: rqcoresrv [--config-dir=<path>] [--ib-client-id=<int>] [--http-port=<port>] [--https-port=<port>] [--enabled-tasks=<Task1,Task2>] [--simulation-only] [--production[=<true|false>]]\n\
+    The same settings can be given as env variables: RQCORE_CONFIG_DIR, RQCORE_IB_CLIENT_ID, RQCORE_HTTP_PORT, RQCORE_HTTPS_PORT, RQCORE_ENABLED_TASKS, RQCORE_SIMULATION_ONLY=true, RQCORE_PRODUCTION";
+
+impl RunningEnvOverrides {
+    pub fn from_env_vars() -> Result<Self, RqError> {
+        let mut overrides = RunningEnvOverrides::default();
+        for (key, arg_name) in [
+            ("RQCORE_CONFIG_DIR", "config-dir"),
+            ("RQCORE_IB_CLIENT_ID", "ib-client-id"),
+            ("RQCORE_HTTP_PORT", "http-port"),
+            ("RQCORE_HTTPS_PORT", "https-port"),
+            ("RQCORE_ENABLED_TASKS", "enabled-tasks"),
+            ("RQCORE_SIMULATION_ONLY", "simulation-only"),
+            ("RQCORE_PRODUCTION", "production"),
+        ] {
+            if let Ok(value) = env::var(key) {
+                overrides.apply(arg_name, value.trim())?;
+            }
+        }
+        Ok(overrides)
+    }
+
+    // CLI args override the env variables. Both "--key=value" and "--key value" forms are accepted.
+    pub fn from_env_vars_and_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, RqError> {
+        let mut overrides = Self::from_env_vars()?;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(arg_body) = arg.strip_prefix("--") else {
+                return Err(RqError::ArgumentInvalid(format!("Unexpected argument '{}'", arg)));
+            };
+            let (arg_name, value) = match arg_body.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None if arg_body == "simulation-only" || arg_body == "production" => (arg_body.to_string(), "true".to_string()), // flags without value
+                None => match args.next() {
+                    Some(value) => (arg_body.to_string(), value),
+                    None => return Err(RqError::ArgumentInvalid(format!("Missing value for argument '{}'", arg))),
+                },
+            };
+            overrides.apply(&arg_name, value.trim())?;
+        }
+        Ok(overrides)
+    }
+
+    fn apply(&mut self, arg_name: &str, value: &str) -> Result<(), RqError> {
+        match arg_name {
+            "config-dir" => {
+                if value.is_empty() {
+                    return Err(RqError::ArgumentInvalid("config-dir is empty".to_string()));
+                }
+                // The rest of the code concatenates file names to this folder, so assure the trailing separator
+                self.config_dir = Some(if value.ends_with('/') || value.ends_with('\\') { value.to_string() } else { [value, "/"].concat() });
+            }
+            "ib-client-id" => self.ib_client_id = Some(value.parse::<i32>().map_err(|e| RqError::ArgumentInvalid(format!("ib-client-id '{}': {}", value, e)))?),
+            "http-port" => self.http_port = Some(value.parse::<u16>().map_err(|e| RqError::ArgumentInvalid(format!("http-port '{}': {}", value, e)))?),
+            "https-port" => self.https_port = Some(value.parse::<u16>().map_err(|e| RqError::ArgumentInvalid(format!("https-port '{}': {}", value, e)))?),
+            "enabled-tasks" => {
+                self.enabled_tasks = Some(value.split(',').map(|task| task.trim()).filter(|task| !task.is_empty()).map(|task| task.to_string()).collect());
+            }
+            "simulation-only" => self.simulation_only = parse_bool_arg(arg_name, value)?,
+            "production" => self.production = Some(parse_bool_arg(arg_name, value)?),
+            _ => return Err(RqError::ArgumentInvalid(format!("Unknown argument '--{}'", arg_name))),
+        }
+        Ok(())
+    }
+
+    pub fn is_production(&self) -> bool {
+        self.production.unwrap_or(env::consts::OS == "linux")
+    }
+
+    pub fn is_task_enabled(&self, task_name: &str) -> Option<bool> { // None if not overridden, so the caller falls back to its built-in rule
+        self.enabled_tasks.as_ref().map(|tasks| tasks.iter().any(|task| task.eq_ignore_ascii_case(task_name)))
+    }
+}
+
+fn parse_bool_arg(arg_name: &str, value: &str) -> Result<bool, RqError> {
+    match value.to_ascii_lowercase().as_str() {
+        "" | "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(RqError::ArgumentInvalid(format!("{} '{}': expected true or false", arg_name, value))),
+    }
+}
+
+// Call it once at startup with the CLI args, before anything calls get_running_env_overrides() (that sets them from the env variables only).
+// Err if they were already set to different values: the args would be silently ignored. A repeated call with the same args is Ok.
+pub fn init_running_env_overrides<I: IntoIterator<Item = String>>(args: I) -> Result<&'static RunningEnvOverrides, RqError> {
+    let overrides = RunningEnvOverrides::from_env_vars_and_args(args)?;
+    let running_overrides = RUNNING_ENV_OVERRIDES.get_or_init(|| overrides.clone());
+    if *running_overrides != overrides {
+        log::error!("RunningEnvOverrides already set to {:?}, the args are ignored: {:?}", running_overrides, overrides);
+        return Err(RqError::Config(format!("RunningEnvOverrides already set to different values, the args are ignored: {:?}", overrides)));
+    }
+    Ok(running_overrides)
+}
+
+pub fn get_running_env_overrides() -> &'static RunningEnvOverrides {
+    RUNNING_ENV_OVERRIDES.get_or_init(|| {
+        RunningEnvOverrides::from_env_vars().unwrap_or_else(|err| {
+            log::error!("RunningEnvOverrides env variables ignored: {}", err);
+            RunningEnvOverrides::default()
+        })
+    })
+}
+
 /// Returns the path to the sensitive configuration folder based on the current OS and user.
 /// 
+/// The --config-dir CLI argument or RQCORE_CONFIG_DIR env variable takes precedence.
 /// On Windows, uses USERDOMAIN to identify the machine.
 /// On Linux/MacOS, uses LOGNAME to identify the user.
 pub fn sensitive_config_folder_path() -> String {
+    if let Some(config_dir) = get_running_env_overrides().config_dir.as_ref() {
+        return config_dir.clone();
+    }
+
     if env::consts::OS == "windows" {
         // On windows, use USERDOMAIN, instead of USERNAME, because USERNAME can be the same on multiple machines
         // (e.g. "gyantal" on both GYANTAL-PC and GYANTAL-LAPTOP)
-        let userdomain = env::var("USERDOMAIN").expect("Failed to get USERDOMAIN environment variable");
+        let userdomain = env::var("USERDOMAIN").unwrap_or_default();
         match userdomain.as_str() {
             "GYANTAL-PC" => "h:/.shortcut-targets-by-id/0BzxkV1ug5ZxvVmtic1FsNTM5bHM/GDriveHedgeQuant/shared/GitHubRepos/NonCommitedSensitiveData/RqCore/".to_string(),
             "GYANTAL-LAPTOP" => "h:/.shortcut-targets-by-id/0BzxkV1ug5ZxvVmtic1FsNTM5bHM/GDriveHedgeQuant/shared/GitHubRepos/NonCommitedSensitiveData/RqCore/".to_string(),
@@ -19,19 +147,28 @@ pub fn sensitive_config_folder_path() -> String {
             "DAYA-DESKTOP" => "g:/.shortcut-targets-by-id/0BzxkV1ug5ZxvVmtic1FsNTM5bHM/GDriveHedgeQuant/shared/GitHubRepos/NonCommitedSensitiveData/RqCore/".to_string(),
             "DAYA-LAPTOP" => "g:/.shortcut-targets-by-id/0BzxkV1ug5ZxvVmtic1FsNTM5bHM/GDriveHedgeQuant/shared/GitHubRepos/NonCommitedSensitiveData/RqCore/".to_string(),
             "DRCHARMAT-LAPTOP" => "c:/Agy/NonCommitedSensitiveData/RqCore/".to_string(),
-            _ => panic!("Windows user name is not recognized. Add your username and folder here!"),
+            _ => panic!("Windows user name '{}' is not recognized. Use --config-dir=<path> or RQCORE_CONFIG_DIR, or add your username and folder here!", userdomain),
         }
     } else {
         // Linux and MacOS
-        // when running in "screen -r" session, LOGNAME is set, but USER is not
-        let username = env::var("LOGNAME").expect("Failed to get LOGNAME environment variable");
+        // when running in "screen -r" session, LOGNAME is set, but USER is not. In some containers only USER is set.
+        let username = env::var("LOGNAME").or_else(|_| env::var("USER"))
+            .expect("Failed to get LOGNAME or USER environment variable. Use --config-dir=<path> or RQCORE_CONFIG_DIR.");
         format!("/home/{}/RQ/sensitive_data/", username) // e.g. "/home/rquser/RQ/sensitive_data/https_certs"
     }
 }
 
+// Non-sensitive data files (downloaded responses, market data), not committed. Relative to the working folder (src/rqcoresrv), as the FastRunner response files.
+pub fn rqcore_data_folder_path() -> String {
+    "../../../rqcore_data/".to_string()
+}
+
+pub fn rqcore_config_path() -> String {
+    format!("{}rqcore.config", sensitive_config_folder_path())
+}
+
 pub fn load_rqcore_config() -> Result<RqCoreConfig, String> {
-    let sensitive_config_folder_path = sensitive_config_folder_path();
-    let rqcore_config_path = format!("{}rqcore.config", sensitive_config_folder_path);
+    let rqcore_config_path = rqcore_config_path();
 
     let content = match fs::read_to_string(&rqcore_config_path) {
         Ok(content) => content,
@@ -57,4 +194,59 @@ pub fn load_rqcore_config() -> Result<RqCoreConfig, String> {
     }
 
     Ok(rqconfig)
-}
\ No newline at end of file
+}
+
+// Checked before a (re)loaded config is applied, so a bad edit of the file does not take down the running service.
+pub fn validate_rqcore_config(cfg: &RqCoreConfig) -> Result<(), String> {
+    for required_key in ["email_hqserver", "email_hqserver_pwd"] {
+        match cfg.get(required_key) {
+            Some(value) if !value.is_empty() => {}
+            _ => return Err(format!("Required key '{}' is missing or empty", required_key)),
+        }
+    }
+
+    for (key, value) in cfg {
+        if key.is_empty() {
+            return Err(format!("Empty key with value '{}'", value));
+        }
+        if key.starts_with("email_") && !is_rqcore_config_secret_key(key) && !value.contains('@') {
+            return Err(format!("'{}' is not an email address: '{}'", key, value));
+        }
+        if key.starts_with("role_") {
+            if !["role_admin", "role_trader", "role_viewer"].contains(&key.as_str()) {
+                return Err(format!("Unknown role key '{}'. Expected role_admin, role_trader or role_viewer", key));
+            }
+            if let Some(non_email) = value.split(',').map(|e| e.trim()).find(|e| !e.is_empty() && !e.contains('@')) {
+                return Err(format!("'{}' contains a non email address: '{}'", key, non_email));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Values of these keys are never written to the log.
+pub fn is_rqcore_config_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    ["pwd", "password", "secret", "private_key", "token", "session_key"].iter().any(|secret_word| key.contains(secret_word))
+}
+
+// Human readable list of the added, removed and changed keys. Secret values are masked.
+pub fn diff_rqcore_config(old_cfg: &RqCoreConfig, new_cfg: &RqCoreConfig) -> Vec<String> {
+    let display_value = |key: &str, value: &str| if is_rqcore_config_secret_key(key) { "***".to_string() } else { format!("'{}'", value) };
+
+    let mut diff_lines: Vec<String> = Vec::new();
+    for (key, new_value) in new_cfg {
+        match old_cfg.get(key) {
+            None => diff_lines.push(format!("+ {} = {}", key, display_value(key, new_value))),
+            Some(old_value) if old_value != new_value => diff_lines.push(format!("~ {}: {} => {}", key, display_value(key, old_value), display_value(key, new_value))),
+            _ => {}
+        }
+    }
+    for (key, old_value) in old_cfg {
+        if !new_cfg.contains_key(key) {
+            diff_lines.push(format!("- {} = {}", key, display_value(key, old_value)));
+        }
+    }
+    diff_lines.sort_by(|a, b| a[2..].cmp(&b[2..])); // by key name, not by the +-~ prefix
+    diff_lines
+}
@@ -0,0 +1,150 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+// NYSE trading calendar: weekends and the NYSE full-day holidays (computed by the exchange rules, so there is no yearly table to maintain).
+// Early closes (13:00 ET on the day before Independence Day, after Thanksgiving, Christmas Eve) are trading days here.
+// Source of the rules: https://www.nyse.com/markets/hours-calendars
+
+// One-off closures that no rule produces (national days of mourning, storms)
+const NYSE_SPECIAL_CLOSURES: [(i32, u32, u32); 4] = [
+    (2012, 10, 29), // Hurricane Sandy
+    (2012, 10, 30),
+    (2018, 12, 5),  // President George H.W. Bush
+    (2025, 1, 9),   // President Jimmy Carter
+];
+
+pub fn is_nyse_trading_day(date: NaiveDate) -> bool {
+    !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !is_nyse_holiday(date)
+}
+
+pub fn is_nyse_holiday(date: NaiveDate) -> bool {
+    nyse_holidays(date.year()).contains(&date)
+        || NYSE_SPECIAL_CLOSURES.iter().any(|&(y, m, d)| NaiveDate::from_ymd_opt(y, m, d) == Some(date))
+}
+
+// The first trading day after date
+pub fn next_nyse_trading_day(date: NaiveDate) -> NaiveDate {
+    let mut next = date + Duration::days(1);
+    while !is_nyse_trading_day(next) {
+        next += Duration::days(1);
+    }
+    next
+}
+
+// The last trading day before date
+pub fn prev_nyse_trading_day(date: NaiveDate) -> NaiveDate {
+    let mut prev = date - Duration::days(1);
+    while !is_nyse_trading_day(prev) {
+        prev -= Duration::days(1);
+    }
+    prev
+}
+
+// The observed dates of the NYSE holidays of the year
+pub fn nyse_holidays(year: i32) -> Vec<NaiveDate> {
+    let ymd = |m: u32, d: u32| NaiveDate::from_ymd_opt(year, m, d).unwrap();
+    let mut holidays = Vec::with_capacity(10);
+    // New Year's Day: if it falls on Saturday, the market is NOT closed on the preceding Friday (Dec 31, the year-end closing)
+    let new_year = ymd(1, 1);
+    match new_year.weekday() {
+        Weekday::Sat => {}
+        Weekday::Sun => holidays.push(ymd(1, 2)),
+        _ => holidays.push(new_year),
+    }
+    holidays.push(nth_weekday_of_month(year, 1, Weekday::Mon, 3));  // Martin Luther King Jr. Day
+    holidays.push(nth_weekday_of_month(year, 2, Weekday::Mon, 3));  // Washington's Birthday (Presidents' Day)
+    holidays.push(easter_sunday(year) - Duration::days(2));         // Good Friday
+    holidays.push(last_weekday_of_month(year, 5, Weekday::Mon));    // Memorial Day
+    if year >= 2022 {
+        holidays.push(observed(ymd(6, 19))); // Juneteenth National Independence Day
+    }
+    holidays.push(observed(ymd(7, 4)));                             // Independence Day
+    holidays.push(nth_weekday_of_month(year, 9, Weekday::Mon, 1));  // Labor Day
+    holidays.push(nth_weekday_of_month(year, 11, Weekday::Thu, 4)); // Thanksgiving Day
+    holidays.push(observed(ymd(12, 25)));                           // Christmas Day
+    holidays
+}
+
+// Saturday holiday => closed on Friday, Sunday holiday => closed on Monday
+fn observed(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date - Duration::days(1),
+        Weekday::Sun => date + Duration::days(1),
+        _ => date,
+    }
+}
+
+fn nth_weekday_of_month(year: i32, month: u32, weekday: Weekday, n: u8) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, n).unwrap()
+}
+
+fn last_weekday_of_month(year: i32, month: u32, weekday: Weekday) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, 5).unwrap_or_else(|| nth_weekday_of_month(year, month, weekday, 4))
+}
+
+// Anonymous Gregorian algorithm (Meeus/Jones/Butcher)
+fn easter_sunday(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn good_friday() {
+        assert!(is_nyse_holiday(ymd(2024, 3, 29)));
+        assert!(is_nyse_holiday(ymd(2025, 4, 18)));
+        assert!(is_nyse_holiday(ymd(2026, 4, 3)));
+        assert!(is_nyse_trading_day(ymd(2025, 4, 17)));
+        assert!(is_nyse_trading_day(ymd(2025, 4, 21))); // Easter Monday is not a holiday
+    }
+
+    #[test]
+    fn independence_day_observed() {
+        assert!(is_nyse_holiday(ymd(2020, 7, 3))); // July 4 on Saturday => Friday closed
+        assert!(is_nyse_holiday(ymd(2021, 7, 5))); // July 4 on Sunday => Monday closed
+        assert!(is_nyse_trading_day(ymd(2021, 7, 2)));
+        assert!(is_nyse_holiday(ymd(2025, 7, 4)));
+    }
+
+    #[test]
+    fn juneteenth_observed_from_2022() {
+        assert!(is_nyse_holiday(ymd(2022, 6, 20))); // June 19 on Sunday => Monday closed
+        assert!(is_nyse_holiday(ymd(2025, 6, 19)));
+        assert!(is_nyse_holiday(ymd(2027, 6, 18))); // June 19 on Saturday => Friday closed
+        assert!(is_nyse_trading_day(ymd(2021, 6, 18))); // not a NYSE holiday yet
+    }
+
+    #[test]
+    fn new_year_on_saturday_has_no_friday_closure() {
+        assert!(is_nyse_trading_day(ymd(2021, 12, 31))); // Jan 1, 2022 is a Saturday
+        assert!(is_nyse_trading_day(ymd(2022, 1, 3)));
+        assert!(!nyse_holidays(2022).iter().any(|date| date.month() == 1 && date.day() < 17)); // only MLK Day in early January
+        assert!(is_nyse_holiday(ymd(2023, 1, 2))); // Jan 1, 2023 is a Sunday => Monday closed
+    }
+
+    #[test]
+    fn next_and_prev_trading_days() {
+        assert_eq!(next_nyse_trading_day(ymd(2025, 4, 17)), ymd(2025, 4, 21)); // over Good Friday and the weekend
+        assert_eq!(prev_nyse_trading_day(ymd(2025, 4, 21)), ymd(2025, 4, 17));
+        assert_eq!(prev_nyse_trading_day(ymd(2025, 1, 10)), ymd(2025, 1, 8)); // the Jimmy Carter special closure
+    }
+}
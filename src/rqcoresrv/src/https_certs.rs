@@ -0,0 +1,196 @@
+use std::{collections::HashMap, fmt, fs::{self, File}, io::BufReader, sync::{Arc, OnceLock}, time::SystemTime};
+use arc_swap::ArcSwap;
+use chrono::{DateTime, NaiveDate, Utc};
+use rustls::{crypto::aws_lc_rs::sign::any_supported_type, pki_types::{CertificateDer, PrivateKeyDer}, server::{ClientHello, ResolvesServerCert, ResolvesServerCertUsingSni}, sign::CertifiedKey};
+use tokio::time as tokio_time;
+
+use rqcommon::utils::rqemail::RqEmail;
+use crate::{get_rqcore_config, web_vhosts::{WebVhost, WebVhosts}};
+
+// The admin script https_certs_renew.sh renews the certs on disk (Let's Encrypt: every 60 days, valid for 90 days).
+// The cert watcher polls the modification time of the cert files, and reloads all of them if any changed. Then new TLS handshakes use the new certs. No restart needed.
+// If the new files are invalid (e.g. the renew script is still writing them), the old certs are kept, and the reload is retried at the next poll.
+// The watcher also checks the expiry dates daily, and sends a warning email if any cert is within HTTPS_CERT_EXPIRY_WARNING_DAYS of expiry (e.g. the renewal cron job failed).
+
+const HTTPS_CERT_WATCH_INTERVAL_SEC: u64 = 60;
+pub const HTTPS_CERT_EXPIRY_WARNING_DAYS: i64 = 14;
+
+// ---------- Global static variables ----------
+pub static HTTPS_CERT_RESOLVER: OnceLock<Arc<SniWithDefaultFallbackResolver>> = OnceLock::new(); // set in actix_websrv_run()
+
+// ---------- Class/struct definitions ----------
+#[derive(Debug, Clone)]
+pub struct HttpsCertInfo {
+    pub vhost_name: String,
+    pub cert_path: String,
+    pub subject: String,
+    pub not_after: DateTime<Utc>, // expiry date of the leaf (first) cert in the chain
+}
+
+impl HttpsCertInfo {
+    pub fn days_until_expiry(&self) -> i64 {
+        self.not_after.signed_duration_since(Utc::now()).num_days()
+    }
+}
+
+// One consistent set of certs. A reload builds a new set and swaps it in at once.
+struct HttpsCertSet {
+    inner: ResolvesServerCertUsingSni, // the main SNI resolver
+    default_ck: Arc<CertifiedKey>, // default certified key to use when no SNI match
+    cert_infos: Vec<HttpsCertInfo>,
+    file_modified_times: HashMap<String, Option<SystemTime>>, // cert and key paths => modified time at load
+}
+
+// SNI (Server Name Indication): the hostname sent by the client. Used for selecting HTTPS cert.
+// ResolvesServerCertUsingSni matches DNS hostnames, not IPs, and SNI itself is defined for hostnames (not addresses).
+// So IP 127.0.0.1 won’t ever hit an entry in that resolver. We need a SniWithDefaultFallbackResolver to provide a default cert for IP connections.
+pub struct SniWithDefaultFallbackResolver {
+    vhosts: WebVhosts,
+    cert_set: ArcSwap<HttpsCertSet>, // swapped at reload. resolve() is called at every TLS handshake, so it has to be lock-free.
+}
+
+impl fmt::Debug for SniWithDefaultFallbackResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SniWithDefault").finish()
+    }
+}
+
+impl ResolvesServerCert for SniWithDefaultFallbackResolver {
+    fn resolve(&self, ch: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let cert_set = self.cert_set.load();
+        cert_set.inner.resolve(ch).or_else(|| Some(cert_set.default_ck.clone()))
+    }
+}
+
+impl SniWithDefaultFallbackResolver {
+    pub fn new(vhosts: WebVhosts) -> Result<SniWithDefaultFallbackResolver, String> {
+        let cert_set = load_cert_set(&vhosts)?;
+        Ok(SniWithDefaultFallbackResolver { vhosts, cert_set: ArcSwap::from_pointee(cert_set) })
+    }
+
+    pub fn cert_infos(&self) -> Vec<HttpsCertInfo> {
+        self.cert_set.load().cert_infos.clone()
+    }
+
+    fn is_any_file_changed(&self) -> bool {
+        self.cert_set.load().file_modified_times.iter().any(|(path, modified_time)| file_modified_time(path) != *modified_time)
+    }
+
+    pub fn reload(&self) -> Result<(), String> {
+        let new_cert_set = load_cert_set(&self.vhosts)?;
+        for cert_info in &new_cert_set.cert_infos {
+            log::warn!("HTTPS cert reloaded: {} ({}), expires {}", cert_info.vhost_name, cert_info.subject, cert_info.not_after.format("%Y-%m-%d"));
+        }
+        self.cert_set.store(Arc::new(new_cert_set));
+        Ok(())
+    }
+}
+
+// ---------- Loading ----------
+fn load_certs(filename: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certfile = File::open(filename).map_err(|err| format!("cannot open certificate file {}: {}", filename, err))?;
+    let mut reader = BufReader::new(certfile);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>().map_err(|err| format!("invalid certificate in file {}: {}", filename, err))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate found in {}", filename));
+    }
+    Ok(certs)
+}
+
+fn load_private_key(filename: &str) -> Result<PrivateKeyDer<'static>, String> {
+    let keyfile = File::open(filename).map_err(|err| format!("cannot open private key file {}: {}", filename, err))?;
+    let mut reader = BufReader::new(keyfile);
+    match rustls_pemfile::private_key(&mut reader) {
+        Ok(Some(key)) => Ok(key),
+        Ok(None) => Err(format!("no private key found in {}", filename)),
+        Err(err) => Err(format!("invalid private key in file {}: {}", filename, err)),
+    }
+}
+
+fn load_certified_key(vhost: &WebVhost) -> Result<(CertifiedKey, HttpsCertInfo), String> {
+    let certs = load_certs(&vhost.cert_path)?;
+    let key = load_private_key(&vhost.key_path)?;
+    let signing_key = any_supported_type(&key).map_err(|err| format!("unsupported private key type for vhost {}: {}", vhost.name, err))?;
+
+    let (_, leaf_cert) = x509_parser::parse_x509_certificate(&certs[0]).map_err(|err| format!("cannot parse certificate {}: {}", vhost.cert_path, err))?;
+    let not_after = DateTime::<Utc>::from_timestamp(leaf_cert.validity().not_after.timestamp(), 0).unwrap_or_default();
+    let cert_info = HttpsCertInfo { vhost_name: vhost.name.clone(), cert_path: vhost.cert_path.clone(), subject: leaf_cert.subject().to_string(), not_after };
+
+    let certified_key = CertifiedKey::new(certs, signing_key);
+    // Catches a half-renewed state, when the new cert is already written, but the key is still the old one.
+    certified_key.keys_match().map_err(|err| format!("certificate {} and private key {} don't match: {}", vhost.cert_path, vhost.key_path, err))?;
+    Ok((certified_key, cert_info))
+}
+
+fn load_cert_set(vhosts: &WebVhosts) -> Result<HttpsCertSet, String> {
+    let mut inner = ResolvesServerCertUsingSni::new();
+    let mut default_ck: Option<Arc<CertifiedKey>> = None;
+    let mut cert_infos = Vec::with_capacity(vhosts.vhosts.len());
+    let mut file_modified_times = HashMap::new();
+    for (i_vhost, vhost) in vhosts.vhosts.iter().enumerate() {
+        // Modified times are read before the files, so a change during the load is detected at the next poll.
+        file_modified_times.insert(vhost.cert_path.clone(), file_modified_time(&vhost.cert_path));
+        file_modified_times.insert(vhost.key_path.clone(), file_modified_time(&vhost.key_path));
+
+        let (certified_key, cert_info) = load_certified_key(vhost)?;
+        for domain in &vhost.domains {
+            inner.add(domain, certified_key.clone()).map_err(|err| format!("Invalid DNS name {} for vhost {}: {}", domain, vhost.name, err))?;
+        }
+        if i_vhost == vhosts.default_index {
+            default_ck = Some(Arc::new(certified_key)); // use the default (e.g. 'localhost') for IP connections when no domain name sent by client
+        }
+        cert_infos.push(cert_info);
+    }
+    let default_ck = default_ck.ok_or("default vhost is not in the vhosts")?;
+    Ok(HttpsCertSet { inner, default_ck, cert_infos, file_modified_times })
+}
+
+fn file_modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// ---------- Watcher ----------
+pub fn start_https_cert_watcher(resolver: Arc<SniWithDefaultFallbackResolver>) {
+    tokio::spawn(async move {
+        let mut last_expiry_check_date: Option<NaiveDate> = None;
+        let mut interval = tokio_time::interval(tokio_time::Duration::from_secs(HTTPS_CERT_WATCH_INTERVAL_SEC));
+        loop {
+            interval.tick().await; // the first tick completes immediately, so the expiry is checked at startup too
+            if resolver.is_any_file_changed() {
+                match resolver.reload() {
+                    Ok(()) => last_expiry_check_date = None, // check the new certs now
+                    Err(err) => log::error!("HTTPS cert reload failed, keeping the old certs: {}", err),
+                }
+            }
+
+            let today = Utc::now().date_naive();
+            if last_expiry_check_date != Some(today) {
+                last_expiry_check_date = Some(today);
+                check_cert_expiry(&resolver.cert_infos()).await;
+            }
+        }
+    });
+}
+
+async fn check_cert_expiry(cert_infos: &[HttpsCertInfo]) {
+    let expiring: Vec<String> = cert_infos.iter()
+        .filter(|cert_info| cert_info.days_until_expiry() <= HTTPS_CERT_EXPIRY_WARNING_DAYS)
+        .map(|cert_info| format!("{} ({}): expires {} (in {} days). File: {}", cert_info.vhost_name, cert_info.subject, cert_info.not_after.format("%Y-%m-%d %H:%M"), cert_info.days_until_expiry(), cert_info.cert_path))
+        .collect();
+    if expiring.is_empty() {
+        return;
+    }
+
+    let body = format!("HTTPS certs within {} days of expiry. Check the https_certs_renew.sh cron job.\n{}", HTTPS_CERT_EXPIRY_WARNING_DAYS, expiring.join("\n"));
+    log::warn!("{}", body);
+    let email_to_address = match get_rqcore_config().get("email_gyant") {
+        Some(email) => email.clone(),
+        None => {
+            log::error!("email_gyant not found in config. Cert expiry warning email not sent.");
+            return;
+        }
+    };
+    if let Err(err) = RqEmail::send_text(&email_to_address, "RqCore: HTTPS cert expiry warning", &body).await {
+        log::error!("RqEmail::send_text() failed: {}", err);
+    }
+}
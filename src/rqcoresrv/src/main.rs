@@ -1,14 +1,15 @@
-use std::{collections::{HashMap, HashSet}, env, path::Path, sync::{Arc, OnceLock}};
+use std::{collections::HashMap, env, path::Path, sync::{Arc, LazyLock, OnceLock}};
+use arc_swap::ArcSwap;
 use tokio::{io::{self, AsyncBufReadExt}, runtime::{Handle, RuntimeFlavor}};
 use log;
 use spdlog::{prelude::*, sink::{StdStreamSink, FileSink}, formatter::{pattern, PatternFormatter}};
 use time::macros::datetime;
 use chrono::{Local, Utc, DateTime};
-use actix_web::dev::ServerHandle;
 use ibapi::{prelude::*, market_data::historical::WhatToShow};
 
-use rqcommon::{rqhelper::RqError, utils::{rqemail::{RqEmail}, rqgsheets::{RqGSheets}, runningenv::{load_rqcore_config, RqCoreConfig}}}; // no need of mod rqcommon, broker-common as that is in Cargo.toml as a dependency.
-use broker_common::brokers_watcher::{BrokerClient, RQ_BROKERS_WATCHER};
+use rqcommon::{rqhelper::{MutexExt, RqError}, utils::{rqemail::{RqEmail}, rqgsheets::{RqGSheets}, runningenv::{get_running_env_overrides, init_running_env_overrides, load_rqcore_config, RqCoreConfig, RUNNING_ENV_OVERRIDES_USAGE}}}; // no need of mod rqcommon, broker-common as that is in Cargo.toml as a dependency.
+use broker_common::{brokers_watcher::{BrokerClient, RQ_BROKERS_WATCHER}, ib_feed::{IbFeed, IB_FEED_DEFAULT_MAX_TICKERS}};
+use memdb::{daily_history::RQ_DAILY_HISTORY, mark_value_cache::{RQ_MARK_VALUE_CACHE, TICKER_GROUP_MANUAL}, quote::MarkPolicy, quote_book::RQ_QUOTE_BOOK};
 
 // All compile target *.rs files in all folders should be mentioned as modules somehow.
 // That is the way how only main.rs is compiled by 'cargo build', and that imports all the other .rs files as modules.
@@ -22,39 +23,38 @@ mod robotrader; // refers ./robotrader/mod.rs
 mod webapps; // refers ./webapps/mod.rs
 // no 'use crate::webapps' here, because main_web.rs uses those, and we refer to them there
 mod main_web; // refers main_web.rs as a module
+mod web_vhosts; // refers web_vhosts.rs as a module
+mod https_certs; // refers https_certs.rs as a module
 
 use crate::{
     main_web::actix_websrv_run,
     robotrader::{fast_runner::FastRunner, fast_runner_task::{FastRunnerApTask, FastRunnerPqpTask}, robotrader::RQ_ROBO_TRADER},
-    services::rqtask_scheduler::{HeartbeatTask, RQ_TASK_SCHEDULER, RqTask}
+    services::{admin_actions::{run_admin_action, RqAdminAction, SERVER_HANDLE}, daily_history_task::DailyHistoryTask, rqcore_config_watcher::start_rqcore_config_watcher, rqtask_scheduler::{HeartbeatTask, RQ_TASK_SCHEDULER}}
 };
 
 // ---------- Global static variables ----------
 pub static SERVER_APP_START_TIME: OnceLock<DateTime<Utc>> = OnceLock::new();
-pub static RQCORE_CONFIG_LOCK: OnceLock<RqCoreConfig> = OnceLock::new();
-
-pub static EMPTY_STRING_HASHSET: OnceLock<HashSet<String>> = OnceLock::new(); // global helper for get() functions to return an empty global static.
+// ArcSwap instead of OnceLock, because the config can be hot-reloaded (see rqcore_config_watcher.rs) without restarting the server (and the IB gateway connections).
+// Readers get an Arc snapshot. A reload swaps the whole HashMap atomically, so a reader never sees a half-updated config.
+pub static RQCORE_CONFIG: LazyLock<ArcSwap<RqCoreConfig>> = LazyLock::new(|| {
+    ArcSwap::from_pointee(match load_rqcore_config() {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            log::error!("RqCore config not loaded: {}", err);
+            HashMap::new()
+        }
+    })
+});
 
 // Maybe this is the best way to handle global static. With a get_rqcore_config() supplier function, rather than accessing the global static variable directly.
-// because RQCORE_CONFIG_LOCK.get() returns an Option<T>. Even though we 'know' that it cannot be None, because we initialized it,
-// Rust simply doesn't believe, doesn't rely on coder's judgement. So, RQCORE_CONFIG_LOCK.get(), we have to do 'match' function for Some, None.
-// Which makes the code much uglier, than just using an get_rqcore_config() supplier function (that performs the 'match' internally))
 // Option 1*: get_rqcore_config() supplier function:
 // "let google_api_secret= match get_rqcore_config().get("google_api_secret_code") {"
-// Option 2: use RQCORE_CONFIG_LOCK directly. And flatten the double nested IF scopes by chaining the Options using and_then()
-// let google_api_secret= match RQCORE_CONFIG_LOCK.get().and_then(|rq_config| rq_config.get("google_api_secret_code")) {
+// Option 2: use RQCORE_CONFIG directly:
+// "let google_api_secret= match RQCORE_CONFIG.load().get("google_api_secret_code") {"
 // Both can be used, but Option1 is more readable.
-
-pub fn get_rqcore_config() -> &'static RqCoreConfig {
-    RQCORE_CONFIG_LOCK.get_or_init(|| {
-        match load_rqcore_config() {
-            Ok(cfg) => cfg,
-            Err(err) => {
-                log::error!("RqCore config not loaded: {}", err);
-                HashMap::new()
-            }
-        }
-    })
+// The returned Arc is a snapshot. Don't keep it for long (e.g. in a struct), otherwise the holder will not see the reloaded config.
+pub fn get_rqcore_config() -> Arc<RqCoreConfig> {
+    RQCORE_CONFIG.load_full()
 }
 
 // ---------- Class/struct definitions ----------
@@ -67,6 +67,20 @@ struct RuntimeInfo {
 
 // ---------- Helpers ----------
 
+// --enabled-tasks (or RQCORE_ENABLED_TASKS) decides, if given. Otherwise the built-in rule:
+// 2025-12-01: only schedule FastRunner tasks on GYANTAL-PC and GYANTAL-LAPTOP (to avoid other developers' machines running them)
+// DailyHistoryTask too: a machine that starts in the morning would run the missed backfill (2 years of history per new ticker) through its own DcMain client.
+fn is_optional_task_enabled(task_name: &str) -> bool {
+    if let Some(is_enabled) = get_running_env_overrides().is_task_enabled(task_name) {
+        return is_enabled;
+    }
+    if env::consts::OS != "windows" { // In the future FastRunner tasks will be scheduled on Linux server only.
+        return false;
+    }
+    let userdomain = env::var("USERDOMAIN").unwrap_or_default();
+    (userdomain.as_str() == "GYANTAL-PC") || (userdomain.as_str() == "GYANTAL-LAPTOP")
+}
+
 fn init_log() -> Result<(), Box<dyn std::error::Error>> {
     // Check if Cargo.toml exists in the current directory, before creating log file in ../../logs/
     if !Path::new("Cargo.toml").exists() {
@@ -125,7 +139,7 @@ fn init_log() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn init_rqemail(rqcore_cfg: &'static RqCoreConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+fn init_rqemail(rqcore_cfg: &RqCoreConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
     let sender_email: String = match rqcore_cfg.get("email_hqserver") {
         Some(value) => value.to_string(),
         None => {
@@ -144,7 +158,7 @@ fn init_rqemail(rqcore_cfg: &'static RqCoreConfig) -> Result<(), Box<dyn std::er
     Ok(())
 }
 
-fn init_rqgsheets(rqcore_cfg: &'static RqCoreConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+fn init_rqgsheets(rqcore_cfg: &RqCoreConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
     let gsheet_client_email: String = match rqcore_cfg.get("gsheet_client_email") {
         Some(value) => value.to_string(),
         None => {
@@ -164,7 +178,38 @@ fn init_rqgsheets(rqcore_cfg: &'static RqCoreConfig) -> Result<(), Box<dyn std::
     Ok(())
 }
 
-async fn console_menu_loop(server_handle: ServerHandle, runtime_info: Arc<RuntimeInfo>) {
+// Both optional.
+// "mark_value_policy": "mid", "last" or "last_if_recent:120". Default: the last trade if it is at most 60 sec old, otherwise the bid/ask mid.
+// "mark_value_ticker_universe": "AAPL,TSLA": the initial 'manual' tickers. FastRunner adds its own tickers before its runs.
+// "mark_value_ib_feed_max_tickers": the number of tickers with IB tick-by-tick data (DcMain gateway). Default: 10. 0: the YF feed only.
+fn init_mark_value_cache(rqcore_cfg: &RqCoreConfig) {
+    let ib_feed_max_tickers = rqcore_cfg.get("mark_value_ib_feed_max_tickers").and_then(|value| value.trim().parse::<usize>().ok()).unwrap_or(IB_FEED_DEFAULT_MAX_TICKERS);
+    let mut mark_value_cache = RQ_MARK_VALUE_CACHE.lock_ignore_poison();
+    apply_mark_value_policy(rqcore_cfg);
+    if let Some(tickers_csv) = rqcore_cfg.get("mark_value_ticker_universe") {
+        let tickers: Vec<String> = tickers_csv.split(',').map(|ticker| ticker.to_string()).collect();
+        mark_value_cache.set_ticker_group(TICKER_GROUP_MANUAL, &tickers);
+    }
+    if ib_feed_max_tickers > 0 { // registered even if DcMain is not connected now: the watchdog restarts the feed, which gets the current DcMain client
+        if RQ_BROKERS_WATCHER.get_ib_client(BrokerClient::DcMain).is_none() {
+            log::warn!("DcMain gateway is not connected. The IB feed of MarkValueCache starts after the gateway is reconnected.");
+        }
+        mark_value_cache.register_feed(Arc::new(IbFeed::new(ib_feed_max_tickers)));
+    }
+}
+
+// Also called at the config reload (rqcore_config_watcher.rs). A removed key resets the default policy.
+pub fn apply_mark_value_policy(rqcore_cfg: &RqCoreConfig) {
+    match rqcore_cfg.get("mark_value_policy") {
+        Some(policy_str) => match MarkPolicy::parse(policy_str) {
+            Some(policy) => RQ_QUOTE_BOOK.set_mark_policy(policy),
+            None => log::error!("Invalid mark_value_policy '{}' in config. Expected mid, last, last_if_recent or last_if_recent:<sec>. Keeping the current policy.", policy_str),
+        },
+        None => RQ_QUOTE_BOOK.set_mark_policy(MarkPolicy::DEFAULT),
+    }
+}
+
+async fn console_menu_loop(runtime_info: Arc<RuntimeInfo>) {
     let stdin = io::stdin();
     let mut lines = io::BufReader::new(stdin).lines();
 
@@ -176,15 +221,15 @@ async fn console_menu_loop(server_handle: ServerHandle, runtime_info: Arc<Runtim
         println!("\x1b[35m----  (type and press Enter)  ----\x1b[0m"); // Print in magenta using ANSI escape code
         println!("1) Say Hello. Don't do anything. Check responsivenes.");
         println!("2) Show runtime info");
-        println!("3) TaskScheduler: Show next trigger times.");
+        for action in [RqAdminAction::ListTasks, RqAdminAction::PauseTask, RqAdminAction::ResumeTask, RqAdminAction::RemoveTask, RqAdminAction::RunTaskNow, RqAdminAction::ReloadConfig] {
+            println!("{}) {}", action.console_key(), action.description());
+        }
         println!("41) Test: tokio::spawn() background async task in main runtime");
         println!("42) Test IbAPI (gyantal): historical data");
         println!("43) Test IbAPI (dcmain): realtime bars");
-        println!("51) FastRunner PQP: test only HttpDownload");
-        println!("52) FastRunner AP: test only HttpDownload");
-        println!("53) FastRunnerTask PQP: Forcerun trade simulation");
-        println!("54) FastRunnerTask AP: Forcerun trade simulation (getprice() hangs OTH)");
-        println!("9) Stop server and exit gracefully (Avoid Ctrl-^C).");
+        for action in [RqAdminAction::FastRunnerPqpHttpDownloadTest, RqAdminAction::FastRunnerApHttpDownloadTest, RqAdminAction::FastRunnerPqpForceRun, RqAdminAction::FastRunnerApForceRun, RqAdminAction::StopServer] {
+            println!("{}) {}", action.console_key(), action.description());
+        }
         print!("Choice: ");
         // flush stdout (small blocking is fine here)
         use std::io::Write;
@@ -198,6 +243,18 @@ async fn console_menu_loop(server_handle: ServerHandle, runtime_info: Arc<Runtim
             }
         };
 
+        // The remote-controllable actions. The same are available at the /admin/actions web page.
+        // E.g. "34 FastRunnerPqpTask forcerun": the action key, then the args.
+        let (key, args) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        if let Some(action) = RqAdminAction::from_console_key(key) {
+            if let Err(err) = run_admin_action(action, args, "console", "console", None).await {
+                println!("Error: {}", err);
+            } else if action == RqAdminAction::StopServer {
+                break;
+            }
+            continue;
+        }
+
         match line.trim() {
             "1" => {
                 println!("Hello. I am not crashed yet! :)");
@@ -220,9 +277,6 @@ async fn console_menu_loop(server_handle: ServerHandle, runtime_info: Arc<Runtim
                 let candidate_tickers = FastRunner::get_sa_candidate_tickers().await;
                 println!("SA PQP CandidateTickers (#{:?}, #{:?}): {:?}", candidate_tickers.0.len(), candidate_tickers.1.len(), candidate_tickers);
             },
-            "3" => {
-                RQ_TASK_SCHEDULER.print_next_trigger_times();
-            },
             "41" => {
                 println!("Spawning background async task...");
                 tokio::spawn(async { // Actix-web runs N (1 per CPU core) CurrentThread realtimes. For faster response times. So, spawn() tasks start to run when the current task awaits.
@@ -238,31 +292,6 @@ async fn console_menu_loop(server_handle: ServerHandle, runtime_info: Arc<Runtim
             "43" => {
                 test_ibapi_realtime_bars().await;
             }
-            "51" => {
-                let mut fast_runner = robotrader::fast_runner::FastRunner::new();
-                fast_runner.init().await;
-                fast_runner.test_http_download_pqp().await;
-            }
-            "52" => {
-                let mut fast_runner = robotrader::fast_runner::FastRunner::new();
-                fast_runner.init().await;
-                fast_runner.test_http_download_ap().await;
-            }
-            "53" => {
-                let mut task = FastRunnerPqpTask::new();
-                task.is_manual_user_forcerun = true;
-                task.run().await;
-            },
-            "54" => {
-                let mut task = FastRunnerApTask::new();
-                task.is_manual_user_forcerun = true;
-                task.run().await;
-            }
-            "9" => {
-                println!("Stopping server...");
-                server_handle.stop(false).await;
-                break;
-            }
             other => {
                 println!("Unknown choice: {other}");
             }
@@ -393,6 +422,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>
     SERVER_APP_START_TIME.set(Utc::now()).ok();
     
     spdlog::info!("***Starting RqCoreSrv...");  // spdlog::info!() goes through, even though RUST_LOG is not set (because that controls the log::info!())
+    // Before anything asks for the sensitive config folder or the IB client ID, because the overrides take precedence over the built-in tables.
+    let running_env_overrides = match init_running_env_overrides(env::args().skip(1)) {
+        Ok(overrides) => overrides,
+        Err(err) => {
+            eprintln!("{}\n{}", err, RUNNING_ENV_OVERRIDES_USAGE);
+            return Err(err.into());
+        }
+    };
+    spdlog::info!("RunningEnvOverrides: {:?}", running_env_overrides);
     // Initialize the global variable RqCoreConfig now (only once), before parallel threads start to use it.
     let rqcore_cfg = get_rqcore_config();
     spdlog::info!("RqCore config loaded: {} entries", rqcore_cfg.len());
@@ -410,22 +448,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>
     // Call CryptoProvider::install_default() before this point to select a provider manually, or make sure exactly one of the 'aws-lc-rs' and 'ring' features is enabled."
     let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
 
-    init_rqemail(rqcore_cfg)?;
-    init_rqgsheets(rqcore_cfg)?;
+    init_rqemail(&rqcore_cfg)?;
+    init_rqgsheets(&rqcore_cfg)?;
 
     RQ_BROKERS_WATCHER.init().await;
+    init_mark_value_cache(&rqcore_cfg);
+    match RQ_DAILY_HISTORY.lock_ignore_poison().load() {
+        Ok(num_tickers) => spdlog::info!("Daily history loaded: {} tickers", num_tickers),
+        Err(err) => log::error!("Daily history not loaded: {}", err), // the DailyHistoryTask backfill rebuilds it
+    }
     RQ_ROBO_TRADER.init().await;
 
     RQ_TASK_SCHEDULER.schedule_task(Arc::new(HeartbeatTask::new()));
-    // In the future FastRunner tasks will be scheduled on Linux server only.
-    if env::consts::OS == "windows" { // 2025-12-01: only schedule FastRunner tasks on GYANTAL-PC and GYANTAL-LAPTOP (to avoid other developers' machines running them)
-        let userdomain = env::var("USERDOMAIN").expect("Failed to get USERDOMAIN environment variable");
-        if (userdomain.as_str() == "GYANTAL-PC") || (userdomain.as_str() == "GYANTAL-LAPTOP") {
-            RQ_TASK_SCHEDULER.schedule_task(Arc::new(FastRunnerPqpTask::new()));
-            RQ_TASK_SCHEDULER.schedule_task(Arc::new(FastRunnerApTask::new()));
-        }
+    if is_optional_task_enabled("DailyHistoryTask") {
+        RQ_TASK_SCHEDULER.schedule_task(Arc::new(DailyHistoryTask::new()));
+    }
+    if is_optional_task_enabled("FastRunnerPqpTask") {
+        RQ_TASK_SCHEDULER.schedule_task(Arc::new(FastRunnerPqpTask::new()));
+    }
+    if is_optional_task_enabled("FastRunnerApTask") {
+        RQ_TASK_SCHEDULER.schedule_task(Arc::new(FastRunnerApTask::new()));
     }
     RQ_TASK_SCHEDULER.start();
+    start_rqcore_config_watcher();
 
     // Detect CPU count
     let logical_cpus = std::thread::available_parallelism()
@@ -443,10 +488,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>
     });
 
     let (server, server_handle) = actix_websrv_run(runtime_info.clone(), server_workers)?;
+    SERVER_HANDLE.set(server_handle).ok(); // for the StopServer admin action (console menu or web)
 
     // Spawn async console menu INSIDE the Tokio runtime
     tokio::spawn(async move { // Actix-web runs N (1 per CPU core) CurrentThread realtimes. For faster response times. So, spawn() tasks start to run when the current task awaits.
-        console_menu_loop(server_handle, runtime_info).await;
+        console_menu_loop(runtime_info).await;
         println!("console_menu_loop() end");
     });
 
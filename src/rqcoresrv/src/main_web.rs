@@ -1,68 +1,16 @@
-use std::{collections::{HashMap, HashSet}, fmt, fs::File, io::BufReader, sync::{Arc, OnceLock}};
+use std::{collections::HashMap, sync::Arc};
 use actix_files::Files;
-use actix_web::{cookie::Key, web, App, HttpServer, middleware::{from_fn, Compress, Logger}, dev::{ServerHandle}};
-use rustls::{ServerConfig, crypto::aws_lc_rs::sign::any_supported_type, pki_types::{CertificateDer, PrivateKeyDer}, server::{ClientHello, ResolvesServerCert, ResolvesServerCertUsingSni}, sign::CertifiedKey};
-use rustls_pemfile;
+use actix_web::{web, App, HttpServer, middleware::{from_fn, Compress, Logger}, dev::{ServerHandle}};
+use rustls::ServerConfig;
 use actix_identity::IdentityMiddleware;
 use actix_session::{storage::CookieSessionStore, config::PersistentSession, SessionMiddleware};
 
-use rqcommon::utils::runningenv::{sensitive_config_folder_path};
+use rqcommon::utils::runningenv::get_running_env_overrides;
 use crate::{
-    EMPTY_STRING_HASHSET, RuntimeInfo, get_rqcore_config, middleware::{ browser_cache_control::{self}, http_request_logger::{self, HTTP_REQUEST_LOGS, HttpRequestLogs, http_request_logger_middleware}, server_diagnostics::{self}, user_account}, webapps::{test_websocket::test_ws::test_websocket_middleware, robotrader_ui::robotrader_ui::robotrader_websocket}
+    RuntimeInfo, get_rqcore_config, middleware::{ admin, authorization::update_user_roles, browser_cache_control::{self}, session_keys::{session_key_rotation_middleware, SessionKeys, SESSION_COOKIE_NAME, SESSION_KEYS, SESSION_TTL_DAYS}, http_request_logger::{self, HTTP_REQUEST_LOGS, HttpRequestLogs, http_request_logger_middleware}, server_diagnostics::{self}, user_account}, webapps::{test_websocket::test_ws::test_websocket_middleware, robotrader_ui::{robotrader_ui::robotrader_websocket, robotrader_api}, markvalues::markvalues_ws::markvalues_websocket}, web_vhosts::{WebVhosts, WEB_VHOSTS}, https_certs::{start_https_cert_watcher, SniWithDefaultFallbackResolver, HTTPS_CERT_RESOLVER}
 };
 
-// ---------- Global static variables ----------
-
-// There are 2 ways to use OnceLock globals: (note that error handling is symbolic, as it never happens tha the lock is uninitalized)
-// Option 1: direct lock.get():  if lock is uninitialized => you handle error yourself at the place of call. 4 lines of code.
-// let auth_users = match AUTHORIZED_USERS_LOCK.get() {
-//     Some(users) => users,
-//     None => return  HttpResponse::InternalServerError().body("AUTHORIZED_USERS_LOCK is not initialized yet.")
-// };
-// Option 2: get() helper function: if lock is uninitialized => returns an empty set. 1 line of code.
-// let auth_users = get_authorized_users();
-
-pub static AUTHORIZED_USERS_LOCK: OnceLock<HashSet<String>> = OnceLock::new();
-
-// the problem of using this as a get_() is that it requires a parameter of the RqConfig. But that is only required for init(), not for get().
-// users of this only wants get_() without any RqConfig parameter.
-pub fn init_authorized_users(cfg: &HashMap<String, String>) -> () {
-    AUTHORIZED_USERS_LOCK.get_or_init(|| {
-        let mut users = HashSet::new();
-        for (key, value) in cfg {
-            if key.starts_with("email_") {
-                users.insert(value.clone());
-            }
-        }
-        users
-    });
-}
-
-pub fn get_authorized_users() -> &'static HashSet<String> {
-    AUTHORIZED_USERS_LOCK.get().unwrap_or_else(|| {
-        EMPTY_STRING_HASHSET.get_or_init(|| HashSet::new()) // AUTHORIZED_USERS_LOCK is not initialized yet. Returning empty set.
-    })
-}
-
-// SNI (Server Name Indication): the hostname sent by the client. Used for selecting HTTPS cert.
-struct SniWithDefaultFallbackResolver {
-    inner: ResolvesServerCertUsingSni, // the main SNI resolver
-    default_ck: Arc<CertifiedKey>, // default certified key to use when no SNI match
-}
-
-impl fmt::Debug for SniWithDefaultFallbackResolver {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("SniWithDefault").finish()
-    }
-}
-
-impl ResolvesServerCert for SniWithDefaultFallbackResolver {
-    fn resolve(&self, ch: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
-        self.inner.resolve(ch).or_else(|| Some(self.default_ck.clone()))
-    }
-}
-
-fn is_taconite_domain(ctx: &actix_web::guard::GuardContext) -> bool {
+fn request_host(ctx: &actix_web::guard::GuardContext) -> String {
     // Prefer HTTP/2 URI host or HTTP/2 authority; But fallback to Host headeri if URI host is missing (e.g. in HTTP/1.1)
     let uri_host = ctx.head().uri.host(); // works only in HTTP/2 as HTTPS protocol. (in HTTP/1.1 head().uri.host() is None).
     let host_header = ctx.head().headers().get("host"); // works only in HTTP/1.1, as HTTP protocol
@@ -75,68 +23,42 @@ fn is_taconite_domain(ctx: &actix_web::guard::GuardContext) -> bool {
         })
         .unwrap_or("");
     
-    println!("DEBUG: UriHost='{:?}' HeaderHost='{:?}' HeaderAuthority='{:?}'", uri_host, host_header, authority_header, );
-    host.to_lowercase().contains("thetaconite.com")
+    log::debug!("UriHost='{:?}' HeaderHost='{:?}' HeaderAuthority='{:?}'", uri_host, host_header, authority_header, );
+    host.to_lowercase()
 }
 
 // actix's bind_rustls_0_23() returns std::io::Error, so we return general std::error::Error here.
 pub fn actix_websrv_run(runtime_info: Arc<RuntimeInfo>, server_workers: usize) -> Result<(actix_web::dev::Server, ServerHandle), Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let cookie_encrypt_secret_key = "A key that is long enough (64 bytes) to encrypt the session cookie content"; // any encryption code that is used to encrypt the 'session' cookie content. Minimum 64 bytes.
     let runtime_info_for_server = runtime_info;
 
     let rq_config = get_rqcore_config();
-    init_authorized_users(rq_config);
+    update_user_roles(&rq_config);
     HTTP_REQUEST_LOGS.set(Arc::new(HttpRequestLogs::new())).expect("REQUEST_LOGS already initialized");
 
-    let http_listening_port = 8080;
-    let https_listening_port = 8443;
-
-    // Load certificates and keys
-    fn load_certs(filename: &str) -> Vec<CertificateDer<'static>> {
-        let certfile = File::open(filename).expect(&format!("cannot open certificate file {}", filename));
-        let mut reader = BufReader::new(certfile);
-        rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>().expect(&format!("invalid certificate in file {}", filename))
-    }
-
-    fn load_private_key(filename: &str) -> PrivateKeyDer<'static> {
-        let keyfile = File::open(filename).expect(&format!("cannot open private key file {}", filename));
-        let mut reader = BufReader::new(keyfile);
-        rustls_pemfile::private_key(&mut reader).expect(&format!("invalid private key in file {}", filename)).expect(&format!("no private key found in {}", filename))
-    }
-
-    let sensitive_config_folder_path = sensitive_config_folder_path();
-    let cert_base_path = format!("{}https_certs/", sensitive_config_folder_path);
-
-    let rq_certs = load_certs(&format!("{}rqcore.com/fullchain.pem", cert_base_path));
-    let rq_key = load_private_key(&format!("{}rqcore.com/privkey.pem", cert_base_path));
-    let rq_signing_key = any_supported_type(&rq_key).expect("unsupported rqcore private key type");
-    let rq_certified_key = CertifiedKey::new(rq_certs, rq_signing_key);
-
-    let theta_certs = load_certs(&format!("{}thetaconite.com/fullchain.pem", cert_base_path));
-    let theta_key = load_private_key(&format!("{}thetaconite.com/privkey.pem", cert_base_path));
-    let theta_signing_key = any_supported_type(&theta_key).expect("unsupported thetaconite private key type");
-    let theta_certified_key = CertifiedKey::new(theta_certs, theta_signing_key);
-
-    // Default cert for 'localhost' and IP. Created as: openssl req -x509 -nodes -days 3650 -newkey rsa:2048 -keyout privkey.pem -out fullchain.pem -subj "/CN=localhost" -addext "subjectAltName=DNS:localhost,DNS:127.0.0.1"
-    let default_certs = load_certs(&format!("{}localhost/fullchain.pem", cert_base_path));
-    let default_key = load_private_key(&format!("{}localhost/privkey.pem", cert_base_path));
-    let default_signing_key = any_supported_type(&default_key).expect("unsupported default key");
-    let default_certified_key = CertifiedKey::new(default_certs, default_signing_key);
-
-    // the SNI (Server Name Indication) hostname sent by the client
-    // ResolvesServerCertUsingSni matches DNS hostnames, not IPs, and SNI itself is defined for hostnames (not addresses). 
-    // So IP 127.0.0.1 won’t ever hit an entry in that resolver. We need a SniWithDefaultFallbackResolver to provide a default cert for IP connections.
-    let mut sni_resolver = ResolvesServerCertUsingSni::new();
-    sni_resolver.add("rqcore.com", rq_certified_key.clone()).expect("Invalid DNS name for rqcore.com");
-    sni_resolver.add("www.rqcore.com", rq_certified_key.clone()).expect("Invalid DNS name for www.rqcore.com");
-    sni_resolver.add("thetaconite.com", theta_certified_key.clone()).expect("Invalid DNS name for thetaconite.com");
-    sni_resolver.add("www.thetaconite.com", theta_certified_key.clone()).expect("Invalid DNS name for www.thetaconite.com");
-    sni_resolver.add("localhost", default_certified_key.clone()).expect("Invalid localhost DNS name"); // default cert for localhost and IP e.g. 127.0.0.1
-
-    let cert_resolver = Arc::new(SniWithDefaultFallbackResolver {
-        inner: sni_resolver,
-        default_ck: Arc::new(default_certified_key.clone()), // use the default (for 'localhost') for IP connections when no domain name sent by client
-    });
+    // Precedence: CLI args/env variables > rqcore.config > built-in default
+    let running_env_overrides = get_running_env_overrides();
+    let http_listening_port = match running_env_overrides.http_port {
+        Some(port) => port,
+        None => config_port(&rq_config, "websrv_http_port", 8080)?,
+    };
+    let https_listening_port = match running_env_overrides.https_port {
+        Some(port) => port,
+        None => config_port(&rq_config, "websrv_https_port", 8443)?,
+    };
+
+    // Encrypts the 'session' cookie content. Loaded from the sensitive config folder (generated at first run). Old keys are accepted for rotation.
+    let session_keys = SessionKeys::load_or_generate(running_env_overrides.is_production())?;
+    let session_primary_key = session_keys.primary.clone();
+    SESSION_KEYS.set(session_keys).map_err(|_| "SESSION_KEYS already initialized")?;
+
+    let web_vhosts = WebVhosts::from_config(&rq_config)?;
+    spdlog::info!("Web vhosts: {:?}", web_vhosts.vhosts.iter().map(|v| format!("{}: {}", v.name, v.domains.join(","))).collect::<Vec<_>>());
+
+    // Certs are reloaded by the cert watcher when https_certs_renew.sh renews them on disk.
+    let cert_resolver = Arc::new(SniWithDefaultFallbackResolver::new(web_vhosts.clone())?);
+    HTTPS_CERT_RESOLVER.set(cert_resolver.clone()).expect("HTTPS_CERT_RESOLVER already initialized");
+    start_https_cert_watcher(cert_resolver.clone());
+    let web_vhosts = WEB_VHOSTS.get_or_init(|| web_vhosts);
 
     let tls_config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
         .with_safe_default_protocol_versions()
@@ -165,18 +87,19 @@ pub fn actix_websrv_run(runtime_info: Arc<RuntimeInfo>, server_workers: usize) -
             .wrap(from_fn(browser_cache_control::browser_cache_control_30_days_middleware))
             .wrap(from_fn(http_request_logger_middleware))
             .wrap(IdentityMiddleware::default()) // Enables Identity API; identity is stored inside the session.
-            .wrap(SessionMiddleware::builder(CookieSessionStore::default(), Key::from(cookie_encrypt_secret_key.as_bytes())) // Uses an encrypted cookie to store the entire session.
+            .wrap(SessionMiddleware::builder(CookieSessionStore::default(), session_primary_key.clone()) // Uses an encrypted cookie to store the entire session.
             .session_lifecycle(PersistentSession::default() // Makes the cookie persistent (not deleted when browser closes).
-            .session_ttl(time::Duration::days(365))) // Session validity duration (365 days).
+            .session_ttl(time::Duration::days(SESSION_TTL_DAYS))) // Session validity duration (365 days).
             .cookie_secure(true) // Cookie is only sent over HTTPS (required for SameSite=None).
             .cookie_http_only(true) // Cookie is not accessible from JavaScript (XSS protection).
-            .cookie_name("session".to_string()) // Name of the session cookie.
+            .cookie_name(SESSION_COOKIE_NAME.to_string()) // Name of the session cookie.
             .cookie_same_site(actix_web::cookie::SameSite::None) // Required for Google OAuth redirects; allows cross-site cookies.
             .cookie_domain(None)
             .build())
+            .wrap(from_fn(session_key_rotation_middleware)) // Outside the SessionMiddleware: re-encrypts cookies of old session keys before the SessionMiddleware reads them.
             .service(browser_cache_control::browser_domain_cache_bust_header)
             .service(user_account::login)
-            .service(user_account::google_callback)
+            .service(user_account::login_callback)
             .service(user_account::logout)
             .service(user_account::user_infor)
             .service(user_account::authorized_sample)
@@ -184,23 +107,44 @@ pub fn actix_websrv_run(runtime_info: Arc<RuntimeInfo>, server_workers: usize) -
             .service(server_diagnostics::webserver_ping)
             .service(server_diagnostics::server_diagnostics)
             .service(http_request_logger::http_request_activity_log)
+            .service(admin::reload_config)
+            .service(admin::api_tokens)
+            .service(admin::issue_api_token)
+            .service(admin::revoke_api_token)
+            .service(admin::admin_actions_page)
+            .service(admin::admin_actions_websocket)
             .service(test_websocket_middleware)
             .service(robotrader_websocket)
+            .service(robotrader_api::api_executions)
+            .service(robotrader_api::api_scheduled_tasks)
+            .service(robotrader_api::api_scheduled_tasks_history)
+            .service(robotrader_api::api_fastrunner_last_runs)
+            .service(robotrader_api::api_mark_values)
+            .service(robotrader_api::api_mark_value_bars)
+            .service(robotrader_api::api_mark_value_vwap)
+            .service(robotrader_api::api_quote_stream_health)
+            .service(robotrader_api::api_daily_history)
+            .service(robotrader_api::api_ticker_universe)
+            .service(robotrader_api::api_change_ticker_universe)
+            .service(markvalues_websocket)
         // We can serve many domains, each having its own subfolder in ./static/
         // However, when we rewritten path in a middleware (from /index.html to /taconite/index.html), it was not being used by Actix Files
         // Because the main Actix -Files service is mounted at the root "/" and doesn't know (?) how to handle the "/taconite" prefix. 
-        // We need to mount two separate Files services - one for taconite and one for default content
-        // fn_guard(is_taconite_domain) is a quick check based on the Host header, so not much overhead
-            .service(
-                web::scope("")
-                    .guard(actix_web::guard::fn_guard(is_taconite_domain))
-                    .service(Files::new("/", "./static/taconite").prefer_utf8(true).index_file("index.html"))
-            )
-            .service(
-                web::scope("")
-                    .guard(actix_web::guard::fn_guard(|ctx| !is_taconite_domain(ctx)))
-                    .service(Files::new("/", "./static").prefer_utf8(true).index_file("index.html"))
-            )
+        // We need to mount separate Files services - one for each non-default vhost and one for the default content
+        // fn_guard(matches_host) is a quick check based on the Host header, so not much overhead
+            .configure(|cfg| {
+                for (i_vhost, vhost) in web_vhosts.vhosts.iter().enumerate() {
+                    if i_vhost == web_vhosts.default_index {
+                        continue;
+                    }
+                    cfg.service(
+                        web::scope("")
+                            .guard(actix_web::guard::fn_guard(move |ctx| vhost.matches_host(&request_host(ctx))))
+                            .service(Files::new("/", &vhost.static_root).prefer_utf8(true).index_file("index.html"))
+                    );
+                }
+                cfg.service(Files::new("/", &web_vhosts.default_vhost().static_root).prefer_utf8(true).index_file("index.html"));
+            })
     })
     .workers(server_workers)
     .bind(format!("0.0.0.0:{}", http_listening_port))?  // Don't bind to 127.0.0.1 because it only listens to localhost, not external requests to the IP. Returns std::io::error
@@ -210,3 +154,10 @@ pub fn actix_websrv_run(runtime_info: Arc<RuntimeInfo>, server_workers: usize) -
     let handle = server.handle();
     Ok((server, handle))
 }
+
+fn config_port(cfg: &HashMap<String, String>, key: &str, default_port: u16) -> Result<u16, String> {
+    match cfg.get(key) {
+        Some(port_str) => port_str.parse::<u16>().map_err(|err| format!("Invalid {} '{}' in rqcore.config: {}", key, port_str, err)),
+        None => Ok(default_port),
+    }
+}
@@ -0,0 +1,239 @@
+use std::fmt::Write;
+use actix_web::{get, post, http::header::ContentType, middleware::from_fn, web::{self, Form}, HttpRequest, HttpResponse, Responder};
+use actix_ws::Message;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use rqcommon::rqhelper::MutexExt;
+use crate::{middleware::{api_tokens::API_TOKENS, authorization::{is_same_origin_request, require_admin, AuthorizedUser, RqRole}}, services::{admin_actions::{run_admin_action, RqAdminAction, ADMIN_AUDIT_LOG}, rqtask_scheduler::{RqTaskState, RQ_TASK_SCHEDULER}}};
+
+// POST, because it changes the server state (config, roles). A GET could be triggered by any site's <img src> with the admin's SameSite=None cookie.
+#[post("/admin/reloadconfig", wrap = "from_fn(require_admin)")]
+pub async fn reload_config(req: HttpRequest, user: AuthorizedUser) -> impl Responder {
+    if user.api_token_id.is_none() && !is_same_origin_request(&req) {
+        log::warn!("Cross-site config reload request rejected: {}", user.display_name());
+        return HttpResponse::Forbidden().body("Cross-site request rejected");
+    }
+    let (output_sender, mut output_receiver) = mpsc::unbounded_channel::<String>();
+    let result = run_admin_action(RqAdminAction::ReloadConfig, "", &user.display_name(), "web", Some(output_sender)).await;
+    let mut output = String::new();
+    while let Ok(line) = output_receiver.try_recv() {
+        writeln!(output, "{}", line).ok();
+    }
+    match result {
+        Ok(()) => HttpResponse::Ok().content_type("text/plain; charset=utf-8").body(output),
+        Err(err) => HttpResponse::BadRequest().content_type("text/plain; charset=utf-8").body(format!("{}{}", output, err)),
+    }
+}
+
+// ---------- API tokens ----------
+// Token management is only allowed with a browser session, not with an API token. So a leaked token cannot issue new tokens for itself.
+// The POST forms are protected against CSRF (the session cookie is SameSite=None because of the OAuth redirects) by checking that the request comes from our own page.
+
+#[derive(Deserialize, Debug)]
+pub struct IssueApiTokenForm {
+    user_email: String,
+    scope: String,
+    description: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RevokeApiTokenForm {
+    id: u32,
+}
+
+pub fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+fn check_api_token_admin(req: &HttpRequest, user: &AuthorizedUser) -> Result<(), HttpResponse> {
+    if user.api_token_id.is_some() {
+        return Err(HttpResponse::Forbidden().body("API token management requires a browser login, not an API token"));
+    }
+    if req.method() == actix_web::http::Method::POST && !is_same_origin_request(req) {
+        log::warn!("Cross-site API token management request rejected: {}", user.display_name());
+        return Err(HttpResponse::Forbidden().body("Cross-site request rejected"));
+    }
+    Ok(())
+}
+
+fn api_tokens_page(message_html: &str) -> HttpResponse {
+    let mut sb = String::from("<html><body><h1>API tokens</h1>");
+    sb.push_str(message_html);
+    write!(sb, "<h2>Issue new token</h2><form method=\"post\" action=\"/admin/apitokens/issue\">User email: <input name=\"user_email\" size=\"30\"> Scope: <select name=\"scope\"><option>viewer</option><option>trader</option><option>admin</option></select> Description: <input name=\"description\" size=\"40\"> <input type=\"submit\" value=\"Issue\"></form>").ok();
+    write!(sb, "<h2>Tokens</h2><table border=\"1\" cellpadding=\"3\"><tr><th>#</th><th>User</th><th>Scope</th><th>Description</th><th>Created</th><th>Status</th><th></th></tr>").ok();
+    let tokens = API_TOKENS.lock_ignore_poison().tokens.clone(); // don't keep the lock while building the page
+    for token in tokens.iter().rev() {
+        let (status, revoke_form) = match token.revoked_at {
+            Some(revoked_at) => (format!("revoked {}", revoked_at.format("%Y-%m-%d %H:%M")), String::new()),
+            None => ("active".to_string(), format!("<form method=\"post\" action=\"/admin/apitokens/revoke\"><input type=\"hidden\" name=\"id\" value=\"{}\"><input type=\"submit\" value=\"Revoke\"></form>", token.id)),
+        };
+        write!(sb, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{} by {}</td><td>{}</td><td>{}</td></tr>", token.id, html_escape(&token.user_email), token.scope, html_escape(&token.description), token.created_at.format("%Y-%m-%d %H:%M"), html_escape(&token.created_by), status, revoke_form).ok();
+    }
+    sb.push_str("</table><p>Usage: <code>curl -H \"Authorization: Bearer rqt_...\" https://rqcore.com/serverdiagnostics</code></p></body></html>");
+    HttpResponse::Ok().content_type(ContentType::html()).body(sb)
+}
+
+#[get("/admin/apitokens", wrap = "from_fn(require_admin)")]
+pub async fn api_tokens(req: HttpRequest, user: AuthorizedUser) -> impl Responder {
+    if let Err(resp) = check_api_token_admin(&req, &user) {
+        return resp;
+    }
+    api_tokens_page("")
+}
+
+#[post("/admin/apitokens/issue", wrap = "from_fn(require_admin)")]
+pub async fn issue_api_token(req: HttpRequest, user: AuthorizedUser, form: Form<IssueApiTokenForm>) -> impl Responder {
+    if let Err(resp) = check_api_token_admin(&req, &user) {
+        return resp;
+    }
+    let user_email = form.user_email.trim();
+    let Some(scope) = RqRole::parse(form.scope.trim()) else {
+        return api_tokens_page(&format!("<p style=\"color:red\">Invalid scope: {}</p>", html_escape(&form.scope)));
+    };
+    if !user_email.contains('@') {
+        return api_tokens_page(&format!("<p style=\"color:red\">Invalid user email: {}</p>", html_escape(user_email)));
+    }
+
+    let issue_result = API_TOKENS.lock_ignore_poison().issue(user_email, scope, form.description.trim(), &user.email);
+    match issue_result {
+        Ok((record, token)) => api_tokens_page(&format!("<p style=\"color:green\">Token #{} issued for {} (scope: {}). Copy it now, it will not be shown again:<br><code>{}</code></p>", record.id, html_escape(&record.user_email), record.scope, token)),
+        Err(err) => {
+            log::error!("API token issue failed: {}", err);
+            api_tokens_page("<p style=\"color:red\">Token issue failed. See the server log.</p>")
+        }
+    }
+}
+
+#[post("/admin/apitokens/revoke", wrap = "from_fn(require_admin)")]
+pub async fn revoke_api_token(req: HttpRequest, user: AuthorizedUser, form: Form<RevokeApiTokenForm>) -> impl Responder {
+    if let Err(resp) = check_api_token_admin(&req, &user) {
+        return resp;
+    }
+    let revoke_result = API_TOKENS.lock_ignore_poison().revoke(form.id, &user.email);
+    match revoke_result {
+        Ok(()) => api_tokens_page(&format!("<p style=\"color:green\">Token #{} revoked.</p>", form.id)),
+        Err(err) => api_tokens_page(&format!("<p style=\"color:red\">{}</p>", html_escape(&err))),
+    }
+}
+
+// ---------- Admin actions ----------
+// The console menu actions (task management, force-run tasks, SA download tests, stop server) from the browser. See admin_actions.rs.
+// The page runs them through the /ws/admin/actions websocket, which streams the output of the long-running actions back line by line.
+// Websocket protocol. Client: {"type": "run", "action": "runtasknow", "args": "FastRunnerPqpTask forcerun"}. Server: {"type": "output", "action", "line"}, then {"type": "finished", "action", "ok", "error"}.
+
+#[get("/admin/actions", wrap = "from_fn(require_admin)")]
+pub async fn admin_actions_page() -> impl Responder {
+    let mut sb = String::from("<html><body><h1>Admin actions</h1><div>");
+    for action in RqAdminAction::ALL {
+        if action == RqAdminAction::ReloadConfig { // a plain POST: it works without the websocket too
+            write!(sb, "<form method=\"post\" action=\"/admin/reloadconfig\" style=\"display:inline\"><input type=\"submit\" value=\"{}\" title=\"console menu: {}\"></form> ", html_escape(action.description()), action.console_key()).ok();
+        } else if !action.has_args() {
+            write!(sb, "<button onclick=\"runAction('{}')\" title=\"console menu: {}\">{}</button> ", action.name(), action.console_key(), html_escape(action.description())).ok();
+        }
+    }
+    write!(sb, "</div><h2>Tasks</h2><table border=\"1\" cellpadding=\"3\"><tr><th>Task</th><th>Schedule</th><th>State</th><th>Next trigger</th><th>Last run</th><th>Duration</th><th>Last result</th><th></th></tr>").ok();
+    for task in RQ_TASK_SCHEDULER.list_tasks() {
+        let (last_run, duration, result) = match &task.last_run {
+            Some(run) => (format!("{}{}", run.started_at.format("%Y-%m-%d %H:%M:%S"), if run.is_manual { " (manual)" } else { "" }),
+                run.duration_ms.map(|ms| format!("{}ms", ms)).unwrap_or_default(), run.result_text()),
+            None => (String::new(), String::new(), String::new()),
+        };
+        let name = html_escape(&task.name);
+        let pause_or_resume = match task.state {
+            RqTaskState::Active => format!("<button onclick=\"runAction('pausetask', '{}')\">Pause</button>", name),
+            RqTaskState::Paused => format!("<button onclick=\"runAction('resumetask', '{}')\">Resume</button>", name),
+        };
+        write!(sb, "<tr><td>{}</td><td>{}</td><td>{:?}{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{} <button onclick=\"runAction('runtasknow', '{}')\">Run now</button> <button onclick=\"runAction('runtasknow', '{} forcerun')\">Run now (forcerun)</button> <button onclick=\"if (confirm('Remove task {}?')) runAction('removetask', '{}')\">Remove</button></td></tr>",
+            name, html_escape(&task.schedule), task.state, if task.running_count > 0 { ", running" } else { "" }, task.next_trigger_time.format("%Y-%m-%d %H:%M:%S"), last_run, duration, html_escape(&result), pause_or_resume, name, name, name, name).ok();
+    }
+    sb.push_str("</table>(Reload the page to refresh the task states.)<h2>Output</h2><pre id=\"output\" style=\"background:#eee;max-height:400px;overflow:auto\"></pre>");
+    write!(sb, "<h2>Audit log</h2><table border=\"1\" cellpadding=\"3\"><tr><th>#</th><th>Started</th><th>Action</th><th>Args</th><th>By</th><th>From</th><th>Duration</th><th>Result</th></tr>").ok();
+    let audit_records: Vec<_> = ADMIN_AUDIT_LOG.lock_ignore_poison().iter().rev().take(50).cloned().collect(); // don't keep the lock while building the page
+    for record in audit_records.iter() {
+        let duration = record.duration_ms.map(|ms| format!("{}ms", ms)).unwrap_or_default();
+        let result = record.result.as_deref().unwrap_or(if record.duration_ms.is_none() { "running" } else { "" });
+        write!(sb, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>", record.id, record.started_at.format("%Y-%m-%d %H:%M:%S"), record.action, html_escape(&record.args), html_escape(&record.triggered_by), record.source, duration, html_escape(result)).ok();
+    }
+    sb.push_str(r#"</table>
+<script>
+const output = document.getElementById("output");
+const print = (line) => { output.textContent += line + "\n"; output.scrollTop = output.scrollHeight; };
+const socket = new WebSocket((location.protocol === "https:" ? "wss://" : "ws://") + location.host + "/ws/admin/actions");
+socket.onmessage = (event) => {
+    const msg = JSON.parse(event.data);
+    if (msg.type === "output") print("[" + msg.action + "] " + msg.line);
+    else if (msg.type === "finished") print("[" + msg.action + "] finished: " + (msg.ok ? "OK" : "ERROR: " + msg.error));
+    else if (msg.type === "error") print("ERROR: " + msg.message);
+};
+socket.onclose = () => print("Websocket closed. Reload the page.");
+function runAction(action, args) {
+    if (action === "stopserver" && !confirm("Stop the server?")) return;
+    socket.send(JSON.stringify({type: "run", action: action, args: args || ""}));
+}
+</script></body></html>"#);
+    HttpResponse::Ok().content_type(ContentType::html()).body(sb)
+}
+
+#[get("/ws/admin/actions", wrap = "from_fn(require_admin)")]
+pub async fn admin_actions_websocket(req: HttpRequest, body: web::Payload, user: AuthorizedUser) -> actix_web::Result<HttpResponse> {
+    // The session cookie is SameSite=None, so another site's page could open this websocket with the admin's cookie (cross-site websocket hijacking).
+    if user.api_token_id.is_none() && !is_same_origin_request(&req) {
+        log::warn!("Cross-site admin actions websocket rejected: {}", user.display_name());
+        return Ok(HttpResponse::Forbidden().body("Cross-site request rejected"));
+    }
+    let (response, ws_session, mut msg_stream) = actix_ws::handle(&req, body)?;
+    log::info!("admin_actions_websocket opened - user = {}", user.display_name());
+
+    actix_web::rt::spawn(async move {
+        while let Some(client_msg) = msg_stream.next().await {
+            match client_msg {
+                Ok(Message::Text(text)) => {
+                    let client_request: Value = serde_json::from_str(&text).unwrap_or_default();
+                    let action_name = client_request["action"].as_str().unwrap_or("");
+                    let args = client_request["args"].as_str().unwrap_or("").to_string();
+                    let Some(action) = RqAdminAction::parse(action_name).filter(|_| client_request["type"] == "run") else {
+                        let mut ws_session = ws_session.clone();
+                        let _ = ws_session.text(json!({"type": "error", "message": format!("Expected {{\"type\": \"run\", \"action\": <action>}}. Unknown action: '{}'", action_name)}).to_string()).await;
+                        continue;
+                    };
+                    // Each action in its own task: the websocket can start other actions meanwhile, and a panicking action doesn't kill the websocket.
+                    actix_web::rt::spawn(run_admin_action_for_websocket(action, args, user.display_name(), ws_session.clone()));
+                }
+                Ok(Message::Close(reason)) => {
+                    let _ = ws_session.close(reason).await;
+                    return;
+                }
+                Err(e) => {
+                    log::error!("admin_actions_websocket error: {:?}", e);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let _ = ws_session.close(None).await;
+    });
+    Ok(response)
+}
+
+async fn run_admin_action_for_websocket(action: RqAdminAction, args: String, triggered_by: String, mut ws_session: actix_ws::Session) {
+    let (output_sender, mut output_receiver) = mpsc::unbounded_channel::<String>();
+    // The action itself runs in a separate (local) task, so its output can be forwarded while it runs, and its panic is caught as a JoinError.
+    let action_task = actix_web::rt::spawn(async move { run_admin_action(action, &args, &triggered_by, "web", Some(output_sender)).await });
+    while let Some(line) = output_receiver.recv().await { // ends when the action finishes (the sender is dropped)
+        if ws_session.text(json!({"type": "output", "action": action, "line": line}).to_string()).await.is_err() {
+            break; // the browser is gone. The action continues, its result is in the audit log.
+        }
+    }
+    let (ok, error) = match action_task.await {
+        Ok(Ok(())) => (true, None),
+        Ok(Err(err)) => (false, Some(err)),
+        Err(join_err) => {
+            log::error!("Admin action '{}' panicked: {}", action, join_err);
+            (false, Some(format!("Action crashed: {}", join_err)))
+        }
+    };
+    let _ = ws_session.text(json!({"type": "finished", "action": action, "ok": ok, "error": error}).to_string()).await;
+}
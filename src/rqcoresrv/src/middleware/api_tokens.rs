@@ -0,0 +1,129 @@
+use std::{fs, sync::{LazyLock, Mutex}};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use rqcommon::{rqhelper::MutexExt, utils::runningenv::sensitive_config_folder_path};
+use crate::middleware::authorization::RqRole;
+
+// Per-user API tokens for scripts (cron jobs, notebooks): "Authorization: Bearer rqt_...".
+// Admins issue, revoke and scope them at /admin/apitokens. The plain token is shown only once, at issue. Only its SHA-256 hash is stored on disk.
+// (A fast hash is fine here, because the tokens are 256 bit random, not user chosen passwords. Brute force is impossible.)
+// The scope is the maximum role of the token. The effective role is min(scope, the user's current role), so removing a user's role in rqcore.config disables their tokens too.
+
+pub const API_TOKENS_FILENAME: &str = "websrv_api_tokens.json";
+const API_TOKEN_PREFIX: &str = "rqt_"; // makes the tokens recognizable (e.g. by secret scanners) in scripts and logs
+
+// ---------- Global static variables ----------
+pub static API_TOKENS: LazyLock<Mutex<ApiTokenStore>> = LazyLock::new(|| Mutex::new(ApiTokenStore::load()));
+
+// ---------- Class/struct definitions ----------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiTokenRecord {
+    pub id: u32,
+    pub user_email: String,
+    pub scope: RqRole, // the maximum role of the token
+    pub description: String,
+    pub token_hash: String, // hex SHA-256 of the full token
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>, // revoked tokens are kept for the audit trail
+}
+
+#[derive(Debug, Default)]
+pub struct ApiTokenStore {
+    pub tokens: Vec<ApiTokenRecord>,
+}
+
+impl ApiTokenStore {
+    fn file_path() -> String {
+        format!("{}{}", sensitive_config_folder_path(), API_TOKENS_FILENAME)
+    }
+
+    // A missing file is not an error: no tokens issued yet.
+    fn load() -> ApiTokenStore {
+        let path = ApiTokenStore::file_path();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return ApiTokenStore::default(),
+            Err(err) => {
+                log::error!("Cannot read API tokens file '{}': {}. No API token is accepted.", path, err);
+                return ApiTokenStore::default();
+            }
+        };
+        match serde_json::from_str::<Vec<ApiTokenRecord>>(&content) {
+            Ok(tokens) => ApiTokenStore { tokens },
+            Err(err) => {
+                log::error!("Invalid API tokens file '{}': {}. No API token is accepted.", path, err);
+                ApiTokenStore::default()
+            }
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = ApiTokenStore::file_path();
+        let content = serde_json::to_string_pretty(&self.tokens).map_err(|err| format!("API tokens serialize error: {}", err))?;
+        // Write to a temp file and rename, so a crash during the write doesn't lose all the tokens
+        let tmp_path = format!("{}.tmp", path);
+        fs::write(&tmp_path, content).map_err(|err| format!("Cannot write '{}': {}", tmp_path, err))?;
+        fs::rename(&tmp_path, &path).map_err(|err| format!("Cannot rename '{}' to '{}': {}", tmp_path, path, err))?;
+        Ok(())
+    }
+
+    // Returns the plain token. It is not stored, so it cannot be shown again.
+    pub fn issue(&mut self, user_email: &str, scope: RqRole, description: &str, created_by: &str) -> Result<(ApiTokenRecord, String), String> {
+        let secret_bytes: [u8; 32] = rand::random();
+        let token = format!("{}{}", API_TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(secret_bytes));
+        let record = ApiTokenRecord {
+            id: self.tokens.iter().map(|t| t.id).max().unwrap_or(0) + 1,
+            user_email: user_email.to_string(),
+            scope,
+            description: description.to_string(),
+            token_hash: hash_api_token(&token),
+            created_by: created_by.to_string(),
+            created_at: Utc::now(),
+            revoked_at: None,
+        };
+        self.tokens.push(record.clone());
+        if let Err(err) = self.save() {
+            self.tokens.pop();
+            return Err(err);
+        }
+        log::warn!("API token #{} issued for {} (scope: {:?}) by {}", record.id, record.user_email, record.scope, created_by);
+        Ok((record, token))
+    }
+
+    pub fn revoke(&mut self, id: u32, revoked_by: &str) -> Result<(), String> {
+        let record = self.tokens.iter_mut().find(|t| t.id == id).ok_or_else(|| format!("API token #{} not found", id))?;
+        if record.revoked_at.is_some() {
+            return Err(format!("API token #{} is already revoked", id));
+        }
+        record.revoked_at = Some(Utc::now());
+        if let Err(err) = self.save() {
+            if let Some(record) = self.tokens.iter_mut().find(|t| t.id == id) {
+                record.revoked_at = None;
+            }
+            return Err(err);
+        }
+        log::warn!("API token #{} revoked by {}", id, revoked_by);
+        Ok(())
+    }
+
+    fn find_active(&self, token: &str) -> Option<&ApiTokenRecord> {
+        if !token.starts_with(API_TOKEN_PREFIX) {
+            return None;
+        }
+        let token_hash = hash_api_token(token);
+        self.tokens.iter().find(|t| t.revoked_at.is_none() && t.token_hash == token_hash)
+    }
+}
+
+fn hash_api_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Returns the token record of a valid, not revoked token.
+pub fn verify_api_token(token: &str) -> Option<ApiTokenRecord> {
+    API_TOKENS.lock_ignore_poison().find_active(token).cloned()
+}
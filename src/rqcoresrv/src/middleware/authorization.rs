@@ -0,0 +1,285 @@
+use std::{collections::HashMap, fmt, future::{ready, Ready}, sync::{Arc, LazyLock}};
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use actix_session::{Session, SessionExt};
+use actix_web::{body::{EitherBody, MessageBody}, dev::{Payload, ServiceRequest, ServiceResponse}, http::header, middleware::Next, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+
+use rqcommon::utils::runningenv::{is_rqcore_config_secret_key, RqCoreConfig};
+use crate::middleware::api_tokens::verify_api_token;
+
+// Role based authorization. Roles are ordered: Admin can do everything a Trader can, and a Trader can do everything a Viewer can.
+// Role assignments in rqcore.config (comma separated emails):
+// role_admin=gyant@gmail.com
+// role_trader=trader1@gmail.com,trader2@gmail.com
+// role_viewer=viewer@gmail.com
+// If there is no role_* key in the config (legacy config), all the email_* users are admins (that was the only 'authorized user' level before roles).
+// The roles are per email, so only the login providers that are trusted to own the emails grant them (an IdP could assert anybody's email):
+// oauth_role_providers=google,keycloak   // Default: google. A login with another provider has no role.
+// Per route usage: #[get("/serverdiagnostics", wrap = "from_fn(require_trader)")]
+// Handlers behind the middleware can get the user with the AuthorizedUser extractor.
+// Scripts authenticate with an API token instead of the session cookie: 'Authorization: Bearer rqt_...' (see api_tokens.rs).
+// Unauthorized requests get a consistent response: 401 if not logged in, 403 if logged in, but the role is not enough.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RqRole {
+    Viewer,
+    Trader,
+    Admin,
+}
+
+impl RqRole {
+    pub fn parse(role: &str) -> Option<RqRole> {
+        match role {
+            "viewer" => Some(RqRole::Viewer),
+            "trader" => Some(RqRole::Trader),
+            "admin" => Some(RqRole::Admin),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RqRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RqRole::Viewer => "viewer",
+            RqRole::Trader => "trader",
+            RqRole::Admin => "admin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub const ROLE_CONFIG_KEYS: [(&str, RqRole); 3] = [("role_viewer", RqRole::Viewer), ("role_trader", RqRole::Trader), ("role_admin", RqRole::Admin)];
+
+// The role assignments and the login providers that grant them, from the config. Built from one config, so a reader never sees the roles of one config with the providers of another.
+#[derive(Debug, Clone, Default)]
+pub struct UserRoles {
+    roles: HashMap<String, RqRole>, // email => role
+    role_granting_providers: Vec<String>,
+}
+
+impl UserRoles {
+    pub fn from_config(cfg: &RqCoreConfig) -> UserRoles {
+        let mut roles: HashMap<String, RqRole> = HashMap::new();
+        let has_role_keys = ROLE_CONFIG_KEYS.iter().any(|(key, _)| cfg.contains_key(*key));
+        if has_role_keys {
+            for (key, role) in ROLE_CONFIG_KEYS { // in increasing order, so the highest role wins if a user is listed more than once
+                if let Some(emails) = cfg.get(key) {
+                    for email in emails.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+                        roles.insert(email.to_string(), role);
+                    }
+                }
+            }
+        } else {
+            // Secret values (passwords, keys) are never user emails, even if their key starts with "email_" (e.g. email_hqserver_pwd).
+            for (key, email) in cfg {
+                if key.starts_with("email_") && !is_rqcore_config_secret_key(key) {
+                    roles.insert(email.clone(), RqRole::Admin);
+                }
+            }
+        }
+
+        let role_granting_providers: Vec<String> = match cfg.get("oauth_role_providers") {
+            Some(names) => names.split(',').map(|n| n.trim().to_string()).filter(|n| !n.is_empty()).collect(),
+            None => vec![OAUTH_DEFAULT_ROLE_PROVIDER.to_string()],
+        };
+        UserRoles { roles, role_granting_providers }
+    }
+
+    pub fn get_user_role(&self, email: &str) -> Option<RqRole> {
+        self.roles.get(email).copied()
+    }
+
+    // The role of a user logged in with auth_provider: None if that provider doesn't grant roles
+    pub fn get_login_user_role(&self, auth_provider: &str, email: &str) -> Option<RqRole> {
+        if !self.role_granting_providers.iter().any(|provider| provider == auth_provider) {
+            return None;
+        }
+        self.get_user_role(email)
+    }
+}
+
+// ---------- Global static variables ----------
+// ArcSwap, because the roles are updated at rqcore.config hot-reload (see rqcore_config_watcher.rs).
+pub static USER_ROLES: LazyLock<ArcSwap<UserRoles>> = LazyLock::new(|| ArcSwap::from_pointee(UserRoles::default()));
+
+const OAUTH_DEFAULT_ROLE_PROVIDER: &str = "google"; // also the provider of the sessions from before the provider was stored in the session
+
+// Called at startup and at every config reload.
+pub fn update_user_roles(cfg: &RqCoreConfig) {
+    USER_ROLES.store(Arc::new(UserRoles::from_config(cfg)));
+}
+
+pub fn get_user_role(email: &str) -> Option<RqRole> {
+    USER_ROLES.load().get_user_role(email)
+}
+
+pub fn get_login_user_role(auth_provider: &str, email: &str) -> Option<RqRole> {
+    USER_ROLES.load().get_login_user_role(auth_provider, email)
+}
+
+// ---------- Extractor ----------
+#[derive(Debug, Clone)]
+pub struct AuthorizedUser {
+    pub email: String,
+    pub role: RqRole,
+    pub api_token_id: Option<u32>, // Some if authenticated with an API token (Authorization: Bearer), None if with the session cookie
+}
+
+impl AuthorizedUser {
+    // None if not logged in, or logged in without any role.
+    pub fn from_session(session: &Session) -> Option<AuthorizedUser> {
+        let email = session_user_email(session).ok()??;
+        let role = get_login_user_role(&session_auth_provider(session), &email)?;
+        Some(AuthorizedUser { email, role, api_token_id: None })
+    }
+
+    // "email" or "email (token #3)". Used in logs.
+    pub fn display_name(&self) -> String {
+        match self.api_token_id {
+            Some(id) => format!("{} (token #{})", self.email, id),
+            None => self.email.clone(),
+        }
+    }
+}
+
+enum AuthError {
+    Unauthorized(&'static str), // 401: not logged in, or invalid token
+    Forbidden(String),          // 403: authenticated, but has no role
+    SessionError,               // 500
+}
+
+// A Bearer API token takes precedence over the session cookie (scripts don't have a session).
+fn authenticate(req: &HttpRequest) -> Result<AuthorizedUser, AuthError> {
+    if let Some(auth_header) = req.headers().get(header::AUTHORIZATION) {
+        let token = auth_header.to_str().ok().and_then(|h| h.strip_prefix("Bearer ")).ok_or(AuthError::Unauthorized("Invalid Authorization header. Expected: Bearer <token>"))?;
+        let token_record = verify_api_token(token.trim()).ok_or(AuthError::Unauthorized("Invalid or revoked API token"))?;
+        // min(token scope, user's current role): removing the user's role disables the tokens too
+        let user_role = get_user_role(&token_record.user_email).ok_or_else(|| AuthError::Forbidden(token_record.user_email.clone()))?;
+        return Ok(AuthorizedUser { email: token_record.user_email, role: user_role.min(token_record.scope), api_token_id: Some(token_record.id) });
+    }
+
+    let email = match session_user_email(&req.get_session()) {
+        Ok(Some(email)) => email,
+        Ok(None) => return Err(AuthError::Unauthorized("Login required")),
+        Err(err) => {
+            log::error!("Failed to read 'user_email' from session: {}", err);
+            return Err(AuthError::SessionError);
+        }
+    };
+    let role = get_login_user_role(&session_auth_provider(&req.get_session()), &email).ok_or(AuthError::Forbidden(email.clone()))?;
+    Ok(AuthorizedUser { email, role, api_token_id: None })
+}
+
+fn auth_error_response(auth_error: AuthError, required_role: RqRole) -> HttpResponse {
+    match auth_error {
+        AuthError::Unauthorized(msg) => HttpResponse::Unauthorized().content_type("text/plain; charset=utf-8").body(msg),
+        AuthError::Forbidden(email) => HttpResponse::Forbidden().content_type("text/plain; charset=utf-8").body(format!("Access denied: {} has no {} role", email, required_role)),
+        AuthError::SessionError => HttpResponse::InternalServerError().content_type("text/plain; charset=utf-8").body("Session error"),
+    }
+}
+
+// Works behind the require_*() middlewares (they insert it into the request extensions), and also without them (with the minimum role Viewer).
+impl FromRequest for AuthorizedUser {
+    type Error = Error;
+    type Future = Ready<Result<AuthorizedUser, Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        if let Some(user) = req.extensions().get::<AuthorizedUser>() {
+            return ready(Ok(user.clone()));
+        }
+        ready(authenticate(req).map_err(|auth_error| actix_web::error::InternalError::from_response("Unauthorized", auth_error_response(auth_error, RqRole::Viewer)).into()))
+    }
+}
+
+fn session_user_email(session: &Session) -> Result<Option<String>, actix_session::SessionGetError> {
+    session.get::<String>("user_email")
+}
+
+fn session_auth_provider(session: &Session) -> String {
+    session.get::<String>("user_auth_provider").ok().flatten().unwrap_or_else(|| OAUTH_DEFAULT_ROLE_PROVIDER.to_string())
+}
+
+// ---------- Middleware ----------
+pub async fn require_viewer(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    require_role(req, next, RqRole::Viewer).await
+}
+
+pub async fn require_trader(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    require_role(req, next, RqRole::Trader).await
+}
+
+pub async fn require_admin(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    require_role(req, next, RqRole::Admin).await
+}
+
+async fn require_role<B: MessageBody>(req: ServiceRequest, next: Next<B>, required_role: RqRole) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let user = match authenticate(req.request()) {
+        Ok(user) => user,
+        Err(auth_error) => {
+            if let AuthError::Forbidden(email) = &auth_error {
+                log::warn!("Access denied: {} (no role) to {}", email, req.path());
+            }
+            return Ok(req.into_response(auth_error_response(auth_error, required_role)).map_into_right_body());
+        }
+    };
+
+    if user.role < required_role {
+        log::warn!("Access denied: {} (role: {}) to {} (required role: {})", user.display_name(), user.role, req.path(), required_role);
+        return Ok(req.into_response(auth_error_response(AuthError::Forbidden(user.display_name()), required_role)).map_into_right_body());
+    }
+    req.extensions_mut().insert(user);
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+// CSRF and cross-site websocket hijacking protection. The session cookie is SameSite=None (because of the OAuth redirects), so the browser sends it
+// with the requests of other sites' pages too. Browsers send Sec-Fetch-Site (modern ones) or Origin at cross-site form POSTs and websocket upgrades.
+// API token requests don't need it (no cookie is involved): the callers check 'user.api_token_id.is_none() && !is_same_origin_request(&req)'.
+pub fn is_same_origin_request(req: &HttpRequest) -> bool {
+    if let Some(fetch_site) = req.headers().get("sec-fetch-site").and_then(|h| h.to_str().ok()) {
+        return fetch_site == "same-origin";
+    }
+    match req.headers().get("origin").and_then(|h| h.to_str().ok()) {
+        Some(origin) => origin.split_once("://").map(|(_, origin_host)| origin_host) == Some(req.connection_info().host()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_role_only_from_role_granting_providers() {
+        let mut cfg = RqCoreConfig::from([("role_admin".to_string(), "admin@example.com".to_string()), ("role_viewer".to_string(), "viewer@example.com".to_string())]);
+        let user_roles = UserRoles::from_config(&cfg);
+        assert_eq!(user_roles.get_login_user_role("google", "admin@example.com"), Some(RqRole::Admin));
+        assert_eq!(user_roles.get_login_user_role("google", "viewer@example.com"), Some(RqRole::Viewer));
+        assert_eq!(user_roles.get_login_user_role("keycloak", "admin@example.com"), None); // an IdP that could assert anybody's email
+        assert_eq!(user_roles.get_login_user_role("google", "other@example.com"), None);
+
+        cfg.insert("oauth_role_providers".to_string(), "google, keycloak".to_string());
+        let user_roles = UserRoles::from_config(&cfg);
+        assert_eq!(user_roles.get_login_user_role("keycloak", "admin@example.com"), Some(RqRole::Admin));
+        assert_eq!(user_roles.get_login_user_role("github", "admin@example.com"), None);
+    }
+
+    #[test]
+    fn legacy_config_email_users_are_admins() {
+        let cfg = RqCoreConfig::from([("email_gyant".to_string(), "gyant@example.com".to_string()), ("email_hqserver_pwd".to_string(), "secret".to_string())]);
+        let user_roles = UserRoles::from_config(&cfg);
+        assert_eq!(user_roles.get_user_role("gyant@example.com"), Some(RqRole::Admin));
+        assert_eq!(user_roles.get_user_role("secret"), None); // a password is not an email
+    }
+
+    #[test]
+    fn same_origin_request_by_fetch_site_or_origin() {
+        use actix_web::test::TestRequest;
+        assert!(is_same_origin_request(&TestRequest::default().insert_header(("sec-fetch-site", "same-origin")).to_http_request()));
+        assert!(!is_same_origin_request(&TestRequest::default().insert_header(("sec-fetch-site", "cross-site")).insert_header(("origin", "https://localhost:8080")).to_http_request()));
+        assert!(is_same_origin_request(&TestRequest::default().insert_header((header::HOST, "rqcore.com")).insert_header(("origin", "https://rqcore.com")).to_http_request()));
+        assert!(!is_same_origin_request(&TestRequest::default().insert_header((header::HOST, "rqcore.com")).insert_header(("origin", "https://evil.example")).to_http_request()));
+        assert!(!is_same_origin_request(&TestRequest::default().to_http_request())); // no header: e.g. a non-browser client with a cookie
+    }
+}
@@ -1,7 +1,9 @@
 use std::{collections::VecDeque, fmt::Write, net::IpAddr, sync::{Arc, Mutex, OnceLock}};
 use chrono::{DateTime, Utc};
 use actix_session::SessionExt;
-use actix_web::{body::MessageBody, dev::{ServiceRequest, ServiceResponse}, get, middleware::Next, Error, HttpResponse,};
+use actix_web::{body::MessageBody, dev::{ServiceRequest, ServiceResponse}, get, middleware::{from_fn, Next}, Error, HttpMessage, HttpResponse,};
+
+use crate::middleware::authorization::{require_trader, AuthorizedUser};
 
 pub static HTTP_REQUEST_LOGS: OnceLock<Arc<HttpRequestLogs>> = OnceLock::new();
 
@@ -86,13 +88,17 @@ where
         .parse::<IpAddr>()
         .unwrap_or(IpAddr::from([0, 0, 0, 0]));
 
-     let user_email = service_req
+     let mut user_email = service_req
         .get_session()
         .get::<String>("user_email")
         .unwrap_or(None)
         .unwrap_or_default();
     // Execute next service
     let res = next.call(service_req).await?;
+    // API token users have no session. The authorization middleware puts them into the request extensions.
+    if let Some(authorized_user) = res.request().extensions().get::<AuthorizedUser>() {
+        user_email = authorized_user.display_name();
+    }
 
     let status_code = res.status().as_u16();
     let is_error = status_code >= 500;
@@ -120,7 +126,7 @@ where
     Ok(res)
 }
 
-#[get("/httprequestactivitylog")]
+#[get("/httprequestactivitylog", wrap = "from_fn(require_trader)")]
 pub async fn http_request_activity_log() -> HttpResponse {
     let logs_html = HTTP_REQUEST_LOGS.get().unwrap().http_request_activity();
     let full_html = format!("<html><body><h1>HttpRequests Activity Log</h1>{}</body></html>", logs_html );
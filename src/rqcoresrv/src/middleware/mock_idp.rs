@@ -0,0 +1,74 @@
+use actix_web::{dev::ServerHandle, http::header, web, App, HttpRequest, HttpResponse, HttpServer};
+use serde_json::{json, Value};
+
+use rqcommon::utils::runningenv::RqCoreConfig;
+use crate::middleware::oidc_provider::OidcEndpoints;
+
+// A local OAuth2/OIDC identity provider for the tests: the token and userinfo endpoints on 127.0.0.1 (a random port).
+// The token endpoint accepts only MOCK_IDP_VALID_CODE, the userinfo endpoint only the access token it gave, and returns the given userinfo JSON.
+
+pub const MOCK_IDP_VALID_CODE: &str = "mock-valid-code";
+const MOCK_IDP_ACCESS_TOKEN: &str = "mock-access-token";
+
+pub struct MockIdp {
+    pub base_url: String,
+    handle: ServerHandle,
+}
+
+impl MockIdp {
+    pub async fn start(userinfo: Value) -> MockIdp {
+        let userinfo = web::Data::new(userinfo);
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(userinfo.clone())
+                .route("/token", web::post().to(mock_token))
+                .route("/userinfo", web::get().to(mock_userinfo))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .expect("mock IdP bind");
+        let base_url = format!("http://{}", server.addrs()[0]);
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        MockIdp { base_url, handle }
+    }
+
+    pub fn endpoints(&self) -> OidcEndpoints {
+        OidcEndpoints { auth_url: format!("{}/auth", self.base_url), token_url: format!("{}/token", self.base_url), userinfo_url: format!("{}/userinfo", self.base_url) }
+    }
+
+    // The config lines of a provider that points to this IdP, with the client credentials of the 'rqcore' vhost prefix. A test App gets it as web::Data<RqCoreConfig> (see user_account.rs).
+    pub fn provider_config(&self, provider_name: &str) -> RqCoreConfig {
+        let endpoints = self.endpoints();
+        RqCoreConfig::from([
+            ("oauth_providers".to_string(), provider_name.to_string()),
+            (format!("oauth_provider.{}.auth_url", provider_name), endpoints.auth_url),
+            (format!("oauth_provider.{}.token_url", provider_name), endpoints.token_url),
+            (format!("oauth_provider.{}.userinfo_url", provider_name), endpoints.userinfo_url),
+            (format!("rqcore_{}_client_id", provider_name), "mock-client-id".to_string()),
+            (format!("rqcore_{}_client_secret", provider_name), "mock-client-secret".to_string()),
+        ])
+    }
+
+    pub async fn stop(self) {
+        self.handle.stop(false).await;
+    }
+}
+
+async fn mock_token(form: web::Form<Vec<(String, String)>>) -> HttpResponse {
+    let is_valid_code = form.iter().any(|(key, value)| key == "code" && value == MOCK_IDP_VALID_CODE);
+    let is_authorization_code_grant = form.iter().any(|(key, value)| key == "grant_type" && value == "authorization_code");
+    if !is_valid_code || !is_authorization_code_grant {
+        return HttpResponse::BadRequest().json(json!({"error": "invalid_grant"}));
+    }
+    HttpResponse::Ok().json(json!({"access_token": MOCK_IDP_ACCESS_TOKEN, "token_type": "Bearer", "expires_in": 3600}))
+}
+
+async fn mock_userinfo(req: HttpRequest, userinfo: web::Data<Value>) -> HttpResponse {
+    let bearer = req.headers().get(header::AUTHORIZATION).and_then(|h| h.to_str().ok()).and_then(|h| h.strip_prefix("Bearer "));
+    if bearer != Some(MOCK_IDP_ACCESS_TOKEN) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok().json(userinfo.get_ref())
+}
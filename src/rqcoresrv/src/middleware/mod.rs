@@ -1,4 +1,12 @@
 pub mod browser_cache_control;
 pub mod http_request_logger;
 pub mod user_account;
-pub mod server_diagnostics;
\ No newline at end of file
+pub mod server_diagnostics;
+pub mod admin;
+pub mod api_tokens;
+pub mod authorization;
+pub mod oauth_state;
+pub mod oidc_provider;
+pub mod session_keys;
+#[cfg(test)]
+pub mod mock_idp;
@@ -0,0 +1,201 @@
+use actix_session::Session;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::middleware::session_keys::SESSION_KEYS;
+
+// The OAuth 'state' parameter goes to Google and comes back in the login callback unchanged. It protects against:
+// - CSRF (login CSRF): an attacker cannot make the victim's browser complete a login that the victim didn't start,
+//   because the state has to contain the random nonce that is stored in the victim's session at login start.
+// - Replay: the nonce is removed from the session at the first callback, so the same state cannot be used twice.
+// - Expiry: a state older than OAUTH_STATE_MAX_AGE_SEC is rejected (e.g. a login page left open, then completed hours later).
+// - Tampering: the state is signed with HMAC-SHA256 (with the session signing key), so the returnUrl cannot be changed.
+// - Open redirect: the returnUrl is restricted to same-origin paths ("/..."), both at login start and at the callback.
+// Format: base64url(json payload) + "." + base64url(hmac of the first part)
+
+const OAUTH_STATE_NONCE_SESSION_KEY: &str = "oauth_state_nonce";
+const OAUTH_STATE_MAX_AGE_SEC: i64 = 10 * 60; // the time to log in at the provider
+
+#[derive(Serialize, Deserialize, Debug)]
+struct OAuthStatePayload {
+    nonce: String,
+    return_url: String,
+    provider: String, // the identity provider the login was started with. The callback URL is the same for all providers.
+    issued_at: i64,   // unix seconds
+}
+
+#[derive(Debug)]
+pub struct VerifiedOAuthState {
+    pub return_url: String,
+    pub provider: String,
+}
+
+// Only local paths are allowed. Everything else (absolute URLs, protocol relative "//evil.com", "/\evil.com" that some browsers treat as "//") becomes "/".
+pub fn sanitize_return_url(return_url: Option<&str>) -> String {
+    match return_url {
+        Some(url) if url.starts_with('/') && !url.starts_with("//") && !url.starts_with("/\\") && !url.chars().any(|c| c.is_control()) => url.to_string(),
+        _ => "/".to_string(),
+    }
+}
+
+fn hmac_sign(data: &[u8]) -> Result<Hmac<Sha256>, String> {
+    let session_keys = SESSION_KEYS.get().ok_or("SESSION_KEYS not initialized")?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(session_keys.primary.signing()).map_err(|err| format!("HMAC key error: {}", err))?;
+    mac.update(data);
+    Ok(mac)
+}
+
+// At login start: stores a new nonce in the session, and returns the signed state for the provider's auth URL.
+pub fn create_oauth_state(session: &Session, return_url: Option<&str>, provider: &str) -> Result<String, String> {
+    create_oauth_state_issued_at(session, return_url, provider, Utc::now().timestamp())
+}
+
+fn create_oauth_state_issued_at(session: &Session, return_url: Option<&str>, provider: &str, issued_at: i64) -> Result<String, String> {
+    let nonce_bytes: [u8; 32] = rand::random();
+    let payload = OAuthStatePayload { nonce: URL_SAFE_NO_PAD.encode(nonce_bytes), return_url: sanitize_return_url(return_url), provider: provider.to_string(), issued_at };
+    session.insert(OAUTH_STATE_NONCE_SESSION_KEY, &payload.nonce).map_err(|err| format!("Session insert error: {}", err))?;
+
+    let payload_json = serde_json::to_vec(&payload).map_err(|err| format!("State serialize error: {}", err))?;
+    let payload_part = URL_SAFE_NO_PAD.encode(payload_json);
+    let signature = hmac_sign(payload_part.as_bytes())?.finalize().into_bytes();
+    Ok(format!("{}.{}", payload_part, URL_SAFE_NO_PAD.encode(signature)))
+}
+
+// At the callback: verifies the signature and the session nonce (then removes it, so a replay fails). Returns the sanitized returnUrl and the provider.
+pub fn verify_oauth_state(session: &Session, state: &str) -> Result<VerifiedOAuthState, String> {
+    // Removed first, so even a failed verification consumes the nonce. The user has to start the login again.
+    let session_nonce = session.remove_as::<String>(OAUTH_STATE_NONCE_SESSION_KEY);
+
+    let (payload_part, signature_part) = state.split_once('.').ok_or("Malformed state")?;
+    let signature = URL_SAFE_NO_PAD.decode(signature_part).map_err(|_| "Malformed state signature")?;
+    hmac_sign(payload_part.as_bytes())?.verify_slice(&signature).map_err(|_| "Invalid state signature")?; // constant time comparison
+
+    let payload_json = URL_SAFE_NO_PAD.decode(payload_part).map_err(|_| "Malformed state payload")?;
+    let payload: OAuthStatePayload = serde_json::from_slice(&payload_json).map_err(|_| "Malformed state payload")?;
+    if Utc::now().timestamp() - payload.issued_at > OAUTH_STATE_MAX_AGE_SEC {
+        return Err("Expired state".to_string());
+    }
+
+    match session_nonce {
+        Some(Ok(nonce)) if nonce == payload.nonce => Ok(VerifiedOAuthState { return_url: sanitize_return_url(Some(&payload.return_url)), provider: payload.provider }),
+        Some(Ok(_)) => Err("State nonce doesn't match the session".to_string()),
+        Some(Err(_)) | None => Err("No login in progress in this session (expired or replayed state)".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::{cookie::{Cookie, Key}, dev::ServiceResponse, test as actix_test, web, App, HttpResponse};
+
+    use super::*;
+    use crate::middleware::session_keys::init_test_session_keys;
+
+    #[test]
+    fn sanitize_return_url_allows_only_local_paths() {
+        assert_eq!(sanitize_return_url(Some("/robotrader?x=1")), "/robotrader?x=1");
+        assert_eq!(sanitize_return_url(None), "/");
+        for url in ["https://evil.com/", "//evil.com", "/\\evil.com", "evil.com", "javascript:alert(1)", "/a\r\nLocation: https://evil.com"] {
+            assert_eq!(sanitize_return_url(Some(url)), "/", "{:?}", url);
+        }
+    }
+
+    // /start?age=<sec> creates a state issued <sec> ago, /verify?state=.. verifies it. Both in the cookie session.
+    async fn start(session: Session, query: web::Query<HashMap<String, String>>) -> HttpResponse {
+        let age_sec: i64 = query.get("age").and_then(|age| age.parse().ok()).unwrap_or(0);
+        let state = create_oauth_state_issued_at(&session, Some("/x"), "google", Utc::now().timestamp() - age_sec).unwrap();
+        HttpResponse::Ok().body(state)
+    }
+
+    async fn verify(session: Session, query: web::Query<HashMap<String, String>>) -> HttpResponse {
+        match verify_oauth_state(&session, &query["state"]) {
+            Ok(verified_state) => HttpResponse::Ok().body(format!("{} {}", verified_state.provider, verified_state.return_url)),
+            Err(err) => HttpResponse::BadRequest().body(err),
+        }
+    }
+
+    fn session_cookie(resp: &ServiceResponse) -> Cookie<'static> {
+        resp.response().cookies().find(|cookie| cookie.name() == "id").expect("session cookie").into_owned()
+    }
+
+    macro_rules! test_app {
+        () => {{
+            init_test_session_keys();
+            actix_test::init_service(App::new()
+                .wrap(SessionMiddleware::new(CookieSessionStore::default(), Key::generate()))
+                .route("/start", web::get().to(start))
+                .route("/verify", web::get().to(verify))).await
+        }};
+    }
+
+    // => (state, session cookie)
+    macro_rules! start_login {
+        ($app:expr, $age_sec:expr) => {{
+            let resp = actix_test::call_service(&$app, actix_test::TestRequest::get().uri(&format!("/start?age={}", $age_sec)).to_request()).await;
+            let cookie = session_cookie(&resp);
+            (String::from_utf8(actix_test::read_body(resp).await.to_vec()).unwrap(), cookie)
+        }};
+    }
+
+    // => (is_ok, body, the session cookie after the verification, if it changed)
+    macro_rules! verify_login {
+        ($app:expr, $state:expr, $cookie:expr) => {{
+            let mut req = actix_test::TestRequest::get().uri(&format!("/verify?state={}", $state));
+            if let Some(cookie) = $cookie {
+                req = req.cookie(cookie);
+            }
+            let resp = actix_test::call_service(&$app, req.to_request()).await;
+            let is_ok = resp.status().is_success();
+            let cookie_after: Option<Cookie<'static>> = resp.response().cookies().find(|cookie| cookie.name() == "id").map(|cookie| cookie.into_owned());
+            (is_ok, String::from_utf8(actix_test::read_body(resp).await.to_vec()).unwrap(), cookie_after)
+        }};
+    }
+
+    #[actix_web::test]
+    async fn verify_accepts_the_state_of_the_session_once() {
+        let app = test_app!();
+        let (state, cookie) = start_login!(app, 0);
+        let (is_ok, body, cookie_after) = verify_login!(app, &state, Some(cookie));
+        assert!(is_ok);
+        assert_eq!(body, "google /x");
+        // Replayed in the same browser: the nonce was removed from its session at the first callback
+        let (is_ok, body, _) = verify_login!(app, &state, cookie_after);
+        assert!(!is_ok);
+        assert!(body.contains("expired or replayed"), "{}", body);
+    }
+
+    #[actix_web::test]
+    async fn verify_rejects_tampered_expired_and_foreign_states() {
+        let app = test_app!();
+        // Tampered: the returnUrl is changed, the signature is kept
+        let (state, cookie) = start_login!(app, 0);
+        let (payload_part, signature_part) = state.split_once('.').unwrap();
+        let payload_json = String::from_utf8(URL_SAFE_NO_PAD.decode(payload_part).unwrap()).unwrap().replace("\"/x\"", "\"https://evil.com\"");
+        let tampered_state = format!("{}.{}", URL_SAFE_NO_PAD.encode(payload_json), signature_part);
+        let (is_ok, body, _) = verify_login!(app, &tampered_state, Some(cookie));
+        assert!(!is_ok);
+        assert_eq!(body, "Invalid state signature");
+
+        // Expired
+        let (state, cookie) = start_login!(app, OAUTH_STATE_MAX_AGE_SEC + 1);
+        let (is_ok, body, _) = verify_login!(app, &state, Some(cookie));
+        assert!(!is_ok);
+        assert_eq!(body, "Expired state");
+
+        // Bound to another session: the state of session A at the callback of session B (that has its own login in progress), or without a session
+        let (state_a, _cookie_a) = start_login!(app, 0);
+        let (_state_b, cookie_b) = start_login!(app, 0);
+        let (is_ok, body, _) = verify_login!(app, &state_a, Some(cookie_b));
+        assert!(!is_ok);
+        assert_eq!(body, "State nonce doesn't match the session");
+        let (is_ok, _, _) = verify_login!(app, &state_a, None::<Cookie<'static>>);
+        assert!(!is_ok);
+
+        let (is_ok, _, _) = verify_login!(app, "not-a-state", None::<Cookie<'static>>);
+        assert!(!is_ok);
+    }
+}
@@ -0,0 +1,274 @@
+use std::{collections::HashMap, sync::{LazyLock, Mutex}};
+use reqwest::{header, Client};
+use serde::Deserialize;
+use serde_json::Value;
+
+use rqcommon::{rqhelper::MutexExt, utils::runningenv::RqCoreConfig};
+
+// Generic OAuth2/OIDC identity provider. Google is only one configured provider. Others (GitHub, a self-hosted Keycloak, a local mock IdP for testing) need only rqcore.config lines:
+// oauth_providers=google,keycloak                    // the first one is the default at /useraccount/login. Default: google
+// oauth_provider.keycloak.discovery_url=https://kc.example.com/realms/rq/.well-known/openid-configuration  // OIDC discovery: the endpoints are read from it
+// oauth_provider.keycloak.auth_url=...               // explicit endpoints (override the discovery). Needed for non-OIDC providers (e.g. GitHub)
+// oauth_provider.keycloak.token_url=...
+// oauth_provider.keycloak.userinfo_url=...
+// oauth_provider.keycloak.scope=openid email profile // Default: "openid email profile"
+// oauth_provider.keycloak.email_field=email          // JSON field names in the userinfo response. Defaults: email, name, email_verified
+// oauth_provider.keycloak.name_field=name
+// oauth_provider.keycloak.email_verified_field=email_verified // if the userinfo has it, it must be true: an unverified email can be anybody's (roles are per email)
+// oauth_provider.keycloak.extra_auth_params=access_type=offline&prompt=consent   // appended to the auth URL
+// Client ID and secret are per vhost (per domain), because the registered redirect URI contains the domain:
+// <vhost oauth_config_prefix>_<provider>_client_id, <vhost oauth_config_prefix>_<provider>_client_secret. E.g. rqcore_google_client_id, taconite_google_client_secret
+// Login: /useraccount/login?provider=keycloak&returnUrl=/x. The callback is the same for all providers: /useraccount/login/callback (the provider name is in the signed state).
+
+const OIDC_DEFAULT_SCOPE: &str = "openid email profile";
+
+// ---------- Global static variables ----------
+// discovery_url => endpoints. The discovery document is downloaded only at the first login with that provider.
+static OIDC_DISCOVERY_CACHE: LazyLock<Mutex<HashMap<String, OidcEndpoints>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// ---------- Class/struct definitions ----------
+#[derive(Debug, Clone, Default)]
+pub struct OidcEndpoints {
+    pub auth_url: String,
+    pub token_url: String,
+    pub userinfo_url: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct OidcDiscoveryDocument {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct OidcUser {
+    pub email: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct OidcProvider {
+    pub name: String,
+    discovery_url: Option<String>,
+    explicit_endpoints: OidcEndpoints, // empty strings are resolved from the discovery document
+    scope: String,
+    email_field: String,
+    name_field: String,
+    email_verified_field: String,
+    extra_auth_params: String,
+}
+
+impl OidcProvider {
+    // The setup before the providers became configurable. Google's endpoints are well known, so no discovery is needed (one less network roundtrip at login).
+    fn built_in_google() -> OidcProvider {
+        OidcProvider {
+            name: "google".to_string(),
+            discovery_url: None,
+            explicit_endpoints: OidcEndpoints {
+                auth_url: "https://accounts.google.com/o/oauth2/v2/auth".to_string(),
+                token_url: "https://oauth2.googleapis.com/token".to_string(),
+                userinfo_url: "https://www.googleapis.com/oauth2/v2/userinfo".to_string(),
+            },
+            scope: "https://www.googleapis.com/auth/userinfo.email https://www.googleapis.com/auth/userinfo.profile".to_string(),
+            email_field: "email".to_string(),
+            name_field: "name".to_string(),
+            email_verified_field: "verified_email".to_string(), // the v2 userinfo's name of it
+            extra_auth_params: "access_type=offline&prompt=consent".to_string(),
+        }
+    }
+
+    fn from_config(cfg: &RqCoreConfig, name: &str) -> OidcProvider {
+        let field = |field: &str| cfg.get(&format!("oauth_provider.{}.{}", name, field)).cloned();
+        // The google provider keeps its built-in defaults, but any of them can be overridden (e.g. to a local mock IdP)
+        let defaults = if name == "google" { OidcProvider::built_in_google() } else {
+            OidcProvider {
+                name: name.to_string(),
+                discovery_url: None,
+                explicit_endpoints: OidcEndpoints::default(),
+                scope: OIDC_DEFAULT_SCOPE.to_string(),
+                email_field: "email".to_string(),
+                name_field: "name".to_string(),
+                email_verified_field: "email_verified".to_string(),
+                extra_auth_params: String::new(),
+            }
+        };
+        OidcProvider {
+            name: name.to_string(),
+            discovery_url: field("discovery_url").or(defaults.discovery_url),
+            explicit_endpoints: OidcEndpoints {
+                auth_url: field("auth_url").unwrap_or(defaults.explicit_endpoints.auth_url),
+                token_url: field("token_url").unwrap_or(defaults.explicit_endpoints.token_url),
+                userinfo_url: field("userinfo_url").unwrap_or(defaults.explicit_endpoints.userinfo_url),
+            },
+            scope: field("scope").unwrap_or(defaults.scope),
+            email_field: field("email_field").unwrap_or(defaults.email_field),
+            name_field: field("name_field").unwrap_or(defaults.name_field),
+            email_verified_field: field("email_verified_field").unwrap_or(defaults.email_verified_field),
+            extra_auth_params: field("extra_auth_params").unwrap_or(defaults.extra_auth_params),
+        }
+    }
+
+    // None provider_name: the default (first) provider. Read from the current config at every login, so a config hot-reload can add providers.
+    pub fn find(cfg: &RqCoreConfig, provider_name: Option<&str>) -> Result<OidcProvider, String> {
+        let provider_names: Vec<&str> = match cfg.get("oauth_providers") {
+            Some(names) => names.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()).collect(),
+            None => vec!["google"],
+        };
+        let name = match provider_name {
+            Some(name) => *provider_names.iter().find(|n| **n == name).ok_or_else(|| format!("Unknown OAuth provider '{}'", name))?,
+            None => *provider_names.first().ok_or("oauth_providers is empty")?,
+        };
+        Ok(OidcProvider::from_config(cfg, name))
+    }
+
+    // Per vhost, because the redirect URI registered at the provider contains the domain.
+    pub fn client_credentials(&self, cfg: &RqCoreConfig, oauth_config_prefix: &str) -> Result<(String, String), String> {
+        let id_key = format!("{}_{}_client_id", oauth_config_prefix, self.name);
+        let secret_key = format!("{}_{}_client_secret", oauth_config_prefix, self.name);
+        let client_id = cfg.get(&id_key).ok_or_else(|| format!("{} not found in config", id_key))?;
+        let client_secret = cfg.get(&secret_key).ok_or_else(|| format!("{} not found in config", secret_key))?;
+        Ok((client_id.clone(), client_secret.clone()))
+    }
+
+    pub async fn endpoints(&self, client: &Client) -> Result<OidcEndpoints, String> {
+        let explicit = &self.explicit_endpoints;
+        if !explicit.auth_url.is_empty() && !explicit.token_url.is_empty() && !explicit.userinfo_url.is_empty() {
+            return Ok(explicit.clone());
+        }
+        let discovery_url = self.discovery_url.as_ref().ok_or_else(|| format!("OAuth provider '{}' has neither discovery_url nor auth_url/token_url/userinfo_url", self.name))?;
+
+        let cached = OIDC_DISCOVERY_CACHE.lock_ignore_poison().get(discovery_url).cloned(); // don't keep the lock over the await
+        let discovered = match cached {
+            Some(endpoints) => endpoints,
+            None => {
+                let doc = client.get(discovery_url).send().await
+                    .map_err(|err| format!("OIDC discovery download failed: {}", err))?
+                    .json::<OidcDiscoveryDocument>().await
+                    .map_err(|err| format!("OIDC discovery parse error: {}", err))?;
+                let endpoints = OidcEndpoints { auth_url: doc.authorization_endpoint, token_url: doc.token_endpoint, userinfo_url: doc.userinfo_endpoint.unwrap_or_default() };
+                OIDC_DISCOVERY_CACHE.lock_ignore_poison().insert(discovery_url.clone(), endpoints.clone());
+                endpoints
+            }
+        };
+
+        let choose = |explicit: &str, discovered: String| if explicit.is_empty() { discovered } else { explicit.to_string() };
+        let endpoints = OidcEndpoints {
+            auth_url: choose(&explicit.auth_url, discovered.auth_url),
+            token_url: choose(&explicit.token_url, discovered.token_url),
+            userinfo_url: choose(&explicit.userinfo_url, discovered.userinfo_url),
+        };
+        if endpoints.userinfo_url.is_empty() {
+            return Err(format!("OAuth provider '{}' has no userinfo endpoint", self.name));
+        }
+        Ok(endpoints)
+    }
+
+    pub fn auth_url(&self, endpoints: &OidcEndpoints, client_id: &str, redirect_uri: &str, state: &str) -> String {
+        let encode = |s: &str| percent_encoding::utf8_percent_encode(s, percent_encoding::NON_ALPHANUMERIC).to_string();
+        let mut url = format!("{}?client_id={}&redirect_uri={}&response_type=code&scope={}&state={}", endpoints.auth_url, encode(client_id), encode(redirect_uri), encode(&self.scope), encode(state));
+        if !self.extra_auth_params.is_empty() {
+            url.push('&');
+            url.push_str(&self.extra_auth_params);
+        }
+        url
+    }
+
+    // Exchanges the authorization code for an access token, then downloads the user info with it.
+    pub async fn fetch_user(&self, client: &Client, endpoints: &OidcEndpoints, code: &str, client_id: &str, client_secret: &str, redirect_uri: &str) -> Result<OidcUser, String> {
+        let params = [
+            ("code", code),
+            ("client_id", client_id),
+            ("client_secret", client_secret),
+            ("redirect_uri", redirect_uri),
+            ("grant_type", "authorization_code"),
+        ];
+        let token_resp = client.post(&endpoints.token_url)
+            .header(header::ACCEPT, "application/json") // GitHub returns form-urlencoded without it
+            .form(&params)
+            .send().await
+            .map_err(|err| format!("Token exchange failed: {}", err))?
+            .json::<TokenResponse>().await
+            .map_err(|err| format!("Token parse error: {}", err))?;
+
+        let user_info = client.get(&endpoints.userinfo_url)
+            .bearer_auth(&token_resp.access_token)
+            .header(header::ACCEPT, "application/json")
+            .header(header::USER_AGENT, "RqCore") // GitHub API requires a User-Agent
+            .send().await
+            .map_err(|err| format!("Failed to fetch userinfo: {}", err))?
+            .json::<Value>().await
+            .map_err(|err| format!("Userinfo parse error: {}", err))?;
+
+        let email = match user_info.get(&self.email_field).and_then(|v| v.as_str()) {
+            Some(email) if !email.is_empty() => email.to_string(),
+            _ => return Err(format!("Userinfo of provider '{}' has no '{}' field", self.name, self.email_field)),
+        };
+        // Some providers send it as a string ("true")
+        match user_info.get(&self.email_verified_field) {
+            None => {} // the provider doesn't tell it (e.g. GitHub's primary email)
+            Some(Value::Bool(true)) => {}
+            Some(Value::String(verified)) if verified == "true" => {}
+            Some(verified) => return Err(format!("The email {} is not verified at provider '{}' ({}: {})", email, self.name, self.email_verified_field, verified)),
+        }
+        let name = user_info.get(&self.name_field).and_then(|v| v.as_str()).unwrap_or("User").to_string();
+        Ok(OidcUser { email, name })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::middleware::mock_idp::{MockIdp, MOCK_IDP_VALID_CODE};
+
+    async fn fetch_mock_user(provider_name: &str, userinfo: Value, code: &str) -> Result<OidcUser, String> {
+        let mock_idp = MockIdp::start(userinfo).await;
+        let provider = OidcProvider::find(&mock_idp.provider_config(provider_name), None).unwrap();
+        let client = Client::new();
+        let endpoints = provider.endpoints(&client).await.unwrap();
+        let result = provider.fetch_user(&client, &endpoints, code, "mock-client-id", "mock-client-secret", "http://localhost/useraccount/login/callback").await;
+        mock_idp.stop().await;
+        result
+    }
+
+    #[actix_web::test]
+    async fn fetch_user_with_verified_email() {
+        let user = fetch_mock_user("keycloak", json!({"email": "a@example.com", "name": "A", "email_verified": true}), MOCK_IDP_VALID_CODE).await.unwrap();
+        assert_eq!((user.email.as_str(), user.name.as_str()), ("a@example.com", "A"));
+    }
+
+    #[actix_web::test]
+    async fn fetch_user_rejects_unverified_email() {
+        let result = fetch_mock_user("keycloak", json!({"email": "a@example.com", "email_verified": false}), MOCK_IDP_VALID_CODE).await;
+        assert!(result.unwrap_err().contains("not verified"));
+        let result = fetch_mock_user("keycloak", json!({"email": "a@example.com", "email_verified": "false"}), MOCK_IDP_VALID_CODE).await;
+        assert!(result.is_err());
+    }
+
+    #[actix_web::test]
+    async fn fetch_user_accepts_verified_as_string_and_a_missing_field() {
+        assert!(fetch_mock_user("keycloak", json!({"email": "a@example.com", "email_verified": "true"}), MOCK_IDP_VALID_CODE).await.is_ok());
+        assert!(fetch_mock_user("github", json!({"email": "a@example.com"}), MOCK_IDP_VALID_CODE).await.is_ok()); // the provider doesn't tell it
+    }
+
+    #[actix_web::test]
+    async fn fetch_user_google_checks_verified_email() {
+        // The built-in google provider (v2 userinfo: 'verified_email'), with the endpoints overridden to the mock IdP
+        let result = fetch_mock_user("google", json!({"email": "a@gmail.com", "verified_email": false}), MOCK_IDP_VALID_CODE).await;
+        assert!(result.is_err());
+        assert!(fetch_mock_user("google", json!({"email": "a@gmail.com", "verified_email": true}), MOCK_IDP_VALID_CODE).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn fetch_user_rejects_invalid_code_and_missing_email() {
+        assert!(fetch_mock_user("keycloak", json!({"email": "a@example.com", "email_verified": true}), "forged-code").await.is_err());
+        assert!(fetch_mock_user("keycloak", json!({"name": "A", "email_verified": true}), MOCK_IDP_VALID_CODE).await.is_err());
+    }
+}
@@ -1,11 +1,13 @@
 use std::{fmt::Write};
 use chrono::{Utc};
-use actix_web::{get, HttpResponse, Responder, http::header::ContentType};
+use actix_web::{get, middleware::from_fn, HttpResponse, Responder, http::header::ContentType};
 
 use broker_common::brokers_watcher::RQ_BROKERS_WATCHER;
-use crate::{SERVER_APP_START_TIME};
+use memdb::{mark_value_cache::get_quote_stream_health, quote_stream_health::QuoteStreamStatus};
+use crate::{SERVER_APP_START_TIME, middleware::{admin::html_escape, authorization::require_trader}, https_certs::{HTTPS_CERT_EXPIRY_WARNING_DAYS, HTTPS_CERT_RESOLVER}, services::rqtask_scheduler::{RqTaskRunStatus, RQ_TASK_SCHEDULER}};
 
-#[get("/serverdiagnostics")]
+// Every dynamic string is HTML escaped: error messages (task panics, feed errors) and cert subjects can contain anything.
+#[get("/serverdiagnostics", wrap = "from_fn(require_trader)")]
 async fn server_diagnostics() -> impl Responder {
      let mut sb = String::from("<html><body><h1>ServerDiagnostics</h1>");
 
@@ -39,9 +41,50 @@ async fn server_diagnostics() -> impl Responder {
     write!(sb, "Total gateways: {}<br>", gateways.len()).ok();
 
     for (broker_client, gateway) in gateways.iter() {
-        write!(sb, "Gateway {:?} → URL: {} | ClientID: {} | Connected: {}<br>", broker_client, gateway.connection_url, gateway.client_id, gateway.ib_client.is_some()).ok();
+        write!(sb, "Gateway {:?} → URL: {} | ClientID: {} | Connected: {}<br>", broker_client, html_escape(&gateway.connection_url), gateway.client_id, gateway.ib_client.is_some()).ok();
     }
 
+    drop(gateways_guard);
+
+    // MarkValueCache quote stream (Stopped and Ok in black, Degraded and Down in red)
+    write!(sb, "<h2>MarkValueCache</h2>").ok();
+    let quote_stream_health = get_quote_stream_health();
+    let color = match quote_stream_health.status { QuoteStreamStatus::Stopped | QuoteStreamStatus::Ok => "black", _ => "red" };
+    write!(sb, "Quote stream: <span style=\"color:{}\">{}</span><br>", color, html_escape(&quote_stream_health.summary())).ok();
+    for feed in quote_stream_health.feeds.iter() {
+        write!(sb, "Feed {} → Running: {} | Restarts: {} | Last error: {}<br>", feed.source, feed.is_running, feed.restart_count, html_escape(feed.last_error.as_deref().unwrap_or("none"))).ok();
+    }
+    if !quote_stream_health.no_data_tickers.is_empty() || !quote_stream_health.stale_tickers.is_empty() {
+        write!(sb, "No data: {}<br>Stale: {}<br>", html_escape(&quote_stream_health.no_data_tickers.join(", ")), html_escape(&quote_stream_health.stale_tickers.join(", "))).ok();
+    }
+
+    // HTTPS certs
+    write!(sb, "<h2>HTTPS certs</h2>").ok();
+    match HTTPS_CERT_RESOLVER.get() {
+        Some(cert_resolver) => {
+            for cert_info in cert_resolver.cert_infos() {
+                let days_until_expiry = cert_info.days_until_expiry();
+                let color = if days_until_expiry <= HTTPS_CERT_EXPIRY_WARNING_DAYS { "red" } else { "black" };
+                write!(sb, "{} ({}) → Expires: <span style=\"color:{}\">{} ({} days)</span> | File: {}<br>", html_escape(&cert_info.vhost_name), html_escape(&cert_info.subject), color, cert_info.not_after.format("%Y-%m-%d %H:%M"), days_until_expiry, html_escape(&cert_info.cert_path)).ok();
+            }
+        }
+        None => { write!(sb, "Not loaded yet.<br>").ok(); }
+    }
+
+    // Task scheduler: the tasks and their recent runs (failed, panicked, timed out and skipped runs in red)
+    write!(sb, "<h2>TaskScheduler</h2>").ok();
+    for task in RQ_TASK_SCHEDULER.list_tasks() {
+        write!(sb, "{} '{}' | State: {:?} | Overlap: {:?} | Timeout: {} | Next trigger: {}<br>", html_escape(&task.name), html_escape(&task.schedule), task.state, task.overlap_policy,
+            task.timeout_sec.map(|sec| format!("{}s", sec)).unwrap_or("none".to_string()), task.next_trigger_time.format("%Y-%m-%d %H:%M:%S")).ok();
+    }
+    write!(sb, "<h3>Last runs</h3><table border=\"1\" cellpadding=\"3\"><tr><th>#</th><th>Task</th><th>Started</th><th>Trigger</th><th>Duration</th><th>Result</th></tr>").ok();
+    for run in RQ_TASK_SCHEDULER.get_run_history(None).iter().take(30) {
+        let color = match run.status { RqTaskRunStatus::Ok | RqTaskRunStatus::Running => "black", _ => "red" };
+        write!(sb, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td style=\"color:{}\">{}</td></tr>", run.id, html_escape(&run.task_name), run.started_at.format("%Y-%m-%d %H:%M:%S"),
+            if run.is_manual { "manual" } else { "schedule" }, run.duration_ms.map(|ms| format!("{}ms", ms)).unwrap_or_default(), color, html_escape(&run.result_text())).ok();
+    }
+    write!(sb, "</table>").ok();
+
     HttpResponse::Ok().content_type(ContentType::html()).body(sb)
 }
 
@@ -0,0 +1,194 @@
+use std::{fs, io::Write, sync::OnceLock};
+use actix_web::{body::MessageBody, cookie::{Cookie, CookieJar, Key, SameSite}, dev::{ServiceRequest, ServiceResponse}, http::header::{self, HeaderValue}, middleware::Next, Error};
+
+use rqcommon::utils::runningenv::sensitive_config_folder_path;
+
+// The 'session' cookie content is encrypted with the session key. Anyone who knows the key can forge a session cookie (log in as anybody).
+// So the keys are in the sensitive config folder (not in the source code): one hex encoded 64 bytes key per line. '#' lines are comments.
+// The first key is the primary: new cookies are encrypted with it. The other keys are old keys: cookies encrypted with them are still accepted, and re-encrypted with the primary key.
+// Rotation without logging everyone out:
+// 1. Insert a new key as the first line (e.g. generate: openssl rand -hex 64), keep the old key(s) below. Restart.
+// 2. Active users get their cookies re-encrypted at their next request. After a few months, remove the old key(s). Restart.
+// If the file is missing, it is generated with one random key at the first run.
+
+pub const SESSION_KEYS_FILENAME: &str = "websrv_session_keys.txt";
+pub const SESSION_COOKIE_NAME: &str = "session";
+pub const SESSION_TTL_DAYS: i64 = 365;
+const SESSION_KEY_MIN_BYTES: usize = 64;
+const PLACEHOLDER_SESSION_KEY: &str = "A key that is long enough (64 bytes) to encrypt the session cookie content"; // only for development, if the keys file cannot be created
+
+// ---------- Global static variables ----------
+pub static SESSION_KEYS: OnceLock<SessionKeys> = OnceLock::new(); // set in actix_websrv_run()
+
+// ---------- Class/struct definitions ----------
+pub struct SessionKeys {
+    pub primary: Key,
+    pub old_keys: Vec<Key>,
+}
+
+impl SessionKeys {
+    // In production, it refuses to use the placeholder key (returns Err), because that is in the source code. The keys file can never contain it.
+    pub fn load_or_generate(is_production: bool) -> Result<SessionKeys, String> {
+        let keys_path = format!("{}{}", sensitive_config_folder_path(), SESSION_KEYS_FILENAME);
+        let content = match fs::read_to_string(&keys_path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => match generate_session_keys_file(&keys_path) {
+                Ok(content) => {
+                    log::warn!("Session keys file '{}' was missing. Generated a new key. Everybody has to log in again.", keys_path);
+                    content
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => { // another machine (shared config folder) generated it meanwhile: use that
+                    fs::read_to_string(&keys_path).map_err(|err| format!("Cannot read session keys file '{}': {}", keys_path, err))?
+                }
+                Err(err) if is_production => return Err(format!("Session keys file '{}' is missing and cannot be generated: {}. Refusing to use the placeholder key in production.", keys_path, err)),
+                Err(err) => {
+                    log::warn!("Session keys file '{}' is missing and cannot be generated: {}. Using the placeholder key (development only).", keys_path, err);
+                    return Ok(SessionKeys { primary: Key::from(PLACEHOLDER_SESSION_KEY.as_bytes()), old_keys: Vec::new() });
+                }
+            },
+            Err(err) => return Err(format!("Cannot read session keys file '{}': {}", keys_path, err)),
+        };
+
+        let mut keys = parse_session_keys(&content)?;
+        if keys.is_empty() {
+            return Err(format!("No session key in '{}'", keys_path));
+        }
+
+        let primary = keys.remove(0);
+        log::info!("Session keys loaded: 1 primary, {} old", keys.len());
+        Ok(SessionKeys { primary, old_keys: keys })
+    }
+}
+
+fn parse_session_keys(content: &str) -> Result<Vec<Key>, String> {
+    let mut keys: Vec<Key> = Vec::new();
+    for (line_no, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let key_bytes = hex::decode(line).map_err(|err| format!("{} line {}: not a hex encoded key: {}", SESSION_KEYS_FILENAME, line_no + 1, err))?;
+        if key_bytes.len() < SESSION_KEY_MIN_BYTES {
+            return Err(format!("{} line {}: key is {} bytes, minimum is {}", SESSION_KEYS_FILENAME, line_no + 1, key_bytes.len(), SESSION_KEY_MIN_BYTES));
+        }
+        // Key uses the first 64 bytes, so a longer key that starts with the placeholder's first 64 bytes is the same key
+        if key_bytes[..SESSION_KEY_MIN_BYTES] == PLACEHOLDER_SESSION_KEY.as_bytes()[..SESSION_KEY_MIN_BYTES] {
+            return Err(format!("{} line {}: the placeholder key from the source code is not allowed", SESSION_KEYS_FILENAME, line_no + 1));
+        }
+        keys.push(Key::from(&key_bytes));
+    }
+    Ok(keys)
+}
+
+// create_new: it never overwrites a key file (e.g. one generated meanwhile by another machine that shares the config folder).
+// On Unix, the file is created with 0600 (only the owner can read it), so the key is never readable by others, not even for a moment.
+fn generate_session_keys_file(keys_path: &str) -> Result<String, std::io::Error> {
+    let content = format!("# RqCore session cookie keys. The first key is the primary. See session_keys.rs for rotation.\n{}\n", hex::encode(Key::generate().master()));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(keys_path)?;
+    file.write_all(content.as_bytes())?;
+    Ok(content)
+}
+
+// Must be wrapped outside (after) the SessionMiddleware, so it runs before it.
+// If the session cookie was encrypted with an old key, it is replaced in the request with the re-encrypted one (so SessionMiddleware can read it),
+// and the re-encrypted cookie is sent back to the browser (if SessionMiddleware didn't set a new one anyway).
+pub async fn session_key_rotation_middleware(mut req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let reencrypted_value = match SESSION_KEYS.get() {
+        Some(session_keys) if !session_keys.old_keys.is_empty() => reencrypt_old_session_cookie(&req, session_keys),
+        _ => None, // no old keys: nothing to do (the usual case)
+    };
+
+    if let Some(reencrypted_value) = &reencrypted_value {
+        let cookie_header: String = req.headers().get_all(header::COOKIE)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .map(|pair| pair.trim())
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match Cookie::parse_encoded(pair) {
+                Ok(cookie) if cookie.name() == SESSION_COOKIE_NAME => Cookie::new(SESSION_COOKIE_NAME, reencrypted_value.as_str()).encoded().to_string(),
+                _ => pair.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        match HeaderValue::from_str(&cookie_header) {
+            Ok(header_value) => { req.head_mut().headers_mut().insert(header::COOKIE, header_value); }
+            Err(err) => log::error!("Re-encrypted cookie header is invalid: {}", err),
+        }
+    }
+
+    let mut res = next.call(req).await?;
+
+    if let Some(reencrypted_value) = reencrypted_value {
+        let is_session_cookie_set = res.response().cookies().any(|cookie| cookie.name() == SESSION_COOKIE_NAME);
+        if !is_session_cookie_set {
+            // The same attributes as the SessionMiddleware in main_web.rs
+            let cookie = Cookie::build(SESSION_COOKIE_NAME, reencrypted_value)
+                .path("/")
+                .secure(true)
+                .http_only(true)
+                .same_site(SameSite::None)
+                .max_age(time::Duration::days(SESSION_TTL_DAYS))
+                .finish();
+            match HeaderValue::from_str(&cookie.encoded().to_string()) { // percent-encoded, as the SessionMiddleware does
+                Ok(header_value) => { res.headers_mut().append(header::SET_COOKIE, header_value); }
+                Err(err) => log::error!("Cannot add re-encrypted session cookie: {}", err),
+            }
+        }
+    }
+    Ok(res)
+}
+
+// Returns the new cookie value if the session cookie was encrypted with an old key.
+fn reencrypt_old_session_cookie(req: &ServiceRequest, session_keys: &SessionKeys) -> Option<String> {
+    // Don't use req.cookie(), because that caches the parsed cookies in the request, and the SessionMiddleware would get the cached old cookie, not the replaced header.
+    let session_cookie = req.headers().get_all(header::COOKIE)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| Cookie::parse_encoded(pair.trim().to_string()).ok())
+        .find(|cookie| cookie.name() == SESSION_COOKIE_NAME)?;
+
+    let decrypt = |key: &Key| {
+        let mut jar = CookieJar::new();
+        jar.add_original(session_cookie.clone());
+        jar.private(key).get(SESSION_COOKIE_NAME).map(|cookie| cookie.value().to_string())
+    };
+    if decrypt(&session_keys.primary).is_some() {
+        return None; // already the primary key
+    }
+    let plain_value = session_keys.old_keys.iter().find_map(decrypt)?; // None: invalid or forged cookie. SessionMiddleware will ignore it.
+
+    let mut jar = CookieJar::new();
+    jar.private_mut(&session_keys.primary).add(Cookie::new(SESSION_COOKIE_NAME, plain_value));
+    let reencrypted_value = jar.get(SESSION_COOKIE_NAME)?.value().to_string();
+    log::info!("Session cookie encrypted with an old key was re-encrypted with the primary key");
+    Some(reencrypted_value)
+}
+
+// A random key for the tests that sign or encrypt with SESSION_KEYS (e.g. the OAuth state)
+#[cfg(test)]
+pub fn init_test_session_keys() {
+    SESSION_KEYS.get_or_init(|| SessionKeys { primary: Key::generate(), old_keys: Vec::new() });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_session_keys_rejects_the_placeholder() {
+        let key = hex::encode(Key::generate().master());
+        assert_eq!(parse_session_keys(&format!("# comment\n{}\n\n{}\n", key, hex::encode(Key::generate().master()))).unwrap().len(), 2);
+        assert!(parse_session_keys(&hex::encode(PLACEHOLDER_SESSION_KEY)).is_err());
+        assert!(parse_session_keys(&hex::encode(&PLACEHOLDER_SESSION_KEY.as_bytes()[..SESSION_KEY_MIN_BYTES])).is_err()); // the same Key
+        assert!(parse_session_keys(&format!("{}\n{}", key, hex::encode(PLACEHOLDER_SESSION_KEY))).is_err()); // as an old key too
+        assert!(parse_session_keys(&key[..100]).is_err()); // too short
+        assert!(parse_session_keys("not hex").is_err());
+    }
+}
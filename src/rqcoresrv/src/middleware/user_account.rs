@@ -1,13 +1,11 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 use actix_identity::Identity;
 use actix_session::Session;
-use actix_web::{get, http::header, web::Query, HttpMessage, HttpRequest, HttpResponse, Responder};
+use actix_web::{get, http::header, middleware::from_fn, web::{self, Query}, HttpMessage, HttpRequest, HttpResponse, Responder};
 use reqwest::Client;
-use serde::Deserialize;
 
-use percent_encoding::{percent_encode, percent_decode_str, NON_ALPHANUMERIC};
-use crate::{get_rqcore_config, main_web::{get_authorized_users}};
-// use rqcommon::utils::runningenv::{RqCoreConfig};
+use rqcommon::utils::runningenv::RqCoreConfig;
+use crate::{get_rqcore_config, middleware::{authorization::{require_viewer, AuthorizedUser}, oauth_state::{create_oauth_state, sanitize_return_url, verify_oauth_state}, oidc_provider::OidcProvider}, web_vhosts::{get_web_vhosts, read_index_template}};
 
 // Steps to create Google OAuth Client ID for a web app:
 // 1. Go to https://console.cloud.google.com (with gya***l1@gmail.com) and create/select a project.
@@ -17,73 +15,76 @@ use crate::{get_rqcore_config, main_web::{get_authorized_users}};
 // 5. Select "Web application" and add Authorized JavaScript Origins and Redirect URIs.
 // 6. Save to generate the Client ID and Client Secret.
 // 7. Redirect URI must exactly match scheme + host + path used in login callback.
+// Other identity providers (GitHub, Keycloak, a mock IdP) are configured in rqcore.config. See oidc_provider.rs.
 
-#[derive(Deserialize, Debug)]
-struct TokenResponse {
-    access_token: String,
-}
-
-#[derive(Deserialize, Debug)]
-struct GoogleUser {
-    email: String,
-    name: String,
-}
-
-fn get_google_redirect_uri(request: &HttpRequest) -> String {
+fn get_redirect_uri(request: &HttpRequest) -> String {
     let conn_info = request.connection_info().clone();
     let scheme = conn_info.scheme().to_string();
     let host = conn_info.host().to_string();
     format!("{scheme}://{host}/useraccount/login/callback")
 }
 
-fn get_google_oauth_config(http_req: &HttpRequest) -> Result<(String, String), HttpResponse> {
-    let host = http_req.connection_info().host().to_string();
-    let cfg = get_rqcore_config();
+// The running config (hot-reloaded), unless the App has its own web::Data<RqCoreConfig>: the tests give the config of a mock IdP, without changing the global one.
+fn get_oauth_config(http_req: &HttpRequest) -> Arc<RqCoreConfig> {
+    match http_req.app_data::<web::Data<RqCoreConfig>>() {
+        Some(cfg) => cfg.clone().into_inner(),
+        None => get_rqcore_config(),
+    }
+}
 
-    let (id_key, secret_key) = if host.contains("thetaconite.com") {
-        ("taconite_google_client_id", "taconite_google_client_secret")
-    } else {
-        ("rqcore_google_client_id", "rqcore_google_client_secret")
-    };
+// The provider and its client credentials for the vhost (domain) of the request.
+fn get_oauth_provider(http_req: &HttpRequest, provider_name: Option<&str>) -> Result<(OidcProvider, String, String), HttpResponse> {
+    let host = http_req.connection_info().host().to_string();
+    let cfg = get_oauth_config(http_req);
 
-    let client_id = match cfg.get(id_key) {
-        Some(value) => value.to_string(),
-        None => {
-            log::error!("{} not found in config", id_key);
-            return Err(HttpResponse::InternalServerError().body("OAuth config error"));
+    let provider = match OidcProvider::find(&cfg, provider_name) {
+        Ok(provider) => provider,
+        Err(err) => {
+            log::warn!("{}", err);
+            return Err(HttpResponse::BadRequest().body("Unknown login provider"));
         }
     };
-
-    let client_secret = match cfg.get(secret_key) {
-        Some(value) => value.to_string(),
-        None => {
-            log::error!("{} not found in config", secret_key);
-            return Err(HttpResponse::InternalServerError().body("OAuth config error"));
+    let oauth_config_prefix = &get_web_vhosts().find_by_host(&host).oauth_config_prefix;
+    match provider.client_credentials(&cfg, oauth_config_prefix) {
+        Ok((client_id, client_secret)) => Ok((provider, client_id, client_secret)),
+        Err(err) => {
+            log::error!("{}", err);
+            Err(HttpResponse::InternalServerError().body("OAuth config error"))
         }
-    };
-
-    Ok((client_id, client_secret))
+    }
 }
 
 #[get("/useraccount/login")]
-pub async fn login(request: HttpRequest, id: Option<Identity>, query: Query<HashMap<String, String>>) -> impl Responder { 
+pub async fn login(request: HttpRequest, id: Option<Identity>, query: Query<HashMap<String, String>>, session: Session) -> impl Responder { 
     if id.is_some() {
-        let return_url = query.get("returnUrl").cloned().unwrap_or("/".to_string());
+        let return_url = sanitize_return_url(query.get("returnUrl").map(|url| url.as_str()));
         return HttpResponse::Found()
             .append_header(("Location", return_url))
             .finish();
     }
 
-    let (google_client_id, _) = match get_google_oauth_config(&request) {
+    let (provider, client_id, _) = match get_oauth_provider(&request, query.get("provider").map(|p| p.as_str())) {
         Ok(value) => value,
         Err(resp) => return resp,
     };
-    let return_url = query.get("returnUrl").cloned().unwrap_or("/".to_string());
-    let redirect_uri = get_google_redirect_uri(&request);
+    let endpoints = match provider.endpoints(&Client::new()).await {
+        Ok(endpoints) => endpoints,
+        Err(err) => {
+            log::error!("{}", err);
+            return HttpResponse::InternalServerError().body("OAuth provider error");
+        }
+    };
+    let redirect_uri = get_redirect_uri(&request);
 
-    let scope = percent_encode(b"https://www.googleapis.com/auth/userinfo.email https://www.googleapis.com/auth/userinfo.profile",NON_ALPHANUMERIC).to_string();
-    let state = percent_encode(return_url.as_bytes(), NON_ALPHANUMERIC).to_string();
-    let auth_url = format!("https://accounts.google.com/o/oauth2/v2/auth?client_id={}&redirect_uri={}&response_type=code&scope={}&access_type=offline&prompt=consent&state={}", google_client_id, redirect_uri, scope, state);
+    // Signed, session bound state with a random nonce, the returnUrl and the provider (see oauth_state.rs)
+    let state = match create_oauth_state(&session, query.get("returnUrl").map(|url| url.as_str()), &provider.name) {
+        Ok(state) => state,
+        Err(err) => {
+            log::error!("OAuth state creation failed: {}", err);
+            return HttpResponse::InternalServerError().body("Login failed");
+        }
+    };
+    let auth_url = provider.auth_url(&endpoints, &client_id, &redirect_uri, &state);
 
     // After the 'login' or 'logout' http call is processed by the server, the Response header "location: /" will redirect the browser.
     // This 'login' or 'logout' call is the place when we can inject to ask the browser for cache busting for the whole domain: 
@@ -99,65 +100,45 @@ pub async fn login(request: HttpRequest, id: Option<Identity>, query: Query<Hash
 }
 
 #[get("/useraccount/login/callback")]
-pub async fn google_callback(request: HttpRequest, query: Query<HashMap<String, String>>, session: Session) -> impl Responder {
+pub async fn login_callback(request: HttpRequest, query: Query<HashMap<String, String>>, session: Session) -> impl Responder {
+    // The state is verified first, before the code is exchanged, so a forged callback doesn't even reach the provider.
+    let verified_state = match query.get("state").map(|state| verify_oauth_state(&session, state)) {
+        Some(Ok(verified_state)) => verified_state,
+        Some(Err(err)) => {
+            log::warn!("OAuth callback rejected: {}", err);
+            return HttpResponse::BadRequest().content_type("text/plain").body("Invalid login state. Please start the login again.");
+        }
+        None => {
+            log::warn!("OAuth callback rejected: missing state");
+            return HttpResponse::BadRequest().content_type("text/plain").body("Missing login state");
+        }
+    };
+
     let code = match query.get("code") {
         Some(code) => code,
         None => {
             return HttpResponse::BadRequest().content_type("text/plain").body("Missing authorization code");
         }
     };
-    let redirect_uri = get_google_redirect_uri(&request);
+    let redirect_uri = get_redirect_uri(&request);
 
-    let (google_client_id, google_client_secret) = match get_google_oauth_config(&request) {
+    let (provider, client_id, client_secret) = match get_oauth_provider(&request, Some(&verified_state.provider)) {
         Ok(value) => value,
         Err(resp) => return resp,
     };
 
     let client = Client::new();
-    let params = [
-        ("code", code.as_str()),
-        ("client_id", google_client_id.as_str()),
-        ("client_secret", google_client_secret.as_str()),
-        ("redirect_uri", redirect_uri.as_str()),
-        ("grant_type", "authorization_code"),
-    ];
-    let token_resp = match client
-        .post("https://oauth2.googleapis.com/token")
-        .form(&params)
-        .send()
-        .await
-        {
-            Ok(resp) => match resp.json::<TokenResponse>().await {
-                Ok(token) => token,
-                Err(e) => {
-                    log::error!("Token parse error: {}", e);
-                    return HttpResponse::InternalServerError().body("Token parsing failed");
-                }
-            },
-            Err(e) => {
-                log::error!("Token exchange failed: {}", e);
-                return HttpResponse::InternalServerError().body("Token exchange failed");
-            }
-        };
-
-    let user_info = match client
-        .get("https://www.googleapis.com/oauth2/v2/userinfo")
-        .bearer_auth(&token_resp.access_token)
-        .send()
-        .await
-        { 
-            Ok(resp) => match resp.json::<GoogleUser>().await {
-                Ok(info) => info,
-                Err(e) => {
-                    log::error!("Userinfo parse error: {}", e);
-                    return HttpResponse::InternalServerError().body("Failed to parse user info");
-                }
-            },
-            Err(e) => {
-                log::error!("Failed to fetch userinfo: {}", e);
-                return HttpResponse::InternalServerError().body("Failed to fetch user info");
-            }
-        };
+    let user_info = match provider.endpoints(&client).await {
+        Ok(endpoints) => provider.fetch_user(&client, &endpoints, code, &client_id, &client_secret, &redirect_uri).await,
+        Err(err) => Err(err),
+    };
+    let user_info = match user_info {
+        Ok(user_info) => user_info,
+        Err(err) => {
+            log::error!("OAuth login with '{}' failed: {}", provider.name, err);
+            return HttpResponse::InternalServerError().body("Login failed");
+        }
+    };
 
     if let Err(e) = session.insert("user_email", &user_info.email) {
         log::error!("Session insert error: {}", e);
@@ -167,21 +148,17 @@ pub async fn google_callback(request: HttpRequest, query: Query<HashMap<String,
         log::error!("Session insert error: {}", e);
     }
 
+    if let Err(e) = session.insert("user_auth_provider", &provider.name) {
+        log::error!("Session insert error: {}", e);
+    }
+
     if let Err(e) = Identity::login(&request.extensions(), user_info.email.clone()) {
         log::error!("Identity login error: {}", e);
         return HttpResponse::InternalServerError().body("Login failed");
     }
 
-    let redirect_url = match query.get("state") {
-        Some(encoded) => match percent_decode_str(encoded).decode_utf8() {
-            Ok(decoded) => decoded.into_owned(),
-            Err(_) => "/".to_string(),
-        },
-        None => "/".to_string(),
-    };
-
     HttpResponse::Found()
-        .append_header((header::LOCATION, redirect_url))
+        .append_header((header::LOCATION, verified_state.return_url))
         .finish()
 }
 
@@ -223,43 +200,29 @@ pub async fn user_infor(session: Session) -> impl Responder {
     }
 }
 
-#[get("/useraccount/authorized_sample")]
-pub async fn authorized_sample(session: Session) -> impl Responder {
-    // let auth_users = match AUTHORIZED_USERS_LOCK.get() {
-    //     Some(users) => users,
-    //     None => return  HttpResponse::InternalServerError().body("Server configuration error")
-    // };
-
-    let auth_users = get_authorized_users();
-
-    match session.get::<String>("user_email") {
-        Ok(Some(email)) if auth_users.contains(&email) => {
-            HttpResponse::Ok().body(format!("Welcome, authorized user: {}", email))
-        }
-        Ok(Some(email)) => {
-            HttpResponse::Forbidden().body(format!("Access denied: {}", email))
-        }
-        _ => HttpResponse::Unauthorized().body("Login required"),
-    }
+#[get("/useraccount/authorized_sample", wrap = "from_fn(require_viewer)")]
+pub async fn authorized_sample(user: AuthorizedUser) -> impl Responder {
+    HttpResponse::Ok().body(format!("Welcome, authorized user: {} (role: {})", user.email, user.role))
 }
 
-const RQCORE_INDEX: &str = include_str!("../../static/index.html"); // compile time operation. Read the file and represent it as a const String. Increases the EXE size. Index.html changes need recompilation. But that is fine.
-const RQCORE_NOUSER: &str = include_str!("../../static/index_nouser.html");
-const TACONITE_INDEX: &str = include_str!("../../static/taconite/index.html");
-const TACONITE_NOUSER: &str = include_str!("../../static/taconite/index_nouser.html");
 const ALLDOMAIN_USER_UNAUTHORIZED_INDEX: &str = r#"You are logged in as {email}, but your user is not <b>authorized</b>.<p>Please logout and login with another user. <a href="/useraccount/logout">Logout</a></p>"#;
 
 #[get("/")] // Without declaring it, this is also called for "/index.html", which is a standard practice.
 pub async fn root_index(http_req: HttpRequest, id: Option<Identity>, session: Session) -> impl Responder {
     let host = http_req.connection_info().host().to_string();
     let is_logged_in = id.as_ref().is_some_and(|i| i.id().is_ok());
-    let is_taconite = host.contains("thetaconite.com");
-    println!("Host: {}, is_taconite: {}", http_req.connection_info().host(), is_taconite);
+    let vhost = get_web_vhosts().find_by_host(&host);
+    log::debug!("Host: {}, vhost: {}", host, vhost.name);
 
-    // 1. Choose which file to serve
-    let (index, index_nouser) = if is_taconite {(TACONITE_INDEX, TACONITE_NOUSER)} else {(RQCORE_INDEX, RQCORE_NOUSER)};
+    // 1. Choose which file to serve. Read at every request (not include_str!), so index.html changes don't need recompilation. It is small and the OS caches it.
     if !is_logged_in {
-        return HttpResponse::Ok().insert_header((header::CONTENT_TYPE, "text/html; charset=utf-8")).body(index_nouser);
+        return match read_index_template(&vhost.index_nouser_path) {
+            Ok(index_nouser) => HttpResponse::Ok().insert_header((header::CONTENT_TYPE, "text/html; charset=utf-8")).body(index_nouser),
+            Err(err) => {
+                log::error!("{}", err);
+                HttpResponse::InternalServerError().body("Index template error")
+            }
+        };
     }
     // 2. Get the email
     let email =  match session.get::<String>("user_email") {
@@ -273,14 +236,119 @@ pub async fn root_index(http_req: HttpRequest, id: Option<Identity>, session: Se
             return HttpResponse::InternalServerError().body("Session error");
         }
     };
-    // 3. Get the authorized users
-    let auth_users = get_authorized_users();
-    // 4. Serve the modified HTML
-    let html = if auth_users.contains(&email) {
-        index.replace("{{USER_EMAIL}}", &email)
+    // 3. Serve the modified HTML, if the user has any role
+    let html = if AuthorizedUser::from_session(&session).is_some() {
+        match read_index_template(&vhost.index_path) {
+            Ok(index) => index.replace("{{USER_EMAIL}}", &email),
+            Err(err) => {
+                log::error!("{}", err);
+                return HttpResponse::InternalServerError().body("Index template error");
+            }
+        }
     } else {
         ALLDOMAIN_USER_UNAUTHORIZED_INDEX.replace("{email}", &email)
     };
 
     HttpResponse::Ok().insert_header((header::CONTENT_TYPE, "text/html; charset=utf-8")).body(html)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use actix_identity::IdentityMiddleware;
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::{cookie::{Cookie, Key}, dev::ServiceResponse, test as actix_test, App};
+    use serde_json::json;
+
+    use super::*;
+    use crate::middleware::{mock_idp::{MockIdp, MOCK_IDP_VALID_CODE}, session_keys::init_test_session_keys};
+
+    fn session_cookie(resp: &ServiceResponse) -> Option<Cookie<'static>> {
+        resp.response().cookies().find(|cookie| cookie.name() == "id").map(|cookie| cookie.into_owned())
+    }
+
+    fn location(resp: &ServiceResponse) -> String {
+        resp.headers().get(header::LOCATION).and_then(|location| location.to_str().ok()).unwrap_or_default().to_string()
+    }
+
+    fn query_param(url: &str, name: &str) -> String {
+        let query = url.split_once('?').map(|(_, query)| query).unwrap_or_default();
+        let value = query.split('&').find_map(|pair| pair.strip_prefix(&format!("{}=", name))).unwrap_or_default();
+        percent_encoding::percent_decode_str(value).decode_utf8_lossy().to_string()
+    }
+
+    // The login flow against a mocked Google token and userinfo endpoint
+    #[actix_web::test]
+    async fn login_callback_with_mocked_google() {
+        init_test_session_keys();
+        let mock_idp = MockIdp::start(json!({"email": "trader@example.com", "name": "Trader", "verified_email": true})).await;
+        let app = actix_test::init_service(App::new()
+            .app_data(web::Data::new(mock_idp.provider_config("google")))
+            .wrap(IdentityMiddleware::default())
+            .wrap(SessionMiddleware::new(CookieSessionStore::default(), Key::generate()))
+            .service(login)
+            .service(login_callback)
+            .service(user_infor)).await;
+
+        // => (state, session cookie)
+        macro_rules! start_login {
+            ($return_url:expr) => {{
+                let resp = actix_test::call_service(&app, actix_test::TestRequest::get().uri(&format!("/useraccount/login?returnUrl={}", $return_url)).to_request()).await;
+                assert_eq!(resp.status(), 302);
+                let auth_url = location(&resp);
+                assert!(auth_url.starts_with(&mock_idp.endpoints().auth_url), "{}", auth_url);
+                (query_param(&auth_url, "state"), session_cookie(&resp).expect("session cookie"))
+            }};
+        }
+        macro_rules! callback {
+            ($state:expr, $code:expr, $cookie:expr) => {{
+                let encode = |s: &str| percent_encoding::utf8_percent_encode(s, percent_encoding::NON_ALPHANUMERIC).to_string();
+                let mut req = actix_test::TestRequest::get().uri(&format!("/useraccount/login/callback?state={}&code={}", encode($state), $code));
+                if let Some(cookie) = $cookie {
+                    req = req.cookie(cookie);
+                }
+                actix_test::call_service(&app, req.to_request()).await
+            }};
+        }
+
+        // Valid: redirected to the returnUrl, logged in
+        let (state, cookie) = start_login!("/robotrader");
+        let resp = callback!(&state, MOCK_IDP_VALID_CODE, Some(cookie));
+        assert_eq!(resp.status(), 302);
+        assert_eq!(location(&resp), "/robotrader");
+        let logged_in_cookie = session_cookie(&resp).expect("session cookie");
+        let resp = actix_test::call_service(&app, actix_test::TestRequest::get().uri("/useraccount/userinfo").cookie(logged_in_cookie.clone()).to_request()).await;
+        assert_eq!(resp.status(), 200);
+        let body = String::from_utf8(actix_test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("trader@example.com"), "{}", body);
+
+        // Replayed: the same state again in the logged in session
+        let resp = callback!(&state, MOCK_IDP_VALID_CODE, Some(logged_in_cookie));
+        assert_eq!(resp.status(), 400);
+
+        // Tampered: a signed state with another character
+        let (state, cookie) = start_login!("/robotrader");
+        let replacement = if &state[1..2] == "A" { "B" } else { "A" };
+        let tampered_state = format!("{}{}{}", &state[..1], replacement, &state[2..]);
+        assert_eq!(callback!(&tampered_state, MOCK_IDP_VALID_CODE, Some(cookie)).status(), 400);
+
+        // Bound to another session, or no session
+        let (state_a, _cookie_a) = start_login!("/a");
+        let (_state_b, cookie_b) = start_login!("/b");
+        assert_eq!(callback!(&state_a, MOCK_IDP_VALID_CODE, Some(cookie_b)).status(), 400);
+        assert_eq!(callback!(&state_a, MOCK_IDP_VALID_CODE, None::<Cookie<'static>>).status(), 400);
+
+        // A non-same-origin returnUrl is replaced by "/"
+        for return_url in ["https%3A%2F%2Fevil.com%2F", "%2F%2Fevil.com", "%2F%5Cevil.com"] {
+            let (state, cookie) = start_login!(return_url);
+            let resp = callback!(&state, MOCK_IDP_VALID_CODE, Some(cookie));
+            assert_eq!(resp.status(), 302);
+            assert_eq!(location(&resp), "/", "{}", return_url);
+        }
+
+        // A code that the IdP rejects: no login
+        let (state, cookie) = start_login!("/robotrader");
+        let resp = callback!(&state, "forged-code", Some(cookie));
+        assert_eq!(resp.status(), 500);
+
+        mock_idp.stop().await;
+    }
+}
@@ -1,9 +1,11 @@
 use chrono::{Datelike, Local, Utc};
 use serde::Deserialize;
 use std::{fmt::Write, collections::{HashMap, HashSet}, fs, path::{Path, PathBuf}, time::{SystemTime}};
-use rqcommon::{log_and_println, log_and_if_println, utils::time::{benchmark_elapsed_time_async}};
+use serde_json::json;
+use rqcommon::{log_and_println, log_and_if_println, rqhelper::MutexExt, utils::{rqevent_hub::{RqEventTopic, RQ_EVENT_HUB}, time::{benchmark_elapsed_time_async}}};
 
 use broker_common::brokers_watcher::{RqOrder, RqOrderType};
+use memdb::mark_value_cache::{RQ_MARK_VALUE_CACHE, TICKER_GROUP_FASTRUNNER};
 use crate::robotrader::robotrader::RoboTrader;
 
 #[derive(Debug, Deserialize)]
@@ -498,6 +500,7 @@ impl FastRunner {
         }
         self.has_trading_ever_started = true;
 
+        Self::publish_signals("SA_PQP", &target_action_date, &rqorders, self.is_simulation);
         RoboTrader::place_orders("SA_PQP", rqorders, self.is_simulation, &mut self.user_log).await;
     }
 
@@ -671,11 +674,17 @@ impl FastRunner {
         }
         self.has_trading_ever_started = true;
 
+        Self::publish_signals("SA_AP", &target_action_date, &rqorders, self.is_simulation);
         RoboTrader::place_orders("SA_AP", rqorders, self.is_simulation, &mut self.user_log).await;
     }
 
     // ---------- Helpers ----------
 
+    fn publish_signals(strategy_name: &str, target_action_date: &str, rqorders: &[RqOrder], is_simulation: bool) {
+        let orders: Vec<_> = rqorders.iter().map(|o| json!({"order_type": o.order_type.to_string(), "ticker": o.ticker, "company_name": o.company_name, "pos_market_value": o.pos_market_value})).collect();
+        RQ_EVENT_HUB.publish(RqEventTopic::Signals, "signals_detected", json!({"strategy": strategy_name, "target_action_date": target_action_date, "is_simulation": is_simulation, "orders": orders}));
+    }
+
     const COOKIES_FILE_PATH: &'static str = "../../../rqcore_data/fast_run_1_headers.txt";
     // Elapsed Time of ensure_cookies_loaded(): 
     // first file read: 13,643us, 
@@ -862,6 +871,21 @@ impl FastRunner {
         (sa_screener_buy_tickers, Vec::new())
     }
 
+    // The tickers the next rebalance can trade: the buy/sell candidates and the current positions (potential sells). Set as the 'fastrunner' group of the MarkValueCache universe,
+    // so the quote stream (started after this) has their prices. If SA gives nothing (e.g. download error), the previous group is kept.
+    pub async fn populate_mark_value_universe() {
+        let (buy_candidate_tickers, sell_candidate_tickers) = FastRunner::get_sa_candidate_tickers().await;
+        let pqp_position_tickers = FastRunner::get_pqp_positions_tickers().await;
+        let tickers: Vec<String> = buy_candidate_tickers.into_iter().chain(sell_candidate_tickers).chain(pqp_position_tickers).collect();
+        if tickers.is_empty() {
+            log_and_println!("FastRunner.populate_mark_value_universe(): no candidate or position tickers. Keeping the previous universe.");
+            return;
+        }
+        let mut mark_value_cache = RQ_MARK_VALUE_CACHE.lock_ignore_poison();
+        mark_value_cache.set_ticker_group(TICKER_GROUP_FASTRUNNER, &tickers);
+        log_and_println!("FastRunner.populate_mark_value_universe(): {} FastRunner ticker(s), universe: {} ticker(s).", tickers.len(), mark_value_cache.get_ticker_universe().len());
+    }
+
     // This Analysis finishes faster than the main Portfolio History download. PortfHistory: 400KB (first: 3800ms), Analysis: 85KB (first: 1200ms).
     async fn get_new_transactions_from_analysis_pqp(cookies: String, target_action_date: String) -> (String, Vec<TransactionEvent>) {
         const URL_PQP_ANALYSIS: &str = "https://seekingalpha.com/api/v3/quant_pro_portfolio/articles?include=primaryTickers%2CsecondaryTickers%2Cauthor%2CsecondaryAuthor&lang=en";
@@ -1,68 +1,88 @@
 use {
-    std::{fmt::Write, future::Future, pin::Pin, sync::{Mutex}},
+    std::{collections::HashMap, fmt::Write, future::Future, pin::Pin, sync::{LazyLock, Mutex}, time::Duration},
     chrono::{DateTime, NaiveTime, TimeZone, Utc},
     chrono_tz::US::Eastern,
+    serde::Serialize,
 };
 
-use rqcommon::{log_and_println, rqhelper::MutexExt, utils::{rqemail::RqEmail, time::localtimeonly2future_datetime_tz}};
-use memdb::mark_value_cache::RQ_MARK_VALUE_CACHE;
+use serde_json::json;
 
-use crate::{get_rqcore_config, robotrader::fast_runner::FastRunner, services::rqtask_scheduler::RqTask};
+use rqcommon::{log_and_println, rqhelper::MutexExt, utils::{rqemail::RqEmail, rqevent_hub::{RqEventTopic, RQ_EVENT_HUB}, rqschedule::RqSchedule}};
+use memdb::mark_value_cache::QuoteStreamGuard;
+
+use crate::{get_rqcore_config, robotrader::fast_runner::FastRunner, services::rqtask_scheduler::{RqTask, RqTaskMissedRunPolicy, RqTaskOverlapPolicy, RqTaskResult, RqTaskRunFlags}};
+
+// Warm up (20s) + the live trading loop (max 4.5 min) + emails. A longer run is stuck (e.g. a hanging HTTP download).
+const FASTRUNNER_TASK_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// ---------- Global static variables ----------
+// task name => summary of its last run. Only in memory, so it is empty after a restart. Served at /api/robotrader/fastrunner/lastruns.
+pub static FASTRUNNER_LAST_RUNS: LazyLock<Mutex<HashMap<String, FastRunnerRunSummary>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FastRunnerRunSummary {
+    pub task_name: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub is_simulation: bool,
+    pub is_manual_user_forcerun: bool,
+    pub json_target_date: String,
+    pub has_trading_ever_started: bool,
+    pub user_log: String,
+}
+
+fn store_last_run_summary(task_name: &str, started_at: DateTime<Utc>, fast_runner: &FastRunner, is_manual_user_forcerun: bool, json_target_date: &str) {
+    let summary = FastRunnerRunSummary {
+        task_name: task_name.to_string(),
+        started_at,
+        ended_at: Utc::now(),
+        is_simulation: fast_runner.is_simulation,
+        is_manual_user_forcerun,
+        json_target_date: json_target_date.to_string(),
+        has_trading_ever_started: fast_runner.has_trading_ever_started,
+        user_log: fast_runner.user_log.clone(),
+    };
+    FASTRUNNER_LAST_RUNS.lock_ignore_poison().insert(task_name.to_string(), summary);
+}
 
 // TODO: There is a lot of code duplication for FastRunnerPqpTask FastRunnerApTask. Unify them to FastRunnerPqpApTask or FastRunnerSaTask
 
 // ---------- FastRunner PQP (daily 11:59 ET) ----------
 pub struct FastRunnerPqpTask {
     name: String,
-    next_time: Mutex<DateTime<Utc>>,
-    pub is_manual_user_forcerun: bool,
+    schedule: RqSchedule,
 }
 
 impl FastRunnerPqpTask {
     pub fn new() -> Self {
-        let next_utc = Self::get_next_trigger_time_impl();
         FastRunnerPqpTask {
             name: "FastRunnerPqpTask".to_string(),
-            next_time: Mutex::new(next_utc),
-            is_manual_user_forcerun: false,
+            // we run 4 times daily: 3x Simulation, 1x RealTrading at 11:59 ET. USA market opens at 9:30 ET, so around 9:45 ET is the earliest.
+            // 11:59:10: consider mark_value_cache warm up time, so trigger earlier than noon. (For manual test, add e.g. 15:26:00.)
+            schedule: RqSchedule::parse("09:45:10, 11:01:10, 11:30:10, 11:59:10 ET on trading days").unwrap(),
         }
     }
-
-    fn get_next_trigger_time_impl() -> DateTime<Utc> { // we run 3 times daily: 2x Simulation, 1x RealTrading at 11:01 ET, 11:30 ET, 11:59 ET
-        let tz = Eastern;
-        let targets_tz = [
-            // NaiveTime::from_hms_opt(15, 26, 00).unwrap(), // for manual test
-            NaiveTime::from_hms_opt(9, 45, 10).unwrap(), // USA market opens at 9:30 ET, so around 9:45 ET is the earliest.
-            NaiveTime::from_hms_opt(11, 1, 10).unwrap(),
-            NaiveTime::from_hms_opt(11, 30, 10).unwrap(),
-            NaiveTime::from_hms_opt(11, 59, 10).unwrap(), // consider mark_value_cache warm up time, so trigger earlier
-        ];
-
-        targets_tz
-            .into_iter()
-            .map(|time| localtimeonly2future_datetime_tz(tz, time).to_utc())
-            .min() // pick the earliest UTC time
-            .unwrap()
-    }
 }
 
 impl RqTask for FastRunnerPqpTask {
     fn name(&self) -> &str { &self.name }
 
-    fn get_next_trigger_time(&self) -> DateTime<Utc> {
-        *self.next_time.lock().unwrap()
-    }
+    fn schedule(&self) -> &RqSchedule { &self.schedule }
 
-    fn update_next_trigger_time(&self) {
-        let next_utc = Self::get_next_trigger_time_impl();
-        let mut next = self.next_time.lock().unwrap();
-        *next = next_utc;
-    }
+    // A restart around 11:59 ET must not lose the live run. Within 2 minutes after the trigger, run() still recognizes the live trading time (close to 12:00 ET).
+    fn missed_run_policy(&self) -> RqTaskMissedRunPolicy { RqTaskMissedRunPolicy::RunImmediately }
+
+    fn missed_run_grace(&self) -> Duration { Duration::from_secs(2 * 60) }
 
-    fn run(&self) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+    fn overlap_policy(&self) -> RqTaskOverlapPolicy { RqTaskOverlapPolicy::Queue } // a slow 11:30 simulation run must not make the 11:59 live run skipped, nor run in parallel with it
+
+    fn timeout(&self) -> Option<Duration> { Some(FASTRUNNER_TASK_TIMEOUT) }
+
+    fn run(&self, flags: RqTaskRunFlags) -> Pin<Box<dyn Future<Output = RqTaskResult> + Send + '_>> {
         // let this = self;
         Box::pin(async move {
-             log_and_println!("{} FastRunnerPqpTask run() started", Utc::now().format("%H:%M:%S%.3f"));
+            let started_at = Utc::now();
+            log_and_println!("{} FastRunnerPqpTask run() started", started_at.format("%H:%M:%S%.3f"));
 
             let mut fast_runner = FastRunner::new();
             fast_runner.init().await;
@@ -77,7 +97,7 @@ impl RqTask for FastRunnerPqpTask {
 
             fast_runner.is_simulation = !is_last_scheduled_today; // live trading is only if it is the last scheduled time (11:59 ET)
 
-            if self.is_manual_user_forcerun {
+            if flags.is_manual_user_forcerun {
                 fast_runner.pqp_is_run_today = true;
                 fast_runner.is_simulation = true; // whatever is the calculation, force simulation in this mode.
                 is_first_scheduled_today = true;
@@ -85,9 +105,10 @@ impl RqTask for FastRunnerPqpTask {
 
             if !fast_runner.pqp_is_run_today {
                 log_and_println!("Today is not the scheduled day for FastRunnerPqpTask");
-                return;
+                return Ok(());
             }
 
+            RQ_EVENT_HUB.publish(RqEventTopic::FastRunner, "run_started", json!({"task": self.name, "json_target_date": fast_runner.pqp_json_target_date_str, "is_simulation": fast_runner.is_simulation}));
             writeln!(fast_runner.user_log, "{}: FastRunnerPqpTask run() loop started. Json target date: {}, is_simulation: {}", Utc::now().format("%H:%M:%S"), fast_runner.pqp_json_target_date_str, fast_runner.is_simulation).unwrap(); // write!() macro never panics for a String (infallible), so unwrap() is safe
 
             if is_first_scheduled_today {
@@ -110,10 +131,8 @@ impl RqTask for FastRunnerPqpTask {
                 }
             }
 
-            { // keep the mutex lock scope as small as possible
-                let mut mark_value_cache = RQ_MARK_VALUE_CACHE.lock_ignore_poison();
-                mark_value_cache.start_quote_stream();
-            }
+            FastRunner::populate_mark_value_universe().await;
+            let quote_stream_guard = QuoteStreamGuard::acquire(); // stops the quote stream even if the run times out (the scheduler drops this future) or panics
             tokio::time::sleep(tokio::time::Duration::from_millis(20000)).await; // let mark_value_cache warm up for 20sec to get some rt-prices
 
             let loop_endtime = tokio::time::Instant::now()
@@ -129,8 +148,9 @@ impl RqTask for FastRunnerPqpTask {
             while tokio::time::Instant::now() < loop_endtime { // if the loop runs more than 4 minutes 30 seconds, then finish the loop
                 log_and_println!(">*{}: FastRunnerPqpTask run() loop iteration started. Json target date: {}, is_simulation: {}", Utc::now().format("%H:%M:%S%.3f"), fast_runner.pqp_json_target_date_str, fast_runner.is_simulation);
 
+                RQ_EVENT_HUB.publish(RqEventTopic::FastRunner, "iteration", json!({"task": self.name, "is_simulation": fast_runner.is_simulation}));
                 fast_runner.fastrunning_loop_pqp_impl().await;
-                if self.is_manual_user_forcerun { // User forcerun only wants to test 1 loop. And if "No new buy/sell events on {}. Skipping trading." happens, then has_trading_ever_started cannot be used to exits after 1 loop, because it will never be true.
+                if flags.is_manual_user_forcerun { // User forcerun only wants to test 1 loop. And if "No new buy/sell events on {}. Skipping trading." happens, then has_trading_ever_started cannot be used to exits after 1 loop, because it will never be true.
                     break;
                 }
 
@@ -146,10 +166,9 @@ impl RqTask for FastRunnerPqpTask {
             }
             log_and_println!("{} FastRunnerPqpTask run() ended", Utc::now().format("%H:%M:%S%.3f"));
 
-            { // keep the mutex lock scope as small as possible
-                let mut mark_value_cache = RQ_MARK_VALUE_CACHE.lock_ignore_poison();
-                mark_value_cache.stop_quote_stream();
-            }
+            drop(quote_stream_guard);
+            store_last_run_summary(&self.name, started_at, &fast_runner, flags.is_manual_user_forcerun, &fast_runner.pqp_json_target_date_str);
+            RQ_EVENT_HUB.publish(RqEventTopic::FastRunner, "run_ended", json!({"task": self.name, "is_simulation": fast_runner.is_simulation, "has_trading_ever_started": fast_runner.has_trading_ever_started}));
 
             if let Some(email_to_address) = get_rqcore_config().get("email_gyant") {
                 log_and_println!("Sending email. Might take 18 sec 'sometimes' (normally: 1-2.5sec)...(In single-threaded Tokio, Console or any messages are not handled. Investigate later: 1. We need an async RqEmail anyway (even if it is only 2 sec). 2. Why does it take 18sec)");
@@ -162,6 +181,7 @@ impl RqTask for FastRunnerPqpTask {
                     log_and_println!("Elapsed Time of RqEmail::send_text(): {:.2}us", start.elapsed().as_secs_f64() * 1_000_000.0);
                 }
             }
+            Ok(())
         })
     }
 }
@@ -170,55 +190,38 @@ impl RqTask for FastRunnerPqpTask {
 // ---------- FastRunner AP (daily 11:59 ET) ----------
 pub struct FastRunnerApTask {
     name: String,
-    next_time: Mutex<DateTime<Utc>>,
-    pub is_manual_user_forcerun: bool,
+    schedule: RqSchedule,
 }
 
 impl FastRunnerApTask {
     pub fn new() -> Self {
-        let next_utc = Self::get_next_trigger_time_impl();
         FastRunnerApTask {
             name: "FastRunnerApTask".to_string(),
-            next_time: Mutex::new(next_utc),
-            is_manual_user_forcerun: false,
+            // we run 4 times daily: 3x Simulation, 1x RealTrading at 11:59 ET. A few seconds after the PQP task, so they don't start at the same time.
+            schedule: RqSchedule::parse("09:50:10, 11:05:20, 11:30:20, 11:59:20 ET on trading days").unwrap(),
         }
     }
-
-    fn get_next_trigger_time_impl() -> DateTime<Utc> { // we run 3 times daily: 2x Simulation, 1x RealTrading at 11:01 ET, 11:30 ET, 11:59 ET
-        let tz = Eastern;
-        let targets_tz = [
-            // NaiveTime::from_hms_opt(15, 26, 10).unwrap(), // for manual test
-            NaiveTime::from_hms_opt(9, 50, 10).unwrap(), // USA market opens at 9:30 ET, so around 9:45 ET is the earliest.
-            NaiveTime::from_hms_opt(11, 5, 20).unwrap(),
-            NaiveTime::from_hms_opt(11, 30, 20).unwrap(),
-            NaiveTime::from_hms_opt(11, 59, 20).unwrap(), // consider mark_value_cache warm up time, so trigger earlier
-        ];
-
-        targets_tz
-            .into_iter()
-            .map(|t| localtimeonly2future_datetime_tz(tz, t).to_utc())
-            .min() // pick the earliest UTC time
-            .unwrap()
-    }
 }
 
 impl RqTask for FastRunnerApTask {
     fn name(&self) -> &str { &self.name }
 
-    fn get_next_trigger_time(&self) -> DateTime<Utc> {
-        *self.next_time.lock().unwrap()
-    }
+    fn schedule(&self) -> &RqSchedule { &self.schedule }
 
-    fn update_next_trigger_time(&self) {
-        let next_utc = Self::get_next_trigger_time_impl();
-        let mut next = self.next_time.lock().unwrap();
-        *next = next_utc;
-    }
+    // A restart around 11:59 ET must not lose the live run. Within 2 minutes after the trigger, run() still recognizes the live trading time (close to 12:00 ET).
+    fn missed_run_policy(&self) -> RqTaskMissedRunPolicy { RqTaskMissedRunPolicy::RunImmediately }
+
+    fn missed_run_grace(&self) -> Duration { Duration::from_secs(2 * 60) }
+
+    fn overlap_policy(&self) -> RqTaskOverlapPolicy { RqTaskOverlapPolicy::Queue } // a slow 11:30 simulation run must not make the 11:59 live run skipped, nor run in parallel with it
 
-    fn run(&self) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+    fn timeout(&self) -> Option<Duration> { Some(FASTRUNNER_TASK_TIMEOUT) }
+
+    fn run(&self, flags: RqTaskRunFlags) -> Pin<Box<dyn Future<Output = RqTaskResult> + Send + '_>> {
         // let this = self;
         Box::pin(async move {
-            log_and_println!("{} FastRunnerApTask run() started", Utc::now().format("%H:%M:%S%.3f"));
+            let started_at = Utc::now();
+            log_and_println!("{} FastRunnerApTask run() started", started_at.format("%H:%M:%S%.3f"));
 
             let mut fast_runner = FastRunner::new();
             fast_runner.init().await;
@@ -231,22 +234,21 @@ impl RqTask for FastRunnerApTask {
 
             fast_runner.is_simulation = !is_live_trading_based_on_closeto_noon;
 
-            if self.is_manual_user_forcerun {
+            if flags.is_manual_user_forcerun {
                 fast_runner.ap_is_run_today = true;
                 fast_runner.is_simulation = true; // whatever is the calculation, force simulation in this mode.
             }
 
             if !fast_runner.ap_is_run_today {
                 log_and_println!("Today is not the scheduled day for FastRunnerApTask");
-                return;
+                return Ok(());
             }
 
+            RQ_EVENT_HUB.publish(RqEventTopic::FastRunner, "run_started", json!({"task": self.name, "json_target_date": fast_runner.ap_json_target_date_str, "is_simulation": fast_runner.is_simulation}));
             writeln!(fast_runner.user_log, "{}: FastRunnerApTask run() loop started. Json target date: {}, is_simulation: {}", Utc::now().format("%H:%M:%S"), fast_runner.ap_json_target_date_str, fast_runner.is_simulation).unwrap(); // write!() macro never panics for a String (infallible), so unwrap() is safe
 
-            { // keep the mutex lock scope as small as possible
-                let mut mark_value_cache = RQ_MARK_VALUE_CACHE.lock_ignore_poison();
-                mark_value_cache.start_quote_stream();
-            }
+            FastRunner::populate_mark_value_universe().await;
+            let quote_stream_guard = QuoteStreamGuard::acquire(); // stops the quote stream even if the run times out (the scheduler drops this future) or panics
             tokio::time::sleep(tokio::time::Duration::from_millis(20000)).await; // let mark_value_cache warm up for 20sec to get some rt-prices
 
             let loop_endtime = tokio::time::Instant::now()
@@ -256,8 +258,9 @@ impl RqTask for FastRunnerApTask {
             while tokio::time::Instant::now() < loop_endtime { // if the loop runs more than 4 minutes 30 seconds, then finish the loop
                 log_and_println!(">*{}: FastRunnerApTask run() loop iteration started. Json target date: {}, is_simulation: {}", Utc::now().format("%H:%M:%S%.3f"), fast_runner.ap_json_target_date_str, fast_runner.is_simulation);
 
+                RQ_EVENT_HUB.publish(RqEventTopic::FastRunner, "iteration", json!({"task": self.name, "is_simulation": fast_runner.is_simulation}));
                 fast_runner.fastrunning_loop_ap_impl().await;
-                if self.is_manual_user_forcerun { // User forcerun only wants to test 1 loop. And if "No new buy/sell events on {}. Skipping trading." happens, then has_trading_ever_started cannot be used to exits after 1 loop, because it will never be true.
+                if flags.is_manual_user_forcerun { // User forcerun only wants to test 1 loop. And if "No new buy/sell events on {}. Skipping trading." happens, then has_trading_ever_started cannot be used to exits after 1 loop, because it will never be true.
                     break;
                 }
 
@@ -273,10 +276,9 @@ impl RqTask for FastRunnerApTask {
             }
             log_and_println!("{} FastRunnerApTask run() ended", Utc::now().format("%H:%M:%S%.3f"));
 
-            { // keep the mutex lock scope as small as possible
-                let mut mark_value_cache = RQ_MARK_VALUE_CACHE.lock_ignore_poison();
-                mark_value_cache.stop_quote_stream();
-            }
+            drop(quote_stream_guard);
+            store_last_run_summary(&self.name, started_at, &fast_runner, flags.is_manual_user_forcerun, &fast_runner.ap_json_target_date_str);
+            RQ_EVENT_HUB.publish(RqEventTopic::FastRunner, "run_ended", json!({"task": self.name, "is_simulation": fast_runner.is_simulation, "has_trading_ever_started": fast_runner.has_trading_ever_started}));
 
             if let Some(email_to_address) = get_rqcore_config().get("email_gyant") {
                 let start = tokio::time::Instant::now();
@@ -286,6 +288,7 @@ impl RqTask for FastRunnerApTask {
                     log_and_println!("Elapsed Time of RqEmail::send_text(): {:.2}us", start.elapsed().as_secs_f64() * 1_000_000.0);
                 }
             }
+            Ok(())
         })
     }
 }
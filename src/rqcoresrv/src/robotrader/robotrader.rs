@@ -1,8 +1,11 @@
-use std::{collections::HashMap, sync::{LazyLock, Mutex}};
+use std::{collections::{HashMap, HashSet}, sync::{LazyLock, Mutex}, time::Duration};
 
 use ibapi::orders::{CommissionReport, ExecutionData};
+use serde_json::json;
 use rqcommon::log_and_println;
 use rqcommon::rqhelper::MutexExt;
+use rqcommon::utils::rqevent_hub::{RqEventTopic, RQ_EVENT_HUB};
+use rqcommon::utils::runningenv::get_running_env_overrides;
 use broker_common::brokers_watcher::{BrokerClient, RqOrder};
 
 use crate::RQ_BROKERS_WATCHER;
@@ -25,36 +28,67 @@ pub static RQ_ROBO_TRADER: LazyLock<RoboTrader> = LazyLock::new(|| RoboTrader::n
 // Register them in SQL. Send an daily TradeReport email to the user with the order details (e.g. ticker, numShares, fill price, fill time, etc.).
 pub struct RoboTrader {
     pub order_executions: Mutex<HashMap<BrokerClient, (Vec<ExecutionData>, Vec<CommissionReport>)>>,
+    seen_execution_ids: Mutex<HashSet<String>>, // the executions that were already published (or were done before the start)
 }
 
+// After placing orders, the executions are polled at these delays (IB's executions() is a request, not a subscription), and the new fills are published
+const FILL_POLL_DELAYS_SEC: [u64; 7] = [2, 5, 10, 30, 60, 120, 300];
+
 impl RoboTrader {
     fn new() -> Self {
-        Self { order_executions: Mutex::new(HashMap::new()) }
+        Self { order_executions: Mutex::new(HashMap::new()), seen_execution_ids: Mutex::new(HashSet::new()) }
     }
 
     pub async fn init(&self) {
-        self.refresh_executions().await;
+        // The executions of the day before the start are seeded silently: they are not new fills
+        self.refresh_executions(false).await;
     }
 
     pub async fn exit(&self) {
     }
 
-    pub async fn refresh_executions(&self) {
-        let mut order_executions = self.order_executions.lock_ignore_poison();
-        order_executions.clear();
-
+    // Refetches today's executions. If publish_new_fills, publishes an "order_filled" event for each execution that was not seen before.
+    pub async fn refresh_executions(&self, publish_new_fills: bool) {
+        // Fetched without holding the locks: a fetch can take seconds
         let executions_dcmain = RQ_BROKERS_WATCHER.get_order_executions(BrokerClient::DcMain).await;
-        for execution_data in executions_dcmain.0.iter() {
-            // log_and_println!("RoboTrader.init(): DcMain execution: {:#?}", execution_data);
-            log_and_println!("RoboTrader.init(): DcMain execution: {} {} {} {}", execution_data.contract.symbol, execution_data.execution.shares, execution_data.execution.price, execution_data.execution.time);
+        let executions_dcblanzac = RQ_BROKERS_WATCHER.get_order_executions(BrokerClient::DcBlanzac).await;
+        let executions_gyantal = RQ_BROKERS_WATCHER.get_order_executions(BrokerClient::Gyantal).await;
+        for execution_data in executions_dcmain.0.iter().filter(|_| !publish_new_fills) { // the startup list only; the polls log the new fills below
+            // log_and_println!("RoboTrader.refresh_executions(): DcMain execution: {:#?}", execution_data);
+            log_and_println!("RoboTrader.refresh_executions(): DcMain execution: {} {} {} {}", execution_data.contract.symbol, execution_data.execution.shares, execution_data.execution.price, execution_data.execution.time);
         }
-        order_executions.insert(BrokerClient::DcMain, executions_dcmain);
 
-        let executions_dcblanzac = RQ_BROKERS_WATCHER.get_order_executions(BrokerClient::DcBlanzac).await;
+        let mut order_executions = self.order_executions.lock_ignore_poison();
+        order_executions.insert(BrokerClient::DcMain, executions_dcmain);
         order_executions.insert(BrokerClient::DcBlanzac, executions_dcblanzac);
-
-        let executions_gyantal = RQ_BROKERS_WATCHER.get_order_executions(BrokerClient::Gyantal).await;
         order_executions.insert(BrokerClient::Gyantal, executions_gyantal);
+
+        // The new fills since the previous refresh. The check and the insert are under the same lock, so the concurrent polls publish a fill only once.
+        let mut seen_execution_ids = self.seen_execution_ids.lock_ignore_poison();
+        for (broker_client, (executions, _)) in order_executions.iter() {
+            for execution_data in executions.iter() {
+                if !seen_execution_ids.insert(execution_data.execution.execution_id.clone()) || !publish_new_fills {
+                    continue;
+                }
+                log_and_println!("RoboTrader: new fill on {:?}: {} {} {} @ {}", broker_client, execution_data.execution.side, execution_data.execution.shares, execution_data.contract.symbol, execution_data.execution.price);
+                RQ_EVENT_HUB.publish(RqEventTopic::Orders, "order_filled", json!({"broker_client": format!("{:?}", broker_client), "order_id": execution_data.execution.order_id, "execution_id": execution_data.execution.execution_id,
+                    "symbol": execution_data.contract.symbol.to_string(), "side": execution_data.execution.side, "shares": execution_data.execution.shares, "price": execution_data.execution.price, "time": execution_data.execution.time}));
+            }
+        }
+    }
+
+    // Polls the executions in the background after the orders were placed, until the last of FILL_POLL_DELAYS_SEC
+    fn poll_fills_after_orders(strategy_name: &str) {
+        let strategy_name = strategy_name.to_string();
+        tokio::spawn(async move {
+            let mut elapsed_sec = 0;
+            for delay_sec in FILL_POLL_DELAYS_SEC {
+                tokio::time::sleep(Duration::from_secs(delay_sec - elapsed_sec)).await;
+                elapsed_sec = delay_sec;
+                log::debug!("RoboTrader.poll_fills_after_orders({}): polling the executions {}s after the orders", strategy_name, delay_sec);
+                RQ_ROBO_TRADER.refresh_executions(true).await;
+            }
+        });
     }
 
     pub async fn place_orders(strategy_name: &str, orders: Vec<RqOrder>, is_simulation: bool, user_log: &mut String) {
@@ -63,6 +97,12 @@ impl RoboTrader {
             return;
         }
 
+        // --simulation-only (or RQCORE_SIMULATION_ONLY) is the single switch for developer machines and containers to never send real orders.
+        let is_simulation = is_simulation || get_running_env_overrides().simulation_only;
+
         RQ_BROKERS_WATCHER.place_orders(orders, is_simulation, user_log).await;
+        if !is_simulation {
+            Self::poll_fills_after_orders(strategy_name);
+        }
     }
 }
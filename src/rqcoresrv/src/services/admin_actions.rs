@@ -0,0 +1,254 @@
+use std::{collections::{HashSet, VecDeque}, fmt, sync::{LazyLock, Mutex, OnceLock}, time::Instant};
+use actix_web::dev::ServerHandle;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+
+use rqcommon::{rqhelper::MutexExt, utils::rqlog::RQ_LOG_CAPTURE};
+use crate::{robotrader::{fast_runner::FastRunner, fast_runner_task::{FastRunnerApTask, FastRunnerPqpTask}}, services::{rqcore_config_watcher::reload_rqcore_config, rqtask_scheduler::{RqTask, RqTaskRunFlags, RQ_TASK_SCHEDULER}}};
+
+// The remote-controllable actions. The same code runs them from the console menu and from the web UI (/admin/actions, websocket /ws/admin/actions), so there is no need for an SSH 'screen' session.
+// Every run is audited (who, from where, when, result): in the log file, and in the ADMIN_AUDIT_LOG (shown on the /admin/actions page).
+// The same action cannot run twice in parallel (e.g. 2 admins clicking FastRunner Forcerun at the same time).
+// Output: the result lines, and the log_and_println!() lines of the action (see RQ_LOG_CAPTURE) are sent to the output channel. Without an output channel (console), they are printed.
+
+pub const ADMIN_AUDIT_LOG_LEN: usize = 500;
+
+// ---------- Global static variables ----------
+pub static SERVER_HANDLE: OnceLock<ServerHandle> = OnceLock::new(); // set in main(), after the web server is created
+pub static ADMIN_AUDIT_LOG: LazyLock<Mutex<VecDeque<AdminAuditRecord>>> = LazyLock::new(|| Mutex::new(VecDeque::new()));
+static RUNNING_ADMIN_ACTIONS: LazyLock<Mutex<HashSet<RqAdminAction>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+// ---------- Class/struct definitions ----------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RqAdminAction {
+    ListTasks,
+    PauseTask,
+    ResumeTask,
+    RemoveTask,
+    RunTaskNow,
+    ReloadConfig,
+    FastRunnerPqpHttpDownloadTest,
+    FastRunnerApHttpDownloadTest,
+    FastRunnerPqpForceRun,
+    FastRunnerApForceRun,
+    StopServer,
+}
+
+impl RqAdminAction {
+    pub const ALL: [RqAdminAction; 11] = [RqAdminAction::ListTasks, RqAdminAction::PauseTask, RqAdminAction::ResumeTask, RqAdminAction::RemoveTask, RqAdminAction::RunTaskNow, RqAdminAction::ReloadConfig, RqAdminAction::FastRunnerPqpHttpDownloadTest, RqAdminAction::FastRunnerApHttpDownloadTest,
+        RqAdminAction::FastRunnerPqpForceRun, RqAdminAction::FastRunnerApForceRun, RqAdminAction::StopServer];
+
+    // The name in the web API
+    pub fn name(&self) -> &'static str {
+        match self {
+            RqAdminAction::ListTasks => "listtasks",
+            RqAdminAction::PauseTask => "pausetask",
+            RqAdminAction::ResumeTask => "resumetask",
+            RqAdminAction::RemoveTask => "removetask",
+            RqAdminAction::RunTaskNow => "runtasknow",
+            RqAdminAction::ReloadConfig => "reloadconfig",
+            RqAdminAction::FastRunnerPqpHttpDownloadTest => "fastrunnerpqphttpdownloadtest",
+            RqAdminAction::FastRunnerApHttpDownloadTest => "fastrunneraphttpdownloadtest",
+            RqAdminAction::FastRunnerPqpForceRun => "fastrunnerpqpforcerun",
+            RqAdminAction::FastRunnerApForceRun => "fastrunnerapforcerun",
+            RqAdminAction::StopServer => "stopserver",
+        }
+    }
+
+    pub fn console_key(&self) -> &'static str {
+        match self {
+            RqAdminAction::ListTasks => "3",
+            RqAdminAction::PauseTask => "31",
+            RqAdminAction::ResumeTask => "32",
+            RqAdminAction::RemoveTask => "33",
+            RqAdminAction::RunTaskNow => "34",
+            RqAdminAction::ReloadConfig => "4",
+            RqAdminAction::FastRunnerPqpHttpDownloadTest => "51",
+            RqAdminAction::FastRunnerApHttpDownloadTest => "52",
+            RqAdminAction::FastRunnerPqpForceRun => "53",
+            RqAdminAction::FastRunnerApForceRun => "54",
+            RqAdminAction::StopServer => "9",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            RqAdminAction::ListTasks => "TaskScheduler: Show tasks (state, next trigger time, last run).",
+            RqAdminAction::PauseTask => "TaskScheduler: Pause task. Args: <task name>",
+            RqAdminAction::ResumeTask => "TaskScheduler: Resume task. Args: <task name>",
+            RqAdminAction::RemoveTask => "TaskScheduler: Remove task. Args: <task name>",
+            RqAdminAction::RunTaskNow => "TaskScheduler: Run task now. Args: <task name> [forcerun]",
+            RqAdminAction::ReloadConfig => "Reload rqcore.config (validate, show diff, apply).",
+            RqAdminAction::FastRunnerPqpHttpDownloadTest => "FastRunner PQP: test only HttpDownload",
+            RqAdminAction::FastRunnerApHttpDownloadTest => "FastRunner AP: test only HttpDownload",
+            RqAdminAction::FastRunnerPqpForceRun => "FastRunnerTask PQP: Forcerun trade simulation",
+            RqAdminAction::FastRunnerApForceRun => "FastRunnerTask AP: Forcerun trade simulation (getprice() hangs OTH)",
+            RqAdminAction::StopServer => "Stop server and exit gracefully (Avoid Ctrl-^C).",
+        }
+    }
+
+    pub fn has_args(&self) -> bool {
+        matches!(self, RqAdminAction::PauseTask | RqAdminAction::ResumeTask | RqAdminAction::RemoveTask | RqAdminAction::RunTaskNow)
+    }
+
+    pub fn parse(name: &str) -> Option<RqAdminAction> {
+        RqAdminAction::ALL.into_iter().find(|action| action.name() == name)
+    }
+
+    pub fn from_console_key(key: &str) -> Option<RqAdminAction> {
+        RqAdminAction::ALL.into_iter().find(|action| action.console_key() == key)
+    }
+}
+
+impl fmt::Display for RqAdminAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdminAuditRecord {
+    pub id: u64,
+    pub action: RqAdminAction,
+    pub args: String,
+    pub triggered_by: String, // user email (+ token id), or "console"
+    pub source: String,       // "console", "web"
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: Option<u64>, // None while running
+    pub result: Option<String>,   // "ok" or the error. None while running (or if the action crashed).
+}
+
+// Removes the action from RUNNING_ADMIN_ACTIONS even if the action panics (and marks it as crashed in the audit log).
+struct RunningAdminActionGuard {
+    action: RqAdminAction,
+}
+
+impl Drop for RunningAdminActionGuard {
+    fn drop(&mut self) {
+        RUNNING_ADMIN_ACTIONS.lock_ignore_poison().remove(&self.action);
+        if std::thread::panicking() {
+            if let Some(record) = ADMIN_AUDIT_LOG.lock_ignore_poison().iter_mut().rev().find(|r| r.action == self.action && r.result.is_none()) {
+                record.result = Some("crashed (panic)".to_string());
+            }
+        }
+    }
+}
+
+fn output_line(output: &Option<UnboundedSender<String>>, line: String) {
+    match output {
+        Some(sender) => { let _ = sender.send(line); }
+        None => println!("{}", line),
+    }
+}
+
+// args: e.g. the task name for the task actions. Empty for the others.
+pub async fn run_admin_action(action: RqAdminAction, args: &str, triggered_by: &str, source: &str, output: Option<UnboundedSender<String>>) -> Result<(), String> {
+    let _running_guard = {
+        let mut running_actions = RUNNING_ADMIN_ACTIONS.lock_ignore_poison();
+        if !running_actions.insert(action) {
+            log::warn!("Admin action '{}' '{}' by {} ({}) rejected: already running", action, args, triggered_by, source);
+            return Err(format!("Action '{}' is already running", action));
+        }
+        RunningAdminActionGuard { action }
+    };
+
+    let audit_id = {
+        let mut audit_log = ADMIN_AUDIT_LOG.lock_ignore_poison();
+        let id = audit_log.back().map(|r| r.id + 1).unwrap_or(1);
+        if audit_log.len() >= ADMIN_AUDIT_LOG_LEN {
+            audit_log.pop_front();
+        }
+        audit_log.push_back(AdminAuditRecord { id, action, args: args.to_string(), triggered_by: triggered_by.to_string(), source: source.to_string(), started_at: Utc::now(), duration_ms: None, result: None });
+        id
+    };
+    log::warn!("Admin action '{}' '{}' started by {} ({})", action, args, triggered_by, source);
+
+    let start = Instant::now();
+    let result = match output.clone() {
+        Some(sender) => RQ_LOG_CAPTURE.scope(sender, run_admin_action_impl(action, args, triggered_by, &output)).await,
+        None => run_admin_action_impl(action, args, triggered_by, &output).await,
+    };
+    let duration_ms = start.elapsed().as_millis() as u64;
+
+    match &result {
+        Ok(()) => log::warn!("Admin action '{}' '{}' by {} ({}) finished in {}ms", action, args, triggered_by, source, duration_ms),
+        Err(err) => log::error!("Admin action '{}' '{}' by {} ({}) failed in {}ms: {}", action, args, triggered_by, source, duration_ms, err),
+    }
+    if let Some(record) = ADMIN_AUDIT_LOG.lock_ignore_poison().iter_mut().find(|r| r.id == audit_id) {
+        record.duration_ms = Some(duration_ms);
+        record.result = Some(match &result { Ok(()) => "ok".to_string(), Err(err) => err.clone() });
+    }
+    result
+}
+
+// Waits for the run, so its output is streamed and the admin sees when it finished. The run's result is in the run history.
+async fn run_task_now_and_wait(task_name: &str, flags: RqTaskRunFlags, output: &Option<UnboundedSender<String>>) -> Result<(), String> {
+    RQ_TASK_SCHEDULER.run_task_now(task_name, flags)?.await.map_err(|err| format!("Task '{}' crashed: {}", task_name, err))?;
+    let task_result = RQ_TASK_SCHEDULER.get_run_history(Some(task_name)).into_iter().find(|r| r.is_manual).map(|r| r.result_text());
+    output_line(output, format!("Task '{}' finished. Result: {}", task_name, task_result.as_deref().unwrap_or("-")));
+    Ok(())
+}
+
+// A scheduled task runs through the scheduler: its overlap policy stops a second run during the scheduled one (e.g. the 11:59 live FastRunner run), and the run is in the run history.
+// The new task instance is run directly only if the task is not scheduled on this machine (see --enabled-tasks).
+async fn force_run_task(task: impl RqTask, output: &Option<UnboundedSender<String>>) -> Result<(), String> {
+    let flags = RqTaskRunFlags { is_manual_user_forcerun: true };
+    if RQ_TASK_SCHEDULER.list_tasks().iter().any(|t| t.name == task.name()) {
+        return run_task_now_and_wait(task.name(), flags, output).await;
+    }
+    output_line(output, format!("Task '{}' is not scheduled on this machine. Running it outside of the scheduler.", task.name()));
+    task.run(flags).await
+}
+
+async fn run_admin_action_impl(action: RqAdminAction, args: &str, triggered_by: &str, output: &Option<UnboundedSender<String>>) -> Result<(), String> {
+    let (task_name, task_args) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+    if action.has_args() && task_name.is_empty() {
+        return Err(format!("Action '{}' needs a task name. Tasks: {}", action, RQ_TASK_SCHEDULER.list_tasks().iter().map(|t| t.name.as_str()).collect::<Vec<_>>().join(", ")));
+    }
+    match action {
+        RqAdminAction::ListTasks => {
+            for task in RQ_TASK_SCHEDULER.list_tasks() {
+                let last_run = match &task.last_run {
+                    Some(run) => format!("last run: {} ({}), {}, result: {}", run.started_at.format("%Y-%m-%d %H:%M:%S"), if run.is_manual { "manual" } else { "scheduled" },
+                        run.duration_ms.map(|ms| format!("{}ms", ms)).unwrap_or("-".to_string()), run.result_text()),
+                    None => "no run yet".to_string(),
+                };
+                output_line(output, format!("{} '{}' [{:?}{}] -> {}, {}", task.name, task.schedule, task.state, if task.running_count > 0 { ", running" } else { "" }, task.next_trigger_time, last_run));
+            }
+        }
+        RqAdminAction::PauseTask => RQ_TASK_SCHEDULER.pause_task(task_name)?,
+        RqAdminAction::ResumeTask => RQ_TASK_SCHEDULER.resume_task(task_name)?,
+        RqAdminAction::RemoveTask => RQ_TASK_SCHEDULER.remove_task(task_name)?,
+        RqAdminAction::RunTaskNow => {
+            run_task_now_and_wait(task_name, RqTaskRunFlags::parse(task_args)?, output).await?;
+        }
+        RqAdminAction::ReloadConfig => {
+            match reload_rqcore_config(&format!("admin action by {}", triggered_by)) {
+                Ok(diff_lines) if diff_lines.is_empty() => output_line(output, "RqCore config reloaded. No changes.".to_string()),
+                Ok(diff_lines) => output_line(output, format!("RqCore config reloaded. {} changes:\n{}", diff_lines.len(), diff_lines.join("\n"))),
+                Err(err) => return Err(format!("RqCore config NOT reloaded. {}", err)),
+            }
+        }
+        RqAdminAction::FastRunnerPqpHttpDownloadTest => {
+            let mut fast_runner = FastRunner::new();
+            fast_runner.init().await;
+            fast_runner.test_http_download_pqp().await;
+        }
+        RqAdminAction::FastRunnerApHttpDownloadTest => {
+            let mut fast_runner = FastRunner::new();
+            fast_runner.init().await;
+            fast_runner.test_http_download_ap().await;
+        }
+        RqAdminAction::FastRunnerPqpForceRun => force_run_task(FastRunnerPqpTask::new(), output).await?,
+        RqAdminAction::FastRunnerApForceRun => force_run_task(FastRunnerApTask::new(), output).await?,
+        RqAdminAction::StopServer => {
+            let server_handle = SERVER_HANDLE.get().ok_or("Server is not started yet")?.clone();
+            output_line(output, "Stopping server...".to_string());
+            // Spawned, because a web request that stops the server would be dropped by the stopping server itself, before the stop() finishes.
+            tokio::spawn(async move { server_handle.stop(false).await; });
+        }
+    }
+    Ok(())
+}
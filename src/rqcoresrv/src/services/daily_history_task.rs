@@ -0,0 +1,55 @@
+use std::{collections::BTreeSet, future::Future, pin::Pin, time::Duration};
+
+use rqcommon::{log_and_println, rqhelper::MutexExt, utils::rqschedule::RqSchedule};
+use broker_common::daily_history_backfill::backfill_daily_history;
+use memdb::mark_value_cache::RQ_MARK_VALUE_CACHE;
+
+use crate::{get_rqcore_config, services::rqtask_scheduler::{RqTask, RqTaskMissedRunPolicy, RqTaskResult, RqTaskRunFlags}};
+
+// ---------- Daily history backfill (daily after the close) ----------
+// Backfills the memdb daily history of the traded tickers: the MarkValueCache universe (manual + FastRunner tickers) and the config 'daily_history_tickers' (CSV).
+pub struct DailyHistoryTask {
+    name: String,
+    schedule: RqSchedule,
+}
+
+impl DailyHistoryTask {
+    pub fn new() -> Self {
+        DailyHistoryTask {
+            name: "DailyHistoryTask".to_string(),
+            schedule: RqSchedule::parse("16:30 ET on trading days").unwrap(), // IB's daily bars are final some minutes after the close
+        }
+    }
+}
+
+impl RqTask for DailyHistoryTask {
+    fn name(&self) -> &str { &self.name }
+
+    fn schedule(&self) -> &RqSchedule { &self.schedule }
+
+    // A server that was down in the evening backfills at the next start, before the FastRunner runs of the next morning
+    fn missed_run_policy(&self) -> RqTaskMissedRunPolicy { RqTaskMissedRunPolicy::RunImmediately }
+
+    fn missed_run_grace(&self) -> Duration { Duration::from_secs(16 * 60 * 60) }
+
+    fn timeout(&self) -> Option<Duration> { Some(Duration::from_secs(60 * 60)) }
+
+    fn run(&self, _flags: RqTaskRunFlags) -> Pin<Box<dyn Future<Output = RqTaskResult> + Send + '_>> {
+        Box::pin(async move {
+            let mut tickers: BTreeSet<String> = RQ_MARK_VALUE_CACHE.lock_ignore_poison().get_ticker_universe();
+            if let Some(tickers_csv) = get_rqcore_config().get("daily_history_tickers") {
+                tickers.extend(tickers_csv.split(',').map(|ticker| ticker.trim().to_uppercase()).filter(|ticker| !ticker.is_empty()));
+            }
+            let tickers: Vec<String> = tickers.into_iter().collect();
+            log_and_println!("DailyHistoryTask: backfilling {} ticker(s)...", tickers.len());
+
+            let result = backfill_daily_history(&tickers).await;
+            log_and_println!("DailyHistoryTask: {} updated, {} up to date, {} failed.", result.num_updated, result.num_up_to_date, result.failed_tickers.len());
+            if result.failed_tickers.is_empty() {
+                Ok(())
+            } else {
+                Err(format!("Daily history backfill failed for {} ticker(s): {}", result.failed_tickers.len(), result.failed_tickers.join(",")))
+            }
+        })
+    }
+}
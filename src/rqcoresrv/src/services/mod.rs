@@ -1 +1,4 @@
 pub mod rqtask_scheduler;
+pub mod rqcore_config_watcher;
+pub mod admin_actions;
+pub mod daily_history_task;
@@ -0,0 +1,65 @@
+use std::{fs, sync::Arc, time::SystemTime};
+use tokio::time as tokio_time;
+
+use rqcommon::utils::runningenv::{diff_rqcore_config, load_rqcore_config, rqcore_config_path, validate_rqcore_config};
+use crate::{RQCORE_CONFIG, apply_mark_value_policy, middleware::authorization::update_user_roles};
+
+// The rqcore.config can be edited while the server is running (e.g. adding a user role), without a restart that would interrupt the IB gateway connections.
+// A reload is triggered by the file watcher (polling the file modification time), by the admin endpoint POST /admin/reloadconfig, or by the console menu.
+// The new config is validated first. If it is invalid, the running config is kept, and the error is logged.
+
+// Keys that are read only once at startup (into OnceLock globals). Changing them has no effect until restart.
+const RQCORE_CONFIG_RESTART_REQUIRED_KEYS: [&str; 4] = ["email_hqserver", "email_hqserver_pwd", "gsheet_client_email", "gsheet_private_key"];
+
+const RQCORE_CONFIG_WATCH_INTERVAL_SEC: u64 = 10;
+
+// Returns the diff lines of the applied changes (empty if the file didn't change).
+pub fn reload_rqcore_config(trigger: &str) -> Result<Vec<String>, String> {
+    let new_cfg = load_rqcore_config()?;
+    if let Err(err) = validate_rqcore_config(&new_cfg) {
+        log::error!("RqCore config reload ({}) rejected, keeping the running config: {}", trigger, err);
+        return Err(format!("Invalid config: {}", err));
+    }
+
+    let old_cfg = RQCORE_CONFIG.load_full();
+    let diff_lines = diff_rqcore_config(&old_cfg, &new_cfg);
+    if diff_lines.is_empty() {
+        log::info!("RqCore config reload ({}): no changes", trigger);
+        return Ok(diff_lines);
+    }
+
+    update_user_roles(&new_cfg);
+    apply_mark_value_policy(&new_cfg);
+    RQCORE_CONFIG.store(Arc::new(new_cfg));
+
+    log::warn!("RqCore config reloaded ({}), {} changes:\n{}", trigger, diff_lines.len(), diff_lines.join("\n"));
+    for key in RQCORE_CONFIG_RESTART_REQUIRED_KEYS {
+        if diff_lines.iter().any(|line| line[2..].starts_with(&format!("{} ", key)) || line[2..].starts_with(&format!("{}:", key))) {
+            log::warn!("RqCore config key '{}' changed. It will be applied only after restart.", key);
+        }
+    }
+    Ok(diff_lines)
+}
+
+fn rqcore_config_modified_time() -> Option<SystemTime> {
+    fs::metadata(rqcore_config_path()).and_then(|metadata| metadata.modified()).ok()
+}
+
+pub fn start_rqcore_config_watcher() {
+    tokio::spawn(async move {
+        let mut last_modified_time = rqcore_config_modified_time();
+        let mut interval = tokio_time::interval(tokio_time::Duration::from_secs(RQCORE_CONFIG_WATCH_INTERVAL_SEC));
+        interval.tick().await; // the first tick completes immediately
+        loop {
+            interval.tick().await;
+            let modified_time = rqcore_config_modified_time();
+            if modified_time.is_none() || modified_time == last_modified_time {
+                continue; // file is temporarily missing (e.g. while an editor saves it) or unchanged
+            }
+            last_modified_time = modified_time;
+            if let Err(err) = reload_rqcore_config("file change") {
+                log::error!("RqCore config watcher: {}", err);
+            }
+        }
+    });
+}
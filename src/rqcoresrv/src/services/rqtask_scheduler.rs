@@ -1,33 +1,95 @@
 use {
-    std::{future::Future, pin::Pin, sync::{Arc, LazyLock, Mutex}},
-    tokio::time as tokio_time,
-    chrono::{DateTime, Duration, Utc},
+    std::{any::Any, collections::{HashMap, VecDeque}, fs, future::Future, panic::AssertUnwindSafe, pin::Pin, sync::{Arc, LazyLock, Mutex}, time::{Duration, Instant}},
+    tokio::{sync::{Mutex as TokioMutex, Notify, OwnedMutexGuard}, task::JoinHandle, time as tokio_time},
+    chrono::{DateTime, Utc},
+    futures_util::FutureExt,
+    serde::{Deserialize, Serialize},
 };
 
+use rqcommon::{rqhelper::MutexExt, utils::{rqemail::RqEmail, rqlog::RQ_LOG_CAPTURE, rqschedule::RqSchedule, runningenv::rqcore_data_folder_path}};
+use crate::get_rqcore_config;
+
+pub const RQ_TASK_RUN_HISTORY_LEN: usize = 100; // per task
+pub const RQ_TASK_STATE_FILENAME: &str = "rqtask_scheduler_state.json"; // in the local rqcore_data folder: the sensitive config folder is shared by some developer machines (Google Drive)
+
 // ---------- Global static variables ----------
 pub static RQ_TASK_SCHEDULER: LazyLock<RqTaskScheduler> = LazyLock::new(|| RqTaskScheduler::new());
 
 // ---------- Task trait ----------
+// Per-run flags. The scheduled runs get the default (all false). A manual "run now" (console, web admin) can set them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RqTaskRunFlags {
+    pub is_manual_user_forcerun: bool, // run even if today is not a scheduled day. Forces simulation. (FastRunner tasks: only 1 loop iteration)
+}
+
+impl RqTaskRunFlags {
+    // E.g. "forcerun". Space or comma separated.
+    pub fn parse(flags_str: &str) -> Result<RqTaskRunFlags, String> {
+        let mut flags = RqTaskRunFlags::default();
+        for flag in flags_str.split([' ', ',']).map(|f| f.trim()).filter(|f| !f.is_empty()) {
+            match flag {
+                "forcerun" => flags.is_manual_user_forcerun = true,
+                _ => return Err(format!("Unknown task run flag '{}'. Known flags: forcerun", flag)),
+            }
+        }
+        Ok(flags)
+    }
+}
+
+pub type RqTaskResult = Result<(), String>;
+
+// What happens when a task is triggered (by the schedule or manually) while its previous run is still in progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RqTaskOverlapPolicy {
+    Skip,  // the new run is skipped (and recorded as skipped)
+    Queue, // the new run starts when the previous one finishes. At most 1 run waits, further triggers are skipped.
+    Allow, // the runs run in parallel
+}
+
+// What happens at startup with a trigger time that was missed while the server was not running (e.g. a restart at 11:58:50 ET misses the 11:59:10 ET run).
+// Only the last missed trigger time counts, and only if it is within the task's grace window. Older missed triggers are only logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RqTaskMissedRunPolicy {
+    Skip,           // wait for the next trigger time
+    RunImmediately, // catch up: run it as soon as the scheduler starts
+    Alert,          // don't run it, but send an alert email to the admin
+}
+
+impl RqTaskMissedRunPolicy {
+    pub fn parse(policy: &str) -> Option<RqTaskMissedRunPolicy> {
+        match policy {
+            "skip" => Some(RqTaskMissedRunPolicy::Skip),
+            "run_immediately" => Some(RqTaskMissedRunPolicy::RunImmediately),
+            "alert" => Some(RqTaskMissedRunPolicy::Alert),
+            _ => None,
+        }
+    }
+}
+
+// A task declares its schedule (see rqschedule.rs in rqcommon, e.g. "11:59:10 ET on trading days"). The scheduler computes the trigger times from it.
 pub trait RqTask: Send + Sync {
     fn name(&self) -> &str;
-    fn get_next_trigger_time(&self) -> DateTime<Utc>;
-    fn update_next_trigger_time(&self);
-    fn run(&self) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+    fn schedule(&self) -> &RqSchedule;
+    fn missed_run_policy(&self) -> RqTaskMissedRunPolicy { RqTaskMissedRunPolicy::Skip } // can be overridden by the config key 'task_missed_run_policy.<task name>' (skip, run_immediately, alert)
+    fn missed_run_grace(&self) -> Duration { Duration::from_secs(5 * 60) } // can be overridden by the config key 'task_missed_run_grace_sec.<task name>'
+    fn overlap_policy(&self) -> RqTaskOverlapPolicy { RqTaskOverlapPolicy::Skip }
+    fn timeout(&self) -> Option<Duration> { None } // the run (its future) is dropped after this, and it is recorded as timed out
+    fn run(&self, flags: RqTaskRunFlags) -> Pin<Box<dyn Future<Output = RqTaskResult> + Send + '_>>;
 }
 
 // ---------- Heartbeat ----------
 pub struct HeartbeatTask {
     name: String,
-    interval: Duration,
-    next_time: Mutex<DateTime<Utc>>,
+    schedule: RqSchedule,
 }
 
 impl HeartbeatTask {
     pub fn new() -> Self {
         HeartbeatTask {
             name: "HeartbeatTask".to_string(),
-            interval: Duration::minutes(10),
-            next_time: Mutex::new(Utc::now() + Duration::minutes(10)),
+            schedule: RqSchedule::parse("every 10m").unwrap(),
         }
     }
 }
@@ -35,41 +97,366 @@ impl HeartbeatTask {
 impl RqTask for HeartbeatTask {
     fn name(&self) -> &str { &self.name }
 
-    fn get_next_trigger_time(&self) -> DateTime<Utc> {
-        *self.next_time.lock().unwrap()
-    }
-
-    fn update_next_trigger_time(&self) {
-        let mut next = self.next_time.lock().unwrap();
-        *next = Utc::now() + self.interval;
-    }
+    fn schedule(&self) -> &RqSchedule { &self.schedule }
 
-    fn run(&self) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+    fn run(&self, _flags: RqTaskRunFlags) -> Pin<Box<dyn Future<Output = RqTaskResult> + Send + '_>> {
         Box::pin(async move {
             log::info!("HeartbeatTask run has started");
+            Ok(())
         })
     }
 }
 
 // ---------- Scheduler ----------
+// Tasks are identified by their name (unique). They can be listed, paused, resumed, removed and run immediately at runtime (console menu, /admin/actions).
+// A paused task is not triggered by the scheduler, but it can be run manually. When it is resumed, its missed trigger times are skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RqTaskState {
+    Active,
+    Paused,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RqTaskRunStatus {
+    Running,
+    Ok,
+    Failed,   // run() returned Err
+    Panicked,
+    TimedOut,
+    Skipped,  // overlap policy
+    Missed,   // the server was not running at the trigger time (see RqTaskMissedRunPolicy)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RqTaskRunRecord {
+    pub id: u64,
+    pub task_name: String,
+    pub started_at: DateTime<Utc>, // triggered at, for the skipped runs. Started at (after waiting) for the queued ones.
+    pub is_manual: bool, // "run now", not triggered by the schedule
+    pub flags: RqTaskRunFlags,
+    pub status: RqTaskRunStatus,
+    pub duration_ms: Option<u64>, // None while running
+    pub error: Option<String>,    // the error, panic message or skip reason
+}
+
+impl RqTaskRunRecord {
+    // E.g. "ok", "running", "failed: <error>"
+    pub fn result_text(&self) -> String {
+        let status = serde_json::to_value(self.status).ok().and_then(|v| v.as_str().map(|s| s.to_string())).unwrap_or_default();
+        match &self.error {
+            Some(error) => format!("{}: {}", status, error),
+            None => status,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RqTaskInfo {
+    pub name: String,
+    pub schedule: String,
+    pub overlap_policy: RqTaskOverlapPolicy,
+    pub timeout_sec: Option<u64>,
+    pub state: RqTaskState,
+    pub running_count: u32, // the number of runs in progress
+    pub next_trigger_time: DateTime<Utc>,
+    pub last_run: Option<RqTaskRunRecord>,
+}
+
+struct RqTaskEntry {
+    task: Arc<dyn RqTask>,
+    next_trigger_time: DateTime<Utc>,
+    state: RqTaskState,
+    running_count: u32,
+    queued_count: u32,
+    run_lock: Arc<TokioMutex<()>>, // held by the running run, for the Skip and Queue overlap policies
+    history: VecDeque<RqTaskRunRecord>, // the last RQ_TASK_RUN_HISTORY_LEN runs, oldest first
+}
+
+// Persisted per task name, so after a restart the scheduler knows what was missed. Tasks that are not scheduled on this machine keep their state in the file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct RqTaskPersistedState {
+    last_trigger_time: Option<DateTime<Utc>>, // the last scheduled trigger time that was handled (run or skipped)
+    last_run_started_at: Option<DateTime<Utc>>,
+    last_run_status: Option<RqTaskRunStatus>,
+}
+
 pub struct RqTaskScheduler {
-    tasks: Mutex<Vec<Arc<dyn RqTask>>>,
+    tasks: Mutex<Vec<RqTaskEntry>>,
+    tasks_changed: Notify, // wakes up the scheduler loop, because the soonest trigger time may have changed
+    next_run_id: Mutex<u64>,
+    persisted_states: Mutex<HashMap<String, RqTaskPersistedState>>, // task name => state. Loaded at the first use, saved at every trigger and run end.
 }
 
 impl RqTaskScheduler {
     pub fn new() -> Self {
-        RqTaskScheduler { tasks: Mutex::new(Vec::new()) }
+        RqTaskScheduler { tasks: Mutex::new(Vec::new()), tasks_changed: Notify::new(), next_run_id: Mutex::new(1), persisted_states: Mutex::new(Self::load_persisted_states()) }
+    }
+
+    fn state_file_path() -> String {
+        format!("{}{}", rqcore_data_folder_path(), RQ_TASK_STATE_FILENAME)
+    }
+
+    // A missing or invalid file is not fatal: no missed run detection at this start.
+    fn load_persisted_states() -> HashMap<String, RqTaskPersistedState> {
+        let path = Self::state_file_path();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return HashMap::new(),
+            Err(err) => {
+                log::error!("Cannot read task scheduler state file '{}': {}. Missed runs are not detected.", path, err);
+                return HashMap::new();
+            }
+        };
+        serde_json::from_str(&content).unwrap_or_else(|err| {
+            log::error!("Invalid task scheduler state file '{}': {}. Missed runs are not detected.", path, err);
+            HashMap::new()
+        })
+    }
+
+    fn update_persisted_state(&self, task_name: &str, update: impl FnOnce(&mut RqTaskPersistedState)) {
+        let mut persisted_states = self.persisted_states.lock_ignore_poison();
+        update(persisted_states.entry(task_name.to_string()).or_default());
+        let path = Self::state_file_path();
+        // Write to a temp file and rename, so a crash during the write doesn't lose the state. Small file, a few writes per day: the sync write is fine.
+        let tmp_path = format!("{}.tmp", path);
+        let save_result = fs::create_dir_all(rqcore_data_folder_path()).map_err(|err| format!("Cannot create '{}': {}", rqcore_data_folder_path(), err))
+            .and_then(|_| serde_json::to_string_pretty(&*persisted_states).map_err(|err| err.to_string()))
+            .and_then(|content| fs::write(&tmp_path, content).map_err(|err| format!("Cannot write '{}': {}", tmp_path, err)))
+            .and_then(|_| fs::rename(&tmp_path, &path).map_err(|err| format!("Cannot rename '{}' to '{}': {}", tmp_path, path, err)));
+        if let Err(err) = save_result {
+            log::error!("RqTaskScheduler: saving the state failed: {}", err);
+        }
     }
 
     pub fn schedule_task(&self, task: Arc<dyn RqTask>) {
-        let mut tasks = self.tasks.lock().unwrap();
-        tasks.push(task);
+        let mut tasks = self.tasks.lock_ignore_poison();
+        if tasks.iter().any(|e| e.task.name() == task.name()) {
+            log::error!("RqTaskScheduler: task '{}' is already scheduled. Ignored.", task.name());
+            return;
+        }
+        let next_trigger_time = task.schedule().next_trigger_time();
+        log::info!("RqTaskScheduler: task '{}' scheduled '{}', next trigger time: {}", task.name(), task.schedule(), next_trigger_time);
+        let mut entry = RqTaskEntry { task: task.clone(), next_trigger_time, state: RqTaskState::Active, running_count: 0, queued_count: 0, run_lock: Arc::new(TokioMutex::new(())), history: VecDeque::new() };
+
+        if let Some(missed_trigger_time) = self.find_missed_trigger_time(&task) {
+            let policy = get_rqcore_config().get(&format!("task_missed_run_policy.{}", task.name())).and_then(|v| RqTaskMissedRunPolicy::parse(v)).unwrap_or(task.missed_run_policy());
+            let reason = format!("Missed trigger time {} (the server was not running). Missed run policy: {:?}", missed_trigger_time.format("%Y-%m-%d %H:%M:%S UTC"), policy);
+            log::warn!("RqTaskScheduler: task '{}': {}", task.name(), reason);
+            let mut record = self.new_run_record(&task, RqTaskRunFlags::default(), false, RqTaskRunStatus::Missed, Some(reason));
+            record.started_at = missed_trigger_time;
+            Self::push_history(&mut entry, record.clone());
+            match policy {
+                RqTaskMissedRunPolicy::Skip => {}
+                RqTaskMissedRunPolicy::RunImmediately => entry.next_trigger_time = Utc::now(), // the scheduler loop runs it as a scheduled run
+                RqTaskMissedRunPolicy::Alert => send_task_alert_email(record),
+            }
+        }
+        tasks.push(entry);
+        self.tasks_changed.notify_one();
+    }
+
+    // The last trigger time between the persisted last trigger and now, if it is within the grace window. The older missed ones are only logged.
+    fn find_missed_trigger_time(&self, task: &Arc<dyn RqTask>) -> Option<DateTime<Utc>> {
+        let last_trigger_time = self.persisted_states.lock_ignore_poison().get(task.name()).and_then(|s| s.last_trigger_time)?; // first start: nothing to compare with
+        let now = Utc::now();
+        let (last_missed, num_missed) = find_last_missed_trigger_time(task.schedule(), last_trigger_time, now)?;
+        let grace = get_rqcore_config().get(&format!("task_missed_run_grace_sec.{}", task.name())).and_then(|v| v.parse::<u64>().ok())
+            .map(Duration::from_secs).unwrap_or(task.missed_run_grace());
+        let missed_ago = (now - last_missed).to_std().unwrap_or_default();
+        if missed_ago > grace {
+            log::info!("RqTaskScheduler: task '{}' missed {} trigger time(s) while the server was not running. The last one ({}) is out of the {}s grace window, ignored.", task.name(), num_missed, last_missed, grace.as_secs());
+            return None;
+        }
+        if num_missed > 1 {
+            log::info!("RqTaskScheduler: task '{}' missed {} trigger time(s), only the last one counts", task.name(), num_missed);
+        }
+        Some(last_missed)
+    }
+
+    pub fn list_tasks(&self) -> Vec<RqTaskInfo> {
+        let tasks = self.tasks.lock_ignore_poison();
+        tasks.iter().map(|e| RqTaskInfo {
+            name: e.task.name().to_string(),
+            schedule: e.task.schedule().to_string(),
+            overlap_policy: e.task.overlap_policy(),
+            timeout_sec: e.task.timeout().map(|t| t.as_secs()),
+            state: e.state,
+            running_count: e.running_count,
+            next_trigger_time: e.next_trigger_time,
+            last_run: e.history.back().cloned(),
+        }).collect()
+    }
+
+    // The run history of all the tasks (or of one task), newest first
+    pub fn get_run_history(&self, task_name: Option<&str>) -> Vec<RqTaskRunRecord> {
+        let tasks = self.tasks.lock_ignore_poison();
+        let mut history: Vec<RqTaskRunRecord> = tasks.iter()
+            .filter(|e| task_name.is_none_or(|name| e.task.name() == name))
+            .flat_map(|e| e.history.iter().cloned())
+            .collect();
+        history.sort_by(|a, b| b.id.cmp(&a.id));
+        history
+    }
+
+    pub fn pause_task(&self, name: &str) -> Result<(), String> {
+        self.change_task_state(name, RqTaskState::Paused)
     }
 
-    pub fn print_next_trigger_times(&self) {
-        let tasks = self.tasks.lock().unwrap();
-        for t in tasks.iter() {
-            println!("{} -> {}", t.name(), t.get_next_trigger_time());
+    pub fn resume_task(&self, name: &str) -> Result<(), String> {
+        self.change_task_state(name, RqTaskState::Active)
+    }
+
+    fn change_task_state(&self, name: &str, new_state: RqTaskState) -> Result<(), String> {
+        let mut tasks = self.tasks.lock_ignore_poison();
+        let entry = tasks.iter_mut().find(|e| e.task.name() == name).ok_or_else(|| format!("Task '{}' not found", name))?;
+        if entry.state == new_state {
+            return Err(format!("Task '{}' is already {:?}", name, new_state));
+        }
+        if new_state == RqTaskState::Active && entry.next_trigger_time <= Utc::now() {
+            entry.next_trigger_time = entry.task.schedule().next_trigger_time(); // skip the trigger times missed while paused
+        }
+        entry.state = new_state;
+        log::warn!("RqTaskScheduler: task '{}' is {:?} now", name, new_state);
+        self.tasks_changed.notify_one();
+        Ok(())
+    }
+
+    // A run in progress is not stopped. It finishes, but it is not recorded.
+    pub fn remove_task(&self, name: &str) -> Result<(), String> {
+        let mut tasks = self.tasks.lock_ignore_poison();
+        let index = tasks.iter().position(|e| e.task.name() == name).ok_or_else(|| format!("Task '{}' not found", name))?;
+        tasks.remove(index);
+        log::warn!("RqTaskScheduler: task '{}' removed", name);
+        self.tasks_changed.notify_one();
+        Ok(())
+    }
+
+    // Runs the task immediately (even if paused), without changing its next trigger time. The returned handle finishes when the run finishes.
+    // Err if the task is not found, or its overlap policy skipped the run.
+    pub fn run_task_now(&self, name: &str, flags: RqTaskRunFlags) -> Result<JoinHandle<()>, String> {
+        let task = {
+            let tasks = self.tasks.lock_ignore_poison();
+            tasks.iter().find(|e| e.task.name() == name).map(|e| e.task.clone()).ok_or_else(|| format!("Task '{}' not found", name))?
+        };
+        log::warn!("RqTaskScheduler: task '{}' run now (flags: {:?})", name, flags);
+        self.spawn_task_run(task, flags, true)
+    }
+
+    fn spawn_task_run(&self, task: Arc<dyn RqTask>, flags: RqTaskRunFlags, is_manual: bool) -> Result<JoinHandle<()>, String> {
+        // The overlap policy decision and the bookkeeping under the same tasks lock, so two triggers at the same time cannot both start
+        let (run_guard, lock_to_wait): (Option<OwnedMutexGuard<()>>, Option<Arc<TokioMutex<()>>>) = {
+            let mut tasks = self.tasks.lock_ignore_poison();
+            let entry = tasks.iter_mut().find(|e| Arc::ptr_eq(&e.task, &task)).ok_or_else(|| format!("Task '{}' was removed", task.name()))?;
+            let policy = task.overlap_policy();
+            let run_guard = match policy {
+                RqTaskOverlapPolicy::Allow => None,
+                _ => entry.run_lock.clone().try_lock_owned().ok(),
+            };
+            let is_previous_running = policy != RqTaskOverlapPolicy::Allow && run_guard.is_none();
+            let lock_to_wait = match get_overlap_action(policy, is_previous_running, entry.queued_count) {
+                RqTaskOverlapAction::Run => None,
+                RqTaskOverlapAction::Queue => {
+                    entry.queued_count += 1;
+                    Some(entry.run_lock.clone())
+                }
+                RqTaskOverlapAction::Skip(reason) => {
+                    let record = self.new_run_record(&task, flags, is_manual, RqTaskRunStatus::Skipped, Some(reason.clone()));
+                    Self::push_history(entry, record.clone());
+                    drop(tasks);
+                    log::warn!("RqTaskScheduler: task '{}' run skipped: {}", task.name(), reason);
+                    if !is_manual { // a skipped scheduled run can be a missed trading run. A manual run gets the Err back.
+                        send_task_alert_email(record);
+                    }
+                    return Err(format!("Task '{}' run skipped: {}", task.name(), reason));
+                }
+            };
+            (run_guard, lock_to_wait)
+        };
+
+        let run_future = async move {
+            let _run_guard = match lock_to_wait {
+                Some(run_lock) => {
+                    log::info!("RqTaskScheduler: task '{}' run is queued, it waits for the previous run", task.name());
+                    let guard = run_lock.lock_owned().await;
+                    RQ_TASK_SCHEDULER.update_task_entry(&task, |entry| entry.queued_count = entry.queued_count.saturating_sub(1));
+                    Some(guard)
+                }
+                None => run_guard,
+            };
+            let run_id = {
+                let record = RQ_TASK_SCHEDULER.new_run_record(&task, flags, is_manual, RqTaskRunStatus::Running, None);
+                let run_id = record.id;
+                RQ_TASK_SCHEDULER.update_task_entry(&task, |entry| {
+                    entry.running_count += 1;
+                    Self::push_history(entry, record);
+                });
+                run_id
+            };
+
+            let start = Instant::now();
+            let run_with_panic_capture = AssertUnwindSafe(task.run(flags)).catch_unwind(); // a panic fails only this run, and it is recorded
+            let (status, error) = match task.timeout() {
+                Some(timeout) => match tokio_time::timeout(timeout, run_with_panic_capture).await {
+                    Ok(run_result) => run_outcome(run_result),
+                    Err(_) => (RqTaskRunStatus::TimedOut, Some(format!("Timed out after {}s", timeout.as_secs()))),
+                },
+                None => run_outcome(run_with_panic_capture.await),
+            };
+            let duration_ms = start.elapsed().as_millis() as u64;
+            if status != RqTaskRunStatus::Ok {
+                log::error!("RqTaskScheduler: task '{}' run {:?} in {}ms: {}", task.name(), status, duration_ms, error.as_deref().unwrap_or(""));
+            }
+
+            let mut finished_record: Option<RqTaskRunRecord> = None;
+            RQ_TASK_SCHEDULER.update_task_entry(&task, |entry| {
+                entry.running_count = entry.running_count.saturating_sub(1);
+                if let Some(record) = entry.history.iter_mut().rev().find(|r| r.id == run_id) {
+                    record.status = status;
+                    record.duration_ms = Some(duration_ms);
+                    record.error = error;
+                    finished_record = Some(record.clone());
+                }
+            });
+            if let Some(record) = finished_record {
+                RQ_TASK_SCHEDULER.update_persisted_state(task.name(), |s| {
+                    s.last_run_started_at = Some(record.started_at);
+                    s.last_run_status = Some(record.status);
+                });
+                if record.status != RqTaskRunStatus::Ok {
+                    send_task_alert_email(record);
+                }
+            }
+        };
+        // A manual run from the web admin streams its log_and_println!() lines to the browser (see admin_actions.rs).
+        Ok(match RQ_LOG_CAPTURE.try_with(|sender| sender.clone()) {
+            Ok(sender) => tokio::spawn(RQ_LOG_CAPTURE.scope(sender, run_future)),
+            Err(_) => tokio::spawn(run_future), // Actix-web runs N (1 per CPU core) CurrentThread realtimes. For faster response times. So, spawn() tasks start to run when the current task awaits.
+        })
+    }
+
+    fn new_run_record(&self, task: &Arc<dyn RqTask>, flags: RqTaskRunFlags, is_manual: bool, status: RqTaskRunStatus, error: Option<String>) -> RqTaskRunRecord {
+        let id = {
+            let mut next_run_id = self.next_run_id.lock_ignore_poison();
+            *next_run_id += 1;
+            *next_run_id - 1
+        };
+        RqTaskRunRecord { id, task_name: task.name().to_string(), started_at: Utc::now(), is_manual, flags, status, duration_ms: None, error }
+    }
+
+    fn push_history(entry: &mut RqTaskEntry, record: RqTaskRunRecord) {
+        if entry.history.len() >= RQ_TASK_RUN_HISTORY_LEN {
+            entry.history.pop_front();
+        }
+        entry.history.push_back(record);
+    }
+
+    // The entry of this very task instance (Arc::ptr_eq), not only the same name: the task might have been removed and another one scheduled with its name.
+    fn update_task_entry(&self, task: &Arc<dyn RqTask>, update: impl FnOnce(&mut RqTaskEntry)) {
+        let mut tasks = self.tasks.lock_ignore_poison();
+        if let Some(entry) = tasks.iter_mut().find(|e| Arc::ptr_eq(&e.task, task)) {
+            update(entry);
         }
     }
 
@@ -78,49 +465,153 @@ impl RqTaskScheduler {
             log::debug!("RqTaskScheduler started");
             loop {
                 let now = Utc::now();
-                let mut due_tasks: Vec<Arc<dyn RqTask>> = Vec::new();
-                {
-                    let tasks = RQ_TASK_SCHEDULER.tasks.lock().unwrap();
-                    for task in tasks.iter() {
-                        let trigger = task.get_next_trigger_time();
-                        if trigger <= now {
-                            due_tasks.push(task.clone());
-                        }
-                    }
-                }
+                let due_tasks: Vec<Arc<dyn RqTask>> = {
+                    let mut tasks = RQ_TASK_SCHEDULER.tasks.lock_ignore_poison();
+                    tasks.iter_mut().filter(|e| e.state == RqTaskState::Active && e.next_trigger_time <= now).map(|e| {
+                        e.next_trigger_time = e.task.schedule().next_trigger_time_after(now); // their trigger time is in the past, so update it
+                        e.task.clone()
+                    }).collect()
+                };
 
                 // Spawn due tasks as separate async tasks (fire-and-forget, no awaiting);
                 for task in due_tasks {
-                    let task_clone = task.clone();
-                    tokio::spawn(async move { // Actix-web runs N (1 per CPU core) CurrentThread realtimes. For faster response times. So, spawn() tasks start to run when the current task awaits.
-                        task_clone.run().await;
-                    });
-                    task.update_next_trigger_time(); // their trigger time is in the past, so update it
+                    RQ_TASK_SCHEDULER.update_persisted_state(task.name(), |s| s.last_trigger_time = Some(now));
+                    let _ = RQ_TASK_SCHEDULER.spawn_task_run(task, RqTaskRunFlags::default(), false); // a skipped run is logged and recorded in the history
                 }
 
                 // Recompute soonest
-                let mut soonest: Option<DateTime<Utc>> = None;
-                {
-                    let tasks = RQ_TASK_SCHEDULER.tasks.lock().unwrap();
-                    for task in tasks.iter() {
-                        let trigger = task.get_next_trigger_time();
-                        match soonest {
-                            Some(s) if trigger < s => soonest = Some(trigger),
-                            None => soonest = Some(trigger),
-                            _ => {}
-                        }
-                    }
-                }
+                let soonest: Option<DateTime<Utc>> = {
+                    let tasks = RQ_TASK_SCHEDULER.tasks.lock_ignore_poison();
+                    tasks.iter().filter(|e| e.state == RqTaskState::Active).map(|e| e.next_trigger_time).min()
+                };
 
-                if let Some(s) = soonest {
-                    if s > now {
-                        let sleep_duration = (s - now).to_std().unwrap_or(std::time::Duration::from_secs(0));
-                        tokio_time::sleep(sleep_duration).await;
-                    }
-                } else {
-                    tokio_time::sleep(std::time::Duration::from_secs(60)).await;
+                let sleep_duration = match soonest {
+                    Some(s) => (s - now).to_std().unwrap_or(std::time::Duration::from_secs(0)),
+                    None => std::time::Duration::from_secs(60),
+                };
+                tokio::select! {
+                    _ = tokio_time::sleep(sleep_duration) => {}
+                    _ = RQ_TASK_SCHEDULER.tasks_changed.notified() => {} // a task was added, resumed or removed
                 }
             }
         });
     }
 }
+
+// The last trigger time after last_trigger_time, until now (inclusive), and the number of the missed trigger times. None if nothing was missed.
+fn find_last_missed_trigger_time(schedule: &RqSchedule, last_trigger_time: DateTime<Utc>, now: DateTime<Utc>) -> Option<(DateTime<Utc>, u32)> {
+    let mut last_missed: Option<DateTime<Utc>> = None;
+    let mut num_missed = 0;
+    let mut trigger_time = schedule.next_trigger_time_after(last_trigger_time);
+    while trigger_time <= now && num_missed < 100_000 { // the limit: e.g. 'every 1s' after a long downtime
+        last_missed = Some(trigger_time);
+        num_missed += 1;
+        trigger_time = schedule.next_trigger_time_after(trigger_time);
+    }
+    last_missed.map(|last_missed| (last_missed, num_missed))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RqTaskOverlapAction {
+    Run,
+    Queue,        // wait for the previous run
+    Skip(String), // the reason
+}
+
+// is_previous_running: a run holds the run_lock of the task (only the Skip and Queue policies take it)
+fn get_overlap_action(policy: RqTaskOverlapPolicy, is_previous_running: bool, queued_count: u32) -> RqTaskOverlapAction {
+    match policy {
+        _ if !is_previous_running => RqTaskOverlapAction::Run,
+        RqTaskOverlapPolicy::Allow => RqTaskOverlapAction::Run,
+        RqTaskOverlapPolicy::Queue if queued_count == 0 => RqTaskOverlapAction::Queue,
+        _ => RqTaskOverlapAction::Skip(format!("The previous run is still in progress (overlap policy: {:?}{})", policy, if queued_count > 0 { ", a run is already queued" } else { "" })),
+    }
+}
+
+fn run_outcome(run_result: Result<RqTaskResult, Box<dyn Any + Send>>) -> (RqTaskRunStatus, Option<String>) {
+    match run_result {
+        Ok(Ok(())) => (RqTaskRunStatus::Ok, None),
+        Ok(Err(err)) => (RqTaskRunStatus::Failed, Some(err)),
+        Err(panic_payload) => {
+            let panic_msg = panic_payload.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| panic_payload.downcast_ref::<String>().cloned())
+                .unwrap_or("unknown panic payload".to_string());
+            (RqTaskRunStatus::Panicked, Some(panic_msg))
+        }
+    }
+}
+
+// Failed, panicked, timed out and skipped scheduled runs, and missed runs (RqTaskMissedRunPolicy::Alert). Config key 'task_alert_email' (default: 'email_gyant'). Sent in the background, the scheduler doesn't wait for SMTP.
+fn send_task_alert_email(record: RqTaskRunRecord) {
+    let rqcore_config = get_rqcore_config();
+    let Some(email_to_address) = rqcore_config.get("task_alert_email").or(rqcore_config.get("email_gyant")).cloned() else {
+        log::warn!("RqTaskScheduler: no task_alert_email in the config, no alert email sent for task '{}'", record.task_name);
+        return;
+    };
+    tokio::spawn(async move {
+        let subject = format!("RqCore: task {} run {:?}", record.task_name, record.status);
+        let body = format!("Task: {}\nStarted: {}\nManual run: {} (flags: {:?})\nStatus: {:?}\nDuration: {}\nError: {}\n\nRun history: /serverdiagnostics",
+            record.task_name, record.started_at.format("%Y-%m-%d %H:%M:%S UTC"), record.is_manual, record.flags, record.status,
+            record.duration_ms.map(|ms| format!("{}ms", ms)).unwrap_or("-".to_string()), record.error.as_deref().unwrap_or("-"));
+        if let Err(err) = RqEmail::send_text(&email_to_address, &subject, &body).await {
+            log::error!("RqTaskScheduler: alert email failed: {}", err);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32, s: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, s).unwrap()
+    }
+
+    #[test]
+    fn missed_trigger_times() {
+        let every_10m = RqSchedule::parse("every 10m").unwrap();
+        let trading_days = RqSchedule::parse("09:45:10, 11:59:10 ET on trading days").unwrap();
+        let every_1s = RqSchedule::parse("every 1s").unwrap();
+        let cases = [
+            // (schedule, last trigger time, now, expected (last missed, number of missed))
+            (&every_10m, utc(2025, 3, 14, 14, 0, 0), utc(2025, 3, 14, 14, 5, 0), None),
+            (&every_10m, utc(2025, 3, 14, 14, 0, 0), utc(2025, 3, 14, 14, 10, 0), Some((utc(2025, 3, 14, 14, 10, 0), 1))), // a trigger time at now is missed
+            (&every_10m, utc(2025, 3, 14, 14, 0, 0), utc(2025, 3, 14, 14, 35, 0), Some((utc(2025, 3, 14, 14, 30, 0), 3))),
+            (&trading_days, utc(2025, 3, 14, 13, 45, 10), utc(2025, 3, 14, 15, 0, 0), None),
+            (&trading_days, utc(2025, 3, 14, 13, 45, 10), utc(2025, 3, 14, 16, 0, 0), Some((utc(2025, 3, 14, 15, 59, 10), 1))),
+            (&trading_days, utc(2025, 3, 14, 15, 59, 10), utc(2025, 3, 17, 14, 0, 0), Some((utc(2025, 3, 17, 13, 45, 10), 1))), // Friday => Monday: the weekend has no trigger time
+            (&trading_days, utc(2025, 4, 17, 15, 59, 10), utc(2025, 4, 22, 16, 0, 0), Some((utc(2025, 4, 22, 15, 59, 10), 4))), // over Good Friday and the weekend
+            (&every_1s, utc(2025, 3, 14, 14, 0, 0), utc(2025, 3, 17, 14, 0, 0), Some((utc(2025, 3, 15, 17, 46, 40), 100_000))), // the limit
+        ];
+        for (schedule, last_trigger_time, now, expected) in cases {
+            assert_eq!(find_last_missed_trigger_time(schedule, last_trigger_time, now), expected, "'{}' last: {}, now: {}", schedule, last_trigger_time, now);
+        }
+    }
+
+    #[test]
+    fn overlap_actions() {
+        use RqTaskOverlapPolicy::{Allow, Queue, Skip};
+        let action_name = |action: RqTaskOverlapAction| match action {
+            RqTaskOverlapAction::Run => "run",
+            RqTaskOverlapAction::Queue => "queue",
+            RqTaskOverlapAction::Skip(_) => "skip",
+        };
+        let cases = [
+            // (policy, is_previous_running, queued_count, expected action)
+            (Skip, false, 0, "run"),
+            (Skip, true, 0, "skip"),
+            (Queue, false, 0, "run"),
+            (Queue, false, 1, "run"), // the previous run finished, before the queued one took the run_lock
+            (Queue, true, 0, "queue"),
+            (Queue, true, 1, "skip"), // at most 1 run waits
+            (Allow, false, 0, "run"),
+            (Allow, true, 0, "run"),
+        ];
+        for (policy, is_previous_running, queued_count, expected) in cases {
+            assert_eq!(action_name(get_overlap_action(policy, is_previous_running, queued_count)), expected, "{:?}, previous running: {}, queued: {}", policy, is_previous_running, queued_count);
+        }
+        assert_eq!(get_overlap_action(Queue, true, 1), RqTaskOverlapAction::Skip("The previous run is still in progress (overlap policy: Queue, a run is already queued)".to_string()));
+    }
+}
@@ -0,0 +1,154 @@
+use std::{collections::BTreeMap, fs, sync::OnceLock};
+
+use rqcommon::utils::runningenv::{sensitive_config_folder_path, RqCoreConfig};
+
+// Declarative list of the virtual hosts (domains) served by the web server. Adding a new domain needs only rqcore.config lines, no code change:
+// websrv_vhosts=rqcore,thetaconite,localhost                  // order matters: the first vhost whose domains match the Host wins
+// websrv_default_vhost=localhost                              // used for IP connections and unknown hosts. Default: the last vhost.
+// websrv_vhost.thetaconite.domains=thetaconite.com,www.thetaconite.com
+// websrv_vhost.thetaconite.cert_path=/home/rquser/RQ/sensitive_data/https_certs/thetaconite.com/fullchain.pem   // Default: <sensitive_folder>https_certs/<first domain>/fullchain.pem
+// websrv_vhost.thetaconite.key_path=...                       // Default: <sensitive_folder>https_certs/<first domain>/privkey.pem
+// websrv_vhost.thetaconite.static_root=./static/taconite      // Default: ./static
+// websrv_vhost.thetaconite.index=./static/taconite/index.html // Default: <static_root>/index.html. Served at "/" for authorized users. {{USER_EMAIL}} is replaced.
+// websrv_vhost.thetaconite.index_nouser=./static/taconite/index_nouser.html // Default: <static_root>/index_nouser.html
+// websrv_vhost.thetaconite.oauth_config_prefix=taconite       // Google OAuth keys: <prefix>_google_client_id, <prefix>_google_client_secret. Default: the vhost name.
+// If 'websrv_vhosts' is missing from the config, the built-in vhosts are used (rqcore.com, thetaconite.com, localhost).
+// Vhosts are read only at startup (the certs and the static file services are bound then), so changes need a restart.
+
+// ---------- Global static variables ----------
+pub static WEB_VHOSTS: OnceLock<WebVhosts> = OnceLock::new();
+
+pub fn get_web_vhosts() -> &'static WebVhosts {
+    WEB_VHOSTS.get_or_init(WebVhosts::built_in) // not initialized yet (never happens after actix_websrv_run()). Returning the built-in vhosts.
+}
+
+// ---------- Class/struct definitions ----------
+#[derive(Debug, Clone)]
+pub struct WebVhost {
+    pub name: String,
+    pub domains: Vec<String>, // lowercase, without port
+    pub cert_path: String,
+    pub key_path: String,
+    pub static_root: String,
+    pub index_path: String,
+    pub index_nouser_path: String,
+    pub oauth_config_prefix: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebVhosts {
+    pub vhosts: Vec<WebVhost>,
+    pub default_index: usize, // index of the default vhost in vhosts
+}
+
+impl WebVhost {
+    fn new(name: &str, domains: &[&str], cert_base_path: &str, static_root: &str, oauth_config_prefix: &str) -> WebVhost {
+        let first_domain = domains.first().copied().unwrap_or(name);
+        WebVhost {
+            name: name.to_string(),
+            domains: domains.iter().map(|d| d.to_lowercase()).collect(),
+            cert_path: format!("{}{}/fullchain.pem", cert_base_path, first_domain),
+            key_path: format!("{}{}/privkey.pem", cert_base_path, first_domain),
+            static_root: static_root.to_string(),
+            index_path: format!("{}/index.html", static_root),
+            index_nouser_path: format!("{}/index_nouser.html", static_root),
+            oauth_config_prefix: oauth_config_prefix.to_string(),
+        }
+    }
+
+    pub fn matches_host(&self, host: &str) -> bool {
+        let host = host_without_port(host).to_lowercase();
+        self.domains.iter().any(|d| *d == host)
+    }
+}
+
+impl WebVhosts {
+    // The setup before the vhosts became configurable.
+    pub fn built_in() -> WebVhosts {
+        let cert_base_path = https_cert_base_path();
+        WebVhosts {
+            vhosts: vec![
+                WebVhost::new("rqcore", &["rqcore.com", "www.rqcore.com"], &cert_base_path, "./static", "rqcore"),
+                WebVhost::new("thetaconite", &["thetaconite.com", "www.thetaconite.com"], &cert_base_path, "./static/taconite", "taconite"),
+                // Default cert for 'localhost' and IP. Created as: openssl req -x509 -nodes -days 3650 -newkey rsa:2048 -keyout privkey.pem -out fullchain.pem -subj "/CN=localhost" -addext "subjectAltName=DNS:localhost,DNS:127.0.0.1"
+                WebVhost::new("localhost", &["localhost"], &cert_base_path, "./static", "rqcore"),
+            ],
+            default_index: 2,
+        }
+    }
+
+    pub fn from_config(cfg: &RqCoreConfig) -> Result<WebVhosts, String> {
+        let vhost_names: Vec<&str> = match cfg.get("websrv_vhosts") {
+            Some(names) => names.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()).collect(),
+            None => return Ok(WebVhosts::built_in()),
+        };
+        if vhost_names.is_empty() {
+            return Err("websrv_vhosts is empty".to_string());
+        }
+
+        // websrv_vhost.<name>.<field>=<value>. The name cannot contain '.', but the value can.
+        let mut vhost_fields: BTreeMap<(&str, &str), &str> = BTreeMap::new();
+        for (key, value) in cfg {
+            if let Some((name, field)) = key.strip_prefix("websrv_vhost.").and_then(|rest| rest.split_once('.')) {
+                if !vhost_names.contains(&name) {
+                    log::warn!("Config key '{}' refers to vhost '{}' that is not in websrv_vhosts. Ignored.", key, name);
+                    continue;
+                }
+                vhost_fields.insert((name, field), value.as_str());
+            }
+        }
+
+        let cert_base_path = https_cert_base_path();
+        let mut vhosts = Vec::with_capacity(vhost_names.len());
+        for name in &vhost_names {
+            let field = |field: &str| vhost_fields.get(&(*name, field)).map(|v| v.to_string());
+            let domains: Vec<&str> = match vhost_fields.get(&(*name, "domains")) {
+                Some(domains) => domains.split(',').map(|d| d.trim()).filter(|d| !d.is_empty()).collect(),
+                None => return Err(format!("websrv_vhost.{}.domains is missing", name)),
+            };
+            if domains.is_empty() {
+                return Err(format!("websrv_vhost.{}.domains is empty", name));
+            }
+            let static_root = field("static_root").unwrap_or("./static".to_string());
+            let oauth_config_prefix = field("oauth_config_prefix").unwrap_or(name.to_string());
+            let mut vhost = WebVhost::new(name, &domains, &cert_base_path, &static_root, &oauth_config_prefix);
+            if let Some(cert_path) = field("cert_path") { vhost.cert_path = cert_path; }
+            if let Some(key_path) = field("key_path") { vhost.key_path = key_path; }
+            if let Some(index_path) = field("index") { vhost.index_path = index_path; }
+            if let Some(index_nouser_path) = field("index_nouser") { vhost.index_nouser_path = index_nouser_path; }
+            vhosts.push(vhost);
+        }
+
+        let default_index = match cfg.get("websrv_default_vhost") {
+            Some(default_name) => vhost_names.iter().position(|n| n == default_name).ok_or_else(|| format!("websrv_default_vhost '{}' is not in websrv_vhosts", default_name))?,
+            None => vhosts.len() - 1,
+        };
+        Ok(WebVhosts { vhosts, default_index })
+    }
+
+    pub fn default_vhost(&self) -> &WebVhost {
+        &self.vhosts[self.default_index]
+    }
+
+    // Falls back to the default vhost for IPs and unknown hosts.
+    pub fn find_by_host(&self, host: &str) -> &WebVhost {
+        self.vhosts.iter().find(|v| v.matches_host(host)).unwrap_or_else(|| self.default_vhost())
+    }
+}
+
+fn https_cert_base_path() -> String {
+    format!("{}https_certs/", sensitive_config_folder_path())
+}
+
+// "rqcore.com:8443" => "rqcore.com". IPv6 "[::1]:8443" => "[::1]"
+pub fn host_without_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((host_part, port)) if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) && (!host_part.contains(':') || host_part.ends_with(']')) => host_part,
+        _ => host,
+    }
+}
+
+// The index templates are read at runtime (not include_str!), so they can be configured per vhost and edited without recompilation.
+pub fn read_index_template(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|err| format!("Cannot read index template '{}': {}", path, err))
+}
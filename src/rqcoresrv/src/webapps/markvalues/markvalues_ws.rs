@@ -0,0 +1,175 @@
+use std::{collections::{HashMap, HashSet}, time::Duration};
+use actix_ws::{Message, Session};
+use actix_web::{get, middleware::from_fn, web, HttpRequest, HttpResponse, Result};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+
+use rqcommon::rqhelper::MutexExt;
+use memdb::{mark_value_cache::{MarkValueUpdate, QuoteStreamGuard, RQ_MARK_VALUE_CACHE}, quote_book::RQ_QUOTE_BOOK};
+use crate::middleware::authorization::{is_same_origin_request, require_viewer, AuthorizedUser};
+
+// Live MarkValues for browser clients. The YF quote stream runs while at least one client (or a FastRunner task) is connected (see QuoteStreamGuard).
+// Connect: /ws/markvalues?tickers=AAPL,TSLA&throttle_ms=1000
+// Client requests: {"type": "subscribe", "tickers": ["NVDA"]}, {"type": "unsubscribe", "tickers": ["AAPL"]}
+// Server messages: {"type": "markvalues", "data": [{"ticker", "mark_value", "mark_time", "source"}]}: at most one message per throttle_ms, with the last value of each changed ticker.
+// At connect and at subscribe, the current values of the (new) tickers are sent immediately. Only the tickers in the MarkValueCache universe get updates.
+
+const MARKVALUES_DEFAULT_THROTTLE_MS: u64 = 1000;
+const MARKVALUES_MIN_THROTTLE_MS: u64 = 250;
+const MARKVALUES_MAX_THROTTLE_MS: u64 = 60_000;
+
+#[derive(Deserialize, Debug)]
+struct MarkValuesWsQuery {
+    tickers: Option<String>, // comma separated
+    throttle_ms: Option<u64>,
+}
+
+#[get("/ws/markvalues", wrap = "from_fn(require_viewer)")]
+pub async fn markvalues_websocket(req: HttpRequest, body: web::Payload, user: AuthorizedUser) -> Result<HttpResponse> {
+    // Another site's page could open this websocket with the viewer's cookie, read the marks and keep the quote stream running (cross-site websocket hijacking).
+    if user.api_token_id.is_none() && !is_same_origin_request(&req) {
+        log::warn!("Cross-site markvalues_websocket rejected: {}", user.display_name());
+        return Ok(HttpResponse::Forbidden().body("Cross-site request rejected"));
+    }
+    let query = web::Query::<MarkValuesWsQuery>::from_query(req.query_string()).map(|q| q.into_inner()).unwrap_or(MarkValuesWsQuery { tickers: None, throttle_ms: None });
+    let throttle_ms = query.throttle_ms.unwrap_or(MARKVALUES_DEFAULT_THROTTLE_MS).clamp(MARKVALUES_MIN_THROTTLE_MS, MARKVALUES_MAX_THROTTLE_MS);
+    let mut tickers: HashSet<String> = query.tickers.as_deref().map(parse_tickers_csv).unwrap_or_default();
+
+    let (response, mut ws_session, mut msg_stream) = actix_ws::handle(&req, body)?;
+    log::info!("markvalues_websocket opened - user = {}, tickers: {}", user.display_name(), tickers.len());
+
+    let quote_stream_guard = QuoteStreamGuard::acquire();
+    let mut update_receiver = RQ_MARK_VALUE_CACHE.lock_ignore_poison().subscribe_updates(); // subscribe before reading the current values, so no tick is missed
+
+    actix_web::rt::spawn(async move {
+        let _quote_stream_guard = quote_stream_guard; // stop_quote_stream() when this task ends, whatever the reason
+
+        let universe = get_ticker_universe();
+        let handshake_msg = json!({"type": "onconnected", "user": user.email, "throttle_ms": throttle_ms, "universe": universe.iter().collect::<Vec<_>>()});
+        if ws_session.text(handshake_msg.to_string()).await.is_err() {
+            return;
+        }
+        let mut pending: HashMap<String, MarkValueUpdate> = HashMap::new(); // ticker => last update since the last send
+        let query_tickers = std::mem::take(&mut tickers);
+        if subscribe_tickers(&mut ws_session, &mut tickers, query_tickers, &universe, &mut pending).await.is_err() {
+            return;
+        }
+
+        let mut throttle_interval = tokio::time::interval(Duration::from_millis(throttle_ms));
+        throttle_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                client_msg = msg_stream.next() => {
+                    let Some(client_msg) = client_msg else { break; };
+                    match client_msg {
+                        Ok(Message::Text(text)) => {
+                            if handle_client_request(&mut ws_session, &mut tickers, &mut pending, &text).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(Message::Ping(bytes)) => {
+                            if ws_session.pong(&bytes).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(Message::Close(reason)) => {
+                            let _ = ws_session.close(reason).await;
+                            log::info!("markvalues_websocket closed by the client");
+                            return;
+                        }
+                        Err(e) => {
+                            log::error!("markvalues_websocket error: {:?}", e);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                update = update_receiver.recv() => {
+                    match update {
+                        Ok(update) if tickers.contains(&update.ticker) => { pending.insert(update.ticker.clone(), update); }
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(_)) => pending.extend(current_mark_values(&tickers).into_iter().filter(|(_, u)| !u.mark_value.is_nan())), // some ticks were dropped: send the current values instead
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+                _ = throttle_interval.tick() => {
+                    if !pending.is_empty() && send_mark_values(&mut ws_session, pending.drain().map(|(_, update)| update).collect()).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        let _ = ws_session.close(None).await;
+        log::info!("markvalues_websocket session ended");
+    });
+    Ok(response)
+}
+
+// Err: the websocket is closed
+async fn handle_client_request(ws_session: &mut Session, tickers: &mut HashSet<String>, pending: &mut HashMap<String, MarkValueUpdate>, text: &str) -> Result<(), actix_ws::Closed> {
+    let Ok(client_request) = serde_json::from_str::<Value>(text) else {
+        return ws_session.text(json!({"type": "error", "message": "Invalid JSON format"}).to_string()).await;
+    };
+    let requested_tickers: HashSet<String> = client_request["tickers"].as_array()
+        .map(|a| a.iter().filter_map(|t| t.as_str()).map(|t| t.trim().to_uppercase()).filter(|t| !t.is_empty()).collect())
+        .unwrap_or_default();
+
+    let request_type = client_request["type"].as_str().unwrap_or("");
+    match request_type {
+        "subscribe" => subscribe_tickers(ws_session, tickers, requested_tickers, &get_ticker_universe(), pending).await,
+        "unsubscribe" => {
+            for ticker in requested_tickers.iter() {
+                tickers.remove(ticker);
+                pending.remove(ticker);
+            }
+            Ok(())
+        }
+        _ => ws_session.text(json!({"type": "error", "message": format!("Unknown command: {}", request_type)}).to_string()).await,
+    }
+}
+
+// Sends the current values of the new tickers at once (not throttled), and warns about the tickers that are not streamed.
+async fn subscribe_tickers(ws_session: &mut Session, tickers: &mut HashSet<String>, new_tickers: HashSet<String>, universe: &HashSet<String>, pending: &mut HashMap<String, MarkValueUpdate>) -> Result<(), actix_ws::Closed> {
+    let new_tickers: HashSet<String> = new_tickers.difference(tickers).cloned().collect();
+    tickers.extend(new_tickers.iter().cloned());
+    let mut not_in_universe: Vec<&String> = new_tickers.iter().filter(|t| !universe.contains(*t)).collect();
+    if !not_in_universe.is_empty() {
+        not_in_universe.sort();
+        ws_session.text(json!({"type": "warning", "message": "These tickers are not in the MarkValueCache universe, they get no updates", "tickers": not_in_universe}).to_string()).await?;
+    }
+    for update in current_mark_values(&new_tickers).into_values() {
+        pending.remove(&update.ticker);
+        if !update.mark_value.is_nan() {
+            pending.insert(update.ticker.clone(), update);
+        }
+    }
+    if pending.is_empty() {
+        return Ok(());
+    }
+    send_mark_values(ws_session, pending.drain().map(|(_, update)| update).collect()).await
+}
+
+async fn send_mark_values(ws_session: &mut Session, mut updates: Vec<MarkValueUpdate>) -> Result<(), actix_ws::Closed> {
+    updates.sort_by(|a, b| a.ticker.cmp(&b.ticker));
+    let data: Vec<Value> = updates.iter().map(|u| json!({"ticker": u.ticker, "mark_value": u.mark_value, "mark_time": u.mark_time, "source": u.source.to_string()})).collect();
+    ws_session.text(json!({"type": "markvalues", "data": data}).to_string()).await
+}
+
+fn current_mark_values(tickers: &HashSet<String>) -> HashMap<String, MarkValueUpdate> {
+    tickers.iter()
+        .map(|ticker| {
+            let (mark_value, mark_time, source) = RQ_QUOTE_BOOK.get_mark_timevalue_source(ticker);
+            (ticker.clone(), MarkValueUpdate { ticker: ticker.clone(), mark_value, mark_time, source })
+        })
+        .collect()
+}
+
+fn get_ticker_universe() -> HashSet<String> {
+    RQ_MARK_VALUE_CACHE.lock_ignore_poison().get_ticker_universe().into_iter().collect()
+}
+
+fn parse_tickers_csv(tickers_csv: &str) -> HashSet<String> {
+    tickers_csv.split(',').map(|t| t.trim().to_uppercase()).filter(|t| !t.is_empty()).collect()
+}
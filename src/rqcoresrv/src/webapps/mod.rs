@@ -4,4 +4,9 @@ pub mod test_websocket {
 
 pub mod robotrader_ui {
     pub mod robotrader_ui;
+    pub mod robotrader_api;
+}
+
+pub mod markvalues {
+    pub mod markvalues_ws;
 }
\ No newline at end of file
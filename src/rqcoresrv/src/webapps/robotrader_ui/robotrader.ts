@@ -49,8 +49,16 @@ window.onload = () => {
             case "onconnected":
                 user_email.innerText = data.user;
                 break;
-            case "executed_orders":
-                console.log(data.symbol, data.price);
+            case "snapshot": // recent events of the subscribed topics (at connect, at subscribe, or "resync" after the client lagged)
+                for (const rq_event of data.events)
+                    console.log("snapshot", rq_event.topic, rq_event.kind, rq_event.data);
+                break;
+            case "event": // server push
+                console.log("event", data.event.topic, data.event.kind, data.event.data);
+                break;
+            case "executed_orders": // the same as GET /api/robotrader/executions
+                for (const execution of data.data)
+                    console.log(execution.broker_client, execution.symbol, execution.side, execution.shares, execution.price);
                 break;
         }
     };
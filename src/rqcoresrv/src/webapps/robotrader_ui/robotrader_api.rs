@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+use actix_web::{get, middleware::from_fn, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::America::New_York;
+use serde::{Deserialize, Serialize};
+
+use rqcommon::rqhelper::MutexExt;
+use memdb::{bars::{Bar, BarPeriod}, daily_history::{DailyBar, RQ_DAILY_HISTORY}, feed::FeedStats, mark_value_cache::{get_quote_stream_health, RQ_MARK_VALUE_CACHE, TICKER_GROUP_MANUAL}, quote_book::RQ_QUOTE_BOOK};
+
+use crate::{middleware::authorization::{is_same_origin_request, require_trader, require_viewer, AuthorizedUser}, robotrader::{fast_runner_task::FASTRUNNER_LAST_RUNS, robotrader::RQ_ROBO_TRADER}, services::rqtask_scheduler::RQ_TASK_SCHEDULER};
+
+// JSON API of the RoboTrader state for the robotrader/index.html and for scripts (with an API token: 'Authorization: Bearer rqt_...').
+// GET /api/robotrader/executions             today's executions per BrokerClient (as refreshed by RoboTrader at startup)
+// GET /api/robotrader/scheduledtasks         the scheduled tasks: state (active/paused), next trigger time, last run (duration, result)
+// GET /api/robotrader/scheduledtasks/history?task=FastRunnerPqpTask  the task run history (status, duration, error), newest first. Without 'task': all tasks.
+// GET /api/robotrader/fastrunner/lastruns    the last run summary of each FastRunner task
+// GET /api/robotrader/markvalues             the MarkValueCache contents, and the latency statistics of its feeds (YF, IB)
+// GET /api/robotrader/markvalues/bars?ticker=AAPL&period=1m&n=30   the last n (default 60) OHLCV bars of the trades. period: 1s or 1m (default)
+// GET /api/robotrader/markvalues/vwap?ticker=AAPL&since=2026-03-09T16:58:00Z   the VWAP of the trades since then (null if no trade with volume)
+// GET /api/robotrader/dailyhistory?ticker=AAPL&n=20   the daily history: previous close, 1/5/20-day returns, 20/60-day volatilities, and the last n (default 20) daily bars
+// GET /api/robotrader/markvalues/health      the quote stream health: status, last tick time, ticks per minute, tickers without (fresh) data, feed restarts
+// GET /api/robotrader/markvalues/universe    the streamed tickers, per group (manual, fastrunner)
+// The /api/robotrader/ endpoints are read-only. The only state-changing one is outside of it (trader role, same origin or API token):
+// POST /admin/markvalues/universe            {"add": ["AAPL"], "remove": ["TSLA"]}: changes the 'manual' group. A running quote stream resubscribes (once).
+// Executions and FastRunner logs are trading data: trader role. Task times, mark values and daily history: viewer role. The universe change: trader role.
+
+#[derive(Debug, Serialize)]
+pub struct ExecutionJson {
+    pub broker_client: String,
+    pub symbol: String,
+    pub side: String,
+    pub shares: f64,
+    pub price: f64,
+    pub time: String,
+    pub exchange: String,
+    pub order_id: i32,
+    pub execution_id: String,
+    pub commission: Option<f64>, // from the matching CommissionReport. IB sends it a little later than the execution.
+    pub realized_pnl: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct MarkValueJson {
+    ticker: String,
+    mark_value: Option<f64>, // None (null) if no price has arrived yet
+    mark_time: Option<DateTime<Utc>>,
+    source: String, // none, snapshot (REST quote, mark_time is the fetch time) or stream (mark_time is the trade time)
+    bid: Option<f64>,
+    ask: Option<f64>,
+    last: Option<f64>,
+    volume: Option<u64>, // cumulative daily volume
+}
+
+#[derive(Debug, Serialize)]
+struct MarkValuesJson {
+    is_quote_stream_running: bool,
+    mark_values: Vec<MarkValueJson>,
+    feed_stats: Vec<FeedStats>,
+}
+
+// Also used by the robotrader websocket 'getexecutedorders' request.
+pub fn get_executions_json() -> Vec<ExecutionJson> {
+    let order_executions = RQ_ROBO_TRADER.order_executions.lock_ignore_poison();
+    let mut executions: Vec<ExecutionJson> = Vec::new();
+    for (broker_client, (execution_datas, commission_reports)) in order_executions.iter() {
+        for execution_data in execution_datas.iter() {
+            let execution = &execution_data.execution;
+            let commission_report = commission_reports.iter().find(|c| c.execution_id == execution.execution_id);
+            executions.push(ExecutionJson {
+                broker_client: format!("{:?}", broker_client),
+                symbol: execution_data.contract.symbol.to_string(),
+                side: execution.side.clone(),
+                shares: execution.shares,
+                price: execution.price,
+                time: execution.time.clone(),
+                exchange: execution.exchange.clone(),
+                order_id: execution.order_id,
+                execution_id: execution.execution_id.clone(),
+                commission: commission_report.map(|c| c.commission),
+                realized_pnl: commission_report.and_then(|c| c.realized_pnl),
+            });
+        }
+    }
+    executions.sort_by(|a, b| (&a.broker_client, &a.time).cmp(&(&b.broker_client, &b.time))); // HashMap order is random. Keep the response stable for the scripts.
+    executions
+}
+
+#[get("/api/robotrader/executions", wrap = "from_fn(require_trader)")]
+pub async fn api_executions() -> impl Responder {
+    HttpResponse::Ok().json(get_executions_json())
+}
+
+#[get("/api/robotrader/scheduledtasks", wrap = "from_fn(require_viewer)")]
+pub async fn api_scheduled_tasks() -> impl Responder {
+    let mut tasks = RQ_TASK_SCHEDULER.list_tasks();
+    tasks.sort_by_key(|t| t.next_trigger_time);
+    HttpResponse::Ok().json(tasks)
+}
+
+#[derive(Debug, Deserialize)]
+struct TaskHistoryQuery {
+    task: Option<String>,
+}
+
+#[get("/api/robotrader/scheduledtasks/history", wrap = "from_fn(require_viewer)")]
+pub async fn api_scheduled_tasks_history(query: web::Query<TaskHistoryQuery>) -> impl Responder {
+    HttpResponse::Ok().json(RQ_TASK_SCHEDULER.get_run_history(query.task.as_deref()))
+}
+
+#[get("/api/robotrader/fastrunner/lastruns", wrap = "from_fn(require_trader)")]
+pub async fn api_fastrunner_last_runs() -> impl Responder {
+    let mut last_runs: Vec<_> = FASTRUNNER_LAST_RUNS.lock_ignore_poison().values().cloned().collect();
+    last_runs.sort_by(|a, b| a.task_name.cmp(&b.task_name));
+    HttpResponse::Ok().json(last_runs)
+}
+
+#[get("/api/robotrader/markvalues", wrap = "from_fn(require_viewer)")]
+pub async fn api_mark_values() -> impl Responder {
+    let response = {
+        let to_option = |value: f64| if value.is_nan() { None } else { Some(value) };
+        let (mark_policy, now) = (RQ_QUOTE_BOOK.get_mark_policy(), Utc::now());
+        let mark_values: Vec<MarkValueJson> = RQ_QUOTE_BOOK.get_quotes().into_iter() // sorted by ticker
+            .map(|(ticker, quote)| {
+                let (mark_value, mark_time, source) = quote.mark(mark_policy, now); // the mark derived by the mark_policy
+                MarkValueJson {
+                    ticker,
+                    mark_value: to_option(mark_value),
+                    mark_time: if mark_time == DateTime::<Utc>::UNIX_EPOCH { None } else { Some(mark_time) }, // UNIX_EPOCH for the tickers without a price
+                    source: source.to_string(),
+                    bid: to_option(quote.bid),
+                    ask: to_option(quote.ask),
+                    last: to_option(quote.last),
+                    volume: quote.volume,
+                }
+            })
+            .collect();
+        let mark_value_cache = RQ_MARK_VALUE_CACHE.lock_ignore_poison();
+        MarkValuesJson { is_quote_stream_running: mark_value_cache.is_quote_stream_running(), mark_values, feed_stats: mark_value_cache.get_feed_stats() }
+    };
+    HttpResponse::Ok().json(response)
+}
+
+#[derive(Debug, Deserialize)]
+struct TickerUniverseChange {
+    #[serde(default)]
+    add: Vec<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct TickerUniverseJson {
+    universe: Vec<String>,
+    groups: BTreeMap<String, Vec<String>>,
+}
+
+fn get_ticker_universe_json() -> TickerUniverseJson {
+    let mark_value_cache = RQ_MARK_VALUE_CACHE.lock_ignore_poison();
+    TickerUniverseJson {
+        universe: mark_value_cache.get_ticker_universe().into_iter().collect(),
+        groups: mark_value_cache.get_ticker_groups().iter().map(|(group, tickers)| (group.clone(), tickers.iter().cloned().collect())).collect(),
+    }
+}
+
+#[get("/api/robotrader/markvalues/health", wrap = "from_fn(require_viewer)")]
+pub async fn api_quote_stream_health() -> impl Responder {
+    let quote_stream_health = get_quote_stream_health();
+    HttpResponse::Ok().json(quote_stream_health)
+}
+
+#[get("/api/robotrader/markvalues/universe", wrap = "from_fn(require_viewer)")]
+pub async fn api_ticker_universe() -> impl Responder {
+    HttpResponse::Ok().json(get_ticker_universe_json())
+}
+
+#[post("/admin/markvalues/universe", wrap = "from_fn(require_trader)")]
+pub async fn api_change_ticker_universe(req: HttpRequest, change: web::Json<TickerUniverseChange>, user: AuthorizedUser) -> impl Responder {
+    if user.api_token_id.is_none() && !is_same_origin_request(&req) {
+        log::warn!("Cross-site ticker universe change rejected: {}", user.display_name());
+        return HttpResponse::Forbidden().body("Cross-site request rejected");
+    }
+    let (added, removed) = RQ_MARK_VALUE_CACHE.lock_ignore_poison().change_tickers(TICKER_GROUP_MANUAL, &change.add, &change.remove);
+    log::info!("Ticker universe changed by {}: added: {:?}, removed: {:?}", user.display_name(), added, removed);
+    HttpResponse::Ok().json(get_ticker_universe_json())
+}
+
+const BARS_DEFAULT_N: usize = 60;
+
+#[derive(Debug, Deserialize)]
+struct BarsQuery {
+    ticker: String,
+    period: Option<String>,
+    n: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct BarsJson {
+    ticker: String,
+    period: String,
+    bars: Vec<Bar>,
+}
+
+#[get("/api/robotrader/markvalues/bars", wrap = "from_fn(require_viewer)")]
+pub async fn api_mark_value_bars(query: web::Query<BarsQuery>) -> impl Responder {
+    let period_str = query.period.as_deref().unwrap_or("1m");
+    let Some(period) = BarPeriod::parse(period_str) else {
+        return HttpResponse::BadRequest().body(format!("Invalid period '{}'. Expected 1s or 1m.", period_str));
+    };
+    let ticker = query.ticker.trim().to_uppercase();
+    let bars = RQ_MARK_VALUE_CACHE.lock_ignore_poison().get_last_bars(&ticker, period, query.n.unwrap_or(BARS_DEFAULT_N));
+    HttpResponse::Ok().json(BarsJson { ticker, period: period_str.to_string(), bars })
+}
+
+#[derive(Debug, Deserialize)]
+struct VwapQuery {
+    ticker: String,
+    since: DateTime<Utc>,
+}
+
+#[get("/api/robotrader/markvalues/vwap", wrap = "from_fn(require_viewer)")]
+pub async fn api_mark_value_vwap(query: web::Query<VwapQuery>) -> impl Responder {
+    let ticker = query.ticker.trim().to_uppercase();
+    let vwap = RQ_MARK_VALUE_CACHE.lock_ignore_poison().get_vwap_since(&ticker, query.since);
+    HttpResponse::Ok().json(serde_json::json!({"ticker": ticker, "since": query.since, "vwap": if vwap.is_nan() { None } else { Some(vwap) }}))
+}
+
+const DAILY_HISTORY_DEFAULT_N: usize = 20;
+
+#[derive(Debug, Deserialize)]
+struct DailyHistoryQuery {
+    ticker: String,
+    n: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct DailyHistoryJson {
+    ticker: String,
+    last_date: Option<NaiveDate>,
+    prev_close: Option<f64>, // the close before today (ET): the base of today's % change
+    return_1d: Option<f64>,
+    return_5d: Option<f64>,
+    return_20d: Option<f64>,
+    volatility_20d: Option<f64>, // annualized
+    volatility_60d: Option<f64>,
+    bars: Vec<DailyBar>,
+}
+
+#[get("/api/robotrader/dailyhistory", wrap = "from_fn(require_viewer)")]
+pub async fn api_daily_history(query: web::Query<DailyHistoryQuery>) -> impl Responder {
+    let ticker = query.ticker.trim().to_uppercase();
+    let today_et = Utc::now().with_timezone(&New_York).date_naive();
+    let non_nan = |value: f64| if value.is_nan() { None } else { Some(value) };
+    let daily_history = RQ_DAILY_HISTORY.lock_ignore_poison();
+    let bars = daily_history.get_bars(&ticker);
+    let daily_history_json = DailyHistoryJson {
+        last_date: daily_history.get_last_date(&ticker),
+        prev_close: non_nan(daily_history.get_prev_close(&ticker, today_et)),
+        return_1d: non_nan(daily_history.get_n_day_return(&ticker, 1, today_et)),
+        return_5d: non_nan(daily_history.get_n_day_return(&ticker, 5, today_et)),
+        return_20d: non_nan(daily_history.get_n_day_return(&ticker, 20, today_et)),
+        volatility_20d: non_nan(daily_history.get_volatility(&ticker, 20, today_et)),
+        volatility_60d: non_nan(daily_history.get_volatility(&ticker, 60, today_et)),
+        bars: bars[bars.len().saturating_sub(query.n.unwrap_or(DAILY_HISTORY_DEFAULT_N))..].to_vec(),
+        ticker,
+    };
+    HttpResponse::Ok().json(daily_history_json)
+}
@@ -1,77 +1,146 @@
-use actix_ws::{Message};
-use actix_web::{get, HttpRequest, HttpResponse, Result};
+use std::{collections::HashSet, sync::Arc};
+use actix_ws::{Message, Session};
+use actix_web::{get, middleware::from_fn, web, HttpRequest, HttpResponse, Result};
 use futures_util::StreamExt;
+use serde::Deserialize;
 use serde_json::{json, Value};
-use actix_identity::Identity;
+use tokio::sync::broadcast::error::RecvError;
+
+use rqcommon::utils::rqevent_hub::{RqEvent, RqEventTopic, RQ_EVENT_HUB};
+use crate::{middleware::authorization::{is_same_origin_request, require_trader, AuthorizedUser}, webapps::robotrader_ui::robotrader_api::get_executions_json};
+
+// Server push: the RQ_EVENT_HUB events (FastRunner status, signals, orders, gateway status) are forwarded to the client as they happen.
+// Connect: /ws/robotrader_websocket?topics=orders,gateway (default: all topics). The server sends 'onconnected', then a 'snapshot' of the recent events of the topics.
+// Client requests: {"type": "subscribe", "topics": ["signals"]} (a snapshot of the new topics follows), {"type": "unsubscribe", "topics": [...]}, {"type": "getexecutedorders"}
+// Server messages: {"type": "event", "event": {id, topic, kind, time, data}}. If the client is too slow and events were dropped, a new 'snapshot' is sent with "resync": true.
+
+#[derive(Deserialize, Debug)]
+struct RobotraderWsQuery {
+    topics: Option<String>, // comma separated
+}
+
+#[get("/ws/robotrader_websocket", wrap = "from_fn(require_trader)")] // the authorization is checked before the websocket upgrade
+pub async fn robotrader_websocket(req: HttpRequest, body: web::Payload, user: AuthorizedUser) -> Result<HttpResponse> {
+    // Another site's page could open this websocket with the trader's cookie and read the orders and fills (cross-site websocket hijacking).
+    if user.api_token_id.is_none() && !is_same_origin_request(&req) {
+        log::warn!("Cross-site robotrader_websocket rejected: {}", user.display_name());
+        return Ok(HttpResponse::Forbidden().body("Cross-site request rejected"));
+    }
+    let mut topics: HashSet<RqEventTopic> = match web::Query::<RobotraderWsQuery>::from_query(req.query_string()).ok().and_then(|q| q.into_inner().topics) {
+        Some(topics_csv) => topics_csv.split(',').filter_map(|t| RqEventTopic::parse(t.trim())).collect(),
+        None => RqEventTopic::ALL.into_iter().collect(),
+    };
 
-#[get("/ws/robotrader_websocket")]
-pub async fn robotrader_websocket(req: HttpRequest, body: actix_web::web::Payload, identity: Option<Identity>,) -> Result<HttpResponse> {
     let (response, mut ws_session, mut msg_stream) = actix_ws::handle(&req, body)?;
-    log::info!("WebSocket session Opened");
-    let user_email = identity.and_then(|id| id.id().ok()).unwrap_or("anonymous".to_string());
-    log::info!("robotrader_websocket - user = {}", user_email);
+    log::info!("robotrader_websocket opened - user = {}", user.display_name());
 
-    let handshake_msg = json!({"type": "onconnected", "user": user_email});
+    let handshake_msg = json!({"type": "onconnected", "user": user.email, "topics": sorted_topics(&topics)});
     if let Err(e) = ws_session.text(handshake_msg.to_string()).await {
         log::error!("WebSocket send error: {}", e);
     }
 
+    let (snapshot, mut event_receiver) = RQ_EVENT_HUB.subscribe_with_snapshot(&sorted_topics(&topics));
+    if send_snapshot(&mut ws_session, &topics, snapshot, false).await.is_err() {
+        return Ok(response);
+    }
+
     // Spawn websocket message handler
     actix_web::rt::spawn(async move {
-        while let Some(client_msg) = msg_stream.next().await {
-            match client_msg {
-                Ok(Message::Text(text)) => {
-                    log::info!("robotrader_websocket message: {}", text);
-                    let client_request: Value;
-                    if let Ok(parsed_request) = serde_json::from_str(&text) {
-                        client_request = parsed_request;
-                    } else {
-                        let server_response = json!({
-                            "type": "error",
-                            "message": "Invalid JSON format"
-                        });
-                        let _ = ws_session.text(server_response.to_string()).await;
-                        continue;
-                    }
-                    // Extract request type
-                    let request_type = client_request["type"].as_str().unwrap_or("");
-                    match request_type {
-                        "getexecutedorders" => { // Example request
-                            let executed_orders = vec![json!({"symbol": "AAPL", "price": 190}),json!({"symbol": "TSLA", "price": 220})]; // Example response data
-                            let server_response = json!({
-                                "type": "executed_orders",
-                                "data": executed_orders
-                            });
-
-                            if ws_session.text(server_response.to_string()).await.is_err() {
+        loop {
+            tokio::select! {
+                client_msg = msg_stream.next() => {
+                    let Some(client_msg) = client_msg else { break; };
+                    match client_msg {
+                        Ok(Message::Text(text)) => {
+                            log::info!("robotrader_websocket message: {}", text);
+                            if handle_client_request(&mut ws_session, &mut topics, &text).await.is_err() {
                                 break;
                             }
                         }
-                        _ => { // Unknown command
-                            let server_response = json!({
-                                "type": "error",
-                                "message": format!("Unknown command: {}", request_type)
-                            });
-                            let _ = ws_session.text(server_response.to_string()).await;
+                        Ok(Message::Ping(bytes)) => {
+                            if ws_session.pong(&bytes).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(Message::Close(reason)) => {
+                            log::info!("WebSocket closed: {:?}", reason);
+                            let _ = ws_session.close(reason).await;
+                            return;
+                        }
+                        Err(e) => {
+                            log::error!("WebSocket error: {:?}", e);
+                            break;
+                        }
+                        _ => {
+                            log::info!("WS: Unsupported message type");
                         }
                     }
                 }
-                Ok(Message::Close(reason)) => {
-                    println!("WebSocket closed: {:?}", reason);
-                    let _ = ws_session.close(reason).await;
-                    break;
-                }
-                Err(e) => {
-                    log::error!("WebSocket error: {:?}", e);
-                    break;
-                }
-                _ => {
-                    log::info!("WS: Unsupported message type");
-                    continue;
+                event = event_receiver.recv() => {
+                    match event {
+                        Ok(event) if topics.contains(&event.topic) => {
+                            if ws_session.text(json!({"type": "event", "event": event.as_ref()}).to_string()).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(_) => {} // not subscribed topic
+                        Err(RecvError::Lagged(num_skipped)) => {
+                            log::warn!("robotrader_websocket: client lagged, {} events skipped. Sending a new snapshot.", num_skipped);
+                            let snapshot = RQ_EVENT_HUB.snapshot(&sorted_topics(&topics));
+                            if send_snapshot(&mut ws_session, &topics, snapshot, true).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
                 }
             }
         }
+        let _ = ws_session.close(None).await;
         log::info!("WebSocket session ended");
     });
     Ok(response)
 }
+
+// Err: the websocket is closed
+async fn handle_client_request(ws_session: &mut Session, topics: &mut HashSet<RqEventTopic>, text: &str) -> Result<(), actix_ws::Closed> {
+    let Ok(client_request) = serde_json::from_str::<Value>(text) else {
+        return ws_session.text(json!({"type": "error", "message": "Invalid JSON format"}).to_string()).await;
+    };
+
+    // Extract request type
+    let request_type = client_request["type"].as_str().unwrap_or("");
+    match request_type {
+        "getexecutedorders" => { // the same data as GET /api/robotrader/executions
+            let executed_orders = get_executions_json();
+            ws_session.text(json!({"type": "executed_orders", "data": executed_orders}).to_string()).await
+        }
+        "subscribe" | "unsubscribe" => {
+            let requested_topics: Vec<&str> = client_request["topics"].as_array().map(|a| a.iter().filter_map(|t| t.as_str()).collect()).unwrap_or_default();
+            let Some(parsed_topics) = requested_topics.iter().map(|t| RqEventTopic::parse(t)).collect::<Option<Vec<RqEventTopic>>>() else {
+                return ws_session.text(json!({"type": "error", "message": format!("Unknown topic in {:?}. Topics: {:?}", requested_topics, RqEventTopic::ALL.map(|t| t.to_string()))}).to_string()).await;
+            };
+            if request_type == "unsubscribe" {
+                parsed_topics.iter().for_each(|t| { topics.remove(t); });
+                return ws_session.text(json!({"type": "unsubscribed", "topics": sorted_topics(topics)}).to_string()).await;
+            }
+            let new_topics: HashSet<RqEventTopic> = parsed_topics.into_iter().filter(|t| !topics.contains(t)).collect();
+            topics.extend(new_topics.iter());
+            // Only the new topics' snapshot. Events of the already subscribed topics were already sent.
+            let snapshot = RQ_EVENT_HUB.snapshot(&sorted_topics(&new_topics));
+            send_snapshot(ws_session, &new_topics, snapshot, false).await
+        }
+        _ => { // Unknown command
+            ws_session.text(json!({"type": "error", "message": format!("Unknown command: {}", request_type)}).to_string()).await
+        }
+    }
+}
+
+async fn send_snapshot(ws_session: &mut Session, topics: &HashSet<RqEventTopic>, events: Vec<Arc<RqEvent>>, is_resync: bool) -> Result<(), actix_ws::Closed> {
+    let events: Vec<&RqEvent> = events.iter().map(|e| e.as_ref()).collect();
+    ws_session.text(json!({"type": "snapshot", "topics": sorted_topics(topics), "resync": is_resync, "events": events}).to_string()).await
+}
+
+fn sorted_topics(topics: &HashSet<RqEventTopic>) -> Vec<RqEventTopic> {
+    RqEventTopic::ALL.into_iter().filter(|t| topics.contains(t)).collect()
+}